@@ -1,6 +1,12 @@
-use clap::{ArgAction, Parser, ValueHint};
+use std::path::PathBuf;
+
+use clap::{ArgAction, Args, Parser, Subcommand, ValueHint};
 use clap_complete::Shell;
 
+use crate::jfswatch::{Backend, StdinPaths};
+use crate::list::ListFormat;
+use crate::runner::Signal;
+
 /// # JFSWatch
 ///
 /// Justin's file system watching program.
@@ -23,8 +29,9 @@ use clap_complete::Shell;
 /// to choose the correct binary for your system. The binary can then be executed
 /// by adding it to a `PATH` directory, or by running it directly.
 ///
-/// Optionally, you can use the `--autocomplete` option to generate a file that will
-/// enable tab completion for your shell. This is not required, but can be helpful.
+/// Optionally, you can use the `completions` subcommand to generate a file that
+/// will enable tab completion for your shell. This is not required, but can be
+/// helpful.
 ///
 /// ## Examples
 ///
@@ -75,6 +82,27 @@ use clap_complete::Shell;
 ///     'cargo run -- --help > README.md'
 /// ```
 ///
+/// ### Config File Example
+///
+/// Options can be kept in a `jfswatch.toml` file in version control instead,
+/// where named rules run their own commands on their own schedule. Running
+/// `jfswatch` in the same directory then runs the tests when any Rust source
+/// changes, and applies each migration as it's written.
+///
+/// ```toml
+/// gitignore = true
+///
+/// [rules.tests]
+/// glob = ["src/**/*.rs"]
+/// exact = ["Cargo.toml"]
+/// cmd = "cargo test"
+/// sleep = 2.0
+///
+/// [rules.migrations]
+/// glob = ["migrations/{name}.sql"]
+/// cmd = ["migrate", "up", "{name}"]
+/// ```
+///
 /// ## Extras
 ///
 /// - It's usually best to use single quotes when accessing full shell features.
@@ -82,9 +110,21 @@ use clap_complete::Shell;
 ///   jfswatch can use them
 /// - Be careful not to create a loop where jfswatch watches a file that is
 ///   modified by the command it runs. The logs will make this obvious if this
-///   happens, but it can still be an annoying mistake to make
+///   happens, but it can still be an annoying mistake to make. Use `--exclude`
+///   to stop watching the files that the command writes
 /// - The logging level can be changed by setting the `RUST_LOG` environment
 ///   variable to one of: `trace`, `debug`, `info`, `warn`, `error`
+/// - Scripts and CI jobs can use `jfswatch list` to print the paths that would
+///   be watched, or `jfswatch check` to validate the patterns, rules, and
+///   config file, without watching anything. Both take the same options as
+///   `watch`. To find out why a path doesn't trigger a command, `jfswatch list
+///   --format table` shows the pattern that found each path, and the paths
+///   that no pattern matches
+/// - Commands run in their own process group, so that stopping them also stops
+///   everything they started. A background process group can't read from the
+///   terminal, so when jfswatch's stdin is a terminal the command gets an empty
+///   stdin instead. Piped input, e.g. `yes | jfswatch ...`, is still passed
+///   through, as are the changed paths with `--stdin`
 ///
 /// ## Usage
 /// ```
@@ -93,10 +133,96 @@ use clap_complete::Shell;
     author,
     verbatim_doc_comment,
     term_width = 80,
-    disable_help_flag = true
+    disable_help_flag = true,
+    args_conflicts_with_subcommands = true
 )]
 pub struct Cli {
-    /// The exact file path to watch
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// The options of the `watch` subcommand, which is run when no
+    /// subcommand is given
+    #[command(flatten)]
+    pub watch: WatchArgs,
+}
+
+/// What jfswatch should do
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Watch the paths, and run the commands when they change. This is the
+    /// default when no subcommand is given
+    #[command(verbatim_doc_comment, disable_help_flag = true)]
+    Watch(WatchArgs),
+
+    /// Print every path that would be watched, and exit
+    #[command(verbatim_doc_comment, disable_help_flag = true)]
+    List(ListArgs),
+
+    /// Check that the patterns, rules, and config file are valid, and exit.
+    /// The exit status is 0 if they are, or 2 with the error otherwise
+    #[command(verbatim_doc_comment, disable_help_flag = true)]
+    Check(WatchArgs),
+
+    /// Generates the appropriate autocomplete file for the specified shell.
+    /// This can help you quickly navigate jfswatch commands using tab
+    /// completion. Remember to restart your shell after writing the file.
+    ///
+    /// This feature of the installation is *not* required, and no cleanup or
+    /// uninstall method is provided.
+    ///
+    /// For 'bash', write this to a file: `/etc/bash_completion.d/jfswatch`.
+    /// Other shell types are supported, but you must figure out where to put
+    /// the file yourself. :)
+    #[command(verbatim_doc_comment)]
+    Completions {
+        /// The shell to generate the autocomplete file for
+        #[arg(value_enum)]
+        shell: Shell,
+    },
+}
+
+/// How to print the paths that would be watched, and what to watch
+#[derive(Debug, Args)]
+pub struct ListArgs {
+    /// How to print the paths. `paths` prints each watched path on its own
+    /// line. `table` and `json` also print each path's size, modified time,
+    /// and the pattern that found it, including which alternative of its
+    /// braces matched. They include the other paths inside of the watched
+    /// directories too, marked as unmatched, and the excluded paths
+    #[arg(long, value_enum, default_value_t, verbatim_doc_comment)]
+    pub format: ListFormat,
+
+    #[command(flatten)]
+    pub watch: WatchArgs,
+}
+
+/// The paths to watch, the commands to run, and how to run them
+#[derive(Debug, Args)]
+pub struct WatchArgs {
+    /// Read options from a TOML config file, which is `jfswatch.toml` in the
+    /// working directory by default. Its keys are the long names of the
+    /// options, like `glob` or `stop-signal`, and `cmd` is the command as a
+    /// list of arguments, or as a string which the shell runs as it is, quotes
+    /// and all. With `no-shell`, `cmd` must be a list, since nothing splits a
+    /// string into arguments. Options given on the command line take
+    /// precedence over the config file. Named rules are written as tables
+    /// like `[rules.tests]`, with their own `exact`, `glob`, `cmd`,
+    /// `interval`, `sleep`, and `batch` keys. Their glob patterns can use
+    /// captures like `{name}`, the same as a `rule`. Relative paths and
+    /// patterns in the config file are relative to the directory that
+    /// contains it, rather than the working directory
+    ///
+    /// The config file is reloaded whenever it changes, while paths that
+    /// didn't change aren't reported again. If the changed config is invalid,
+    /// the error is logged and the previous config is kept. Changes to the
+    /// `backend`, `reconcile`, and `exclude` options need a restart
+    #[arg(long, verbatim_doc_comment, value_hint = ValueHint::FilePath)]
+    pub config: Option<PathBuf>,
+
+    /// The exact file path to watch. `~`, `~user`, `$VAR`, and
+    /// `${VAR:-default}` are expanded like a shell would, even when no shell
+    /// started jfswatch, and an undefined variable is an error. A backslash
+    /// keeps them literal, like `\$HOME`
     #[arg(
         short,
         long,
@@ -106,7 +232,15 @@ pub struct Cli {
     )]
     pub exact: Vec<String>,
 
-    /// The file paths to watch using extended glob patterns
+    /// The file paths to watch using extended glob patterns. Braces like
+    /// `config.{yml,yaml}` match any of their comma separated alternatives.
+    /// Braces can also be a range like `{1..10}`, `{a..f}`, or `{0..100..5}`,
+    /// and `part-{000..127}` pads the numbers with zeros like bash does. A
+    /// range can have at most 4096 values.
+    /// The extglob operators `!(a|b)`, `@(a|b)`, `?(a|b)`, `*(a|b)`, and
+    /// `+(a|b)` match anything except, exactly one, at most one, any number
+    /// of, or at least one of their alternatives, e.g. `src/!(*.test).ts`.
+    /// `~` and environment variables are expanded like they are for `exact`
     #[arg(
         short,
         long,
@@ -116,16 +250,132 @@ pub struct Cli {
     )]
     pub glob: Vec<String>,
 
-    /// Seconds to wait between each non-differing check
+    /// The file paths to never watch using extended glob patterns, even when
+    /// they're found by `exact` or `glob`. A pattern without a slash, like
+    /// `*.swp`, matches file names in any directory. Excluding a directory
+    /// also excludes everything inside of it
+    #[arg(
+        short = 'x',
+        long,
+        visible_alias = "ignore",
+        action = ArgAction::Append,
+        verbatim_doc_comment,
+        value_hint = ValueHint::AnyPath
+    )]
+    pub exclude: Vec<String>,
+
+    /// Skip paths that are ignored by `.gitignore`, `.ignore`, or
+    /// `.git/info/exclude` files while exploring `glob` patterns, including
+    /// nested ignore files and negated rules. Ignored directories like
+    /// `target/` or `node_modules/` are never explored, which makes checks
+    /// much cheaper in large repositories. `.git` directories are skipped too
+    #[arg(long, verbatim_doc_comment)]
+    pub gitignore: bool,
+
+    /// Run a separate command for each changed path that matches a pattern,
+    /// written as `PATTERN -> COMMAND`. A bare name in braces like `{name}`
+    /// is a named capture in the extended glob pattern, which matches like
+    /// `*`. What it matched replaces `{name}` in the command, while `{1}`,
+    /// `{2}`, ... are replaced by what each wildcard matched in order. For
+    /// example, to render each changed markdown file:
+    /// `--rule 'src/{name}.md -> pandoc $path -o out/{name}.html'`
+    ///
+    /// The command supports the same variables as the main command, which is
+    /// optional when rules are given. Rules always run once per changed path,
+    /// even in `batch` mode. Each rule and the main command keep their own
+    /// view of their paths and sleep on their own, so a slow command doesn't
+    /// hold up the others, while paths shared by several are checked once
+    #[arg(long, action = ArgAction::Append, verbatim_doc_comment)]
+    pub rule: Vec<String>,
+
+    /// Seconds to wait between each non-differing check. With the `inotify`
+    /// backend, this is how long to wait for more events after the first one
     #[arg(short, long, default_value_t = 0.1, verbatim_doc_comment)]
     pub interval: f32,
 
     /// Seconds to sleep the program after the specified command has been
-    /// executed. The program will not check for changes during this time.
-    /// By default it uses the same value as `interval`
+    /// executed. The command's paths will not be checked for changes during
+    /// this time, though those of other rules are. By default it uses the
+    /// same value as `interval`
     #[arg(short, long, verbatim_doc_comment)]
     pub sleep: Option<f32>,
 
+    /// Run the command once per check with every changed path, rather than
+    /// once per changed path. Use list variables like `$paths` to access the
+    /// changes
+    #[arg(short, long, verbatim_doc_comment)]
+    pub batch: bool,
+
+    /// Keep the command running in the background instead of waiting for it
+    /// to exit, e.g. for a development server. When the next changes are
+    /// detected, the running command is stopped along with every process it
+    /// started, and then started again. If several paths change in the same
+    /// check without `batch` mode, the command is only started for the last
+    /// of them
+    #[arg(short, long, verbatim_doc_comment)]
+    pub restart: bool,
+
+    /// The signal sent to stop the running command in `restart` mode, by
+    /// name (e.g. `SIGINT` or `INT`) or number
+    #[arg(long, default_value = "SIGTERM", verbatim_doc_comment)]
+    pub stop_signal: Signal,
+
+    /// Seconds to wait for the running command to exit after it's signalled
+    /// before killing it with `SIGKILL`. This applies to the `stop-signal` in
+    /// `restart` mode, and to SIGINT or SIGTERM received by jfswatch, which
+    /// are forwarded to the running command before jfswatch exits
+    #[arg(long, default_value_t = 5.0, verbatim_doc_comment)]
+    pub stop_timeout: f32,
+
+    /// Write the changed paths to the command's stdin instead of letting it
+    /// read from jfswatch's stdin (or an empty stdin when that's a
+    /// terminal), e.g. for `xargs -0` or
+    /// `rsync --files-from=-`. Each path is followed by a newline with
+    /// `lines`, or a NUL byte with `nul`, which is safe for any path. The
+    /// command receives every changed path in `batch` mode, or otherwise the
+    /// path it was run for
+    #[arg(long, value_enum, verbatim_doc_comment)]
+    pub stdin: Option<StdinPaths>,
+
+    /// Execute the first argument of the command directly instead of running
+    /// the command with `$SHELL -c`. Variables are substituted into each
+    /// argument on its own, so paths with spaces or shell syntax in them are
+    /// passed through untouched. An argument that is just a list variable,
+    /// e.g. `$paths`, becomes one argument per path
+    #[arg(long, verbatim_doc_comment)]
+    pub no_shell: bool,
+
+    /// The separator used to join paths in list variables like `$paths`
+    #[arg(long, default_value = " ", verbatim_doc_comment)]
+    pub separator: String,
+
+    /// Detect modifications by hashing file contents instead of comparing
+    /// mtimes. Files are only considered modified when their contents change,
+    /// so a `touch` is ignored, and a write that preserves the mtime is
+    /// noticed. Directories, unreadable files, and files larger than
+    /// `hash-limit` still use their mtime
+    #[arg(long, verbatim_doc_comment)]
+    pub hash: bool,
+
+    /// The largest file size in bytes to hash when `hash` is enabled
+    #[arg(long, default_value_t = 16 * 1024 * 1024, verbatim_doc_comment)]
+    pub hash_limit: u64,
+
+    /// How changes are detected. `poll` explores every watched path each
+    /// `interval`, and works on any file system. `inotify` (Linux only) lets
+    /// the kernel report changes, which uses far less CPU for large trees but
+    /// may miss changes on network file systems. If inotify is unavailable,
+    /// e.g. because too many directories are watched, polling is used instead
+    #[arg(long, value_enum, default_value_t, verbatim_doc_comment)]
+    pub backend: Backend,
+
+    /// Seconds between full explorations of every watched path while using
+    /// the `inotify` backend. Changes that inotify missed, e.g. because its
+    /// event queue overflowed or a network file system didn't report them,
+    /// are caught and logged. By default inotify events are fully trusted
+    #[arg(long, verbatim_doc_comment)]
+    pub reconcile: Option<f32>,
+
     /// The command to execute when changes are detected. The command can
     /// include substitutable bash-like variables:
     /// - `$diff` or `${diff}` will be one of `new`, `deleted`, or `modified`
@@ -133,6 +383,43 @@ pub struct Cli {
     /// - `$path` or `${path}` will be the watched path that changed.
     /// - `$mtime` or `${mtime}` will be the last modified time of the watched
     ///   path (unavailable for deleted paths).
+    /// - `$old_mtime` will be the modified time of the watched path before
+    ///   it changed (unavailable for new paths).
+    /// - `$dir`, `$basename`, `$stem`, and `$ext` will be the directory, file
+    ///   name, file name without its extension, and extension (without a dot)
+    ///   of the watched path.
+    /// - `$relpath` and `$abspath` will be the watched path relative to the
+    ///   working directory, and as an absolute path.
+    /// - `$size` will be the size in bytes of the watched path (unavailable
+    ///   for deleted paths).
+    /// - `$pattern` will be the `exact` path or `glob` pattern that matched.
+    /// - `$paths` or `${paths}` will be every path that changed during the
+    ///   same check, joined by the `separator`. Similarly, `$new_paths`,
+    ///   `$modified_paths`, and `$deleted_paths` only include paths with the
+    ///   corresponding change.
+    /// - `$count` or `${count}` will be the number of paths that changed
+    ///   during the same check.
+    ///
+    /// Modifiers change how a variable is written:
+    /// - `${mtime:%s}` formats a time with a strftime format starting with
+    ///   `%`, e.g. `${old_mtime:%Y-%m-%d}`.
+    /// - `${mtime:rfc3339}` formats a time like `2024-01-31T12:00:00+00:00`.
+    /// - `${path:q}` quotes the value for the shell so it's always a single
+    ///   word, even if it includes spaces, quotes, or characters like `;`.
+    ///   List variables quote each path separately.
+    ///
+    /// Other variables like `$HOME` are left for the shell to expand, or are
+    /// rejected with `no-shell`. Unknown variables with a modifier like
+    /// `${pth:q}`, or starting with `JFSWATCH_`, are always rejected. Escape
+    /// a variable like `\$path` to keep it as-is.
+    ///
+    /// Variables describing a single path, like `$path`, are unavailable in
+    /// `batch` mode.
+    ///
+    /// The same details are also available to the command as environment
+    /// variables, which never need quoting: `JFSWATCH_DIFF`, `JFSWATCH_PATH`,
+    /// `JFSWATCH_MTIME`, `JFSWATCH_OLD_MTIME` (the mtime before the change),
+    /// `JFSWATCH_PATHS` (one path per line), and `JFSWATCH_COUNT`.
     #[arg(
         verbatim_doc_comment,
         trailing_var_arg = true,
@@ -143,19 +430,6 @@ pub struct Cli {
     /// Print help
     #[arg(short, long, verbatim_doc_comment, action = ArgAction::HelpLong)]
     pub help: Option<bool>,
-
-    /// Generates the appropriate autocomplete file for the specified shell.
-    /// This can help you quickly navigate jfswatch commands using tab
-    /// completion. Remember to restart your shell after writing the file.
-    ///
-    /// This feature of the installation is *not* required, and no cleanup or
-    /// uninstall method is provided.
-    ///
-    /// For 'bash', write this to a file: `/etc/bash_completion.d/jfswatch`.
-    /// Other shell types are supported, but you must figure out where to put
-    /// the file yourself. :)
-    #[arg(long, value_enum, verbatim_doc_comment)]
-    pub autocomplete: Option<Shell>,
 }
 
 #[cfg(test)]
@@ -0,0 +1,604 @@
+use std::collections::BTreeMap;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use clap::parser::ValueSource;
+use clap::ArgMatches;
+use serde::Deserialize;
+use toml::Spanned;
+
+use crate::cli::WatchArgs;
+use crate::explorers::{ExactExplorer, Explorer, GlobExplorer, PatternError};
+use crate::jfswatch::{Backend, StdinPaths};
+use crate::rule::{Rule, RULE_SEPARATOR};
+use crate::runner::Signal;
+
+/// The config file which is read when it's in the working directory and no other config file was given
+pub const DEFAULT_CONFIG_FILE: &str = "jfswatch.toml";
+
+/// A command in a config file, either as a single line or as a list of arguments
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(untagged)]
+pub enum ConfigCommand {
+    /// A whole shell command line, which is run as it is so its quoting is kept
+    Line(String),
+
+    /// Each item is an argument
+    Args(Vec<String>),
+}
+
+impl ConfigCommand {
+    /// Whether there's no command to run
+    fn is_empty(&self) -> bool {
+        return match self {
+            ConfigCommand::Line(line) => line.trim().is_empty(),
+            ConfigCommand::Args(args) => args.is_empty(),
+        };
+    }
+
+    /// The arguments of the command. A line is kept as one argument, since the shell splits it when the arguments are
+    /// joined into a command line again. Without a `shell`, there's no telling where a line's arguments end, so only
+    /// a list of arguments can be run
+    pub fn into_args(self, shell: bool) -> Result<Vec<String>, String> {
+        return match self {
+            ConfigCommand::Line(line) if line.trim().is_empty() => Ok(Vec::new()),
+            ConfigCommand::Line(line) if !shell => Err(format!(
+                "The command '{line}' must be a list of arguments with `no-shell`, like `cmd = [\"cargo\", \"test\"]`"
+            )),
+            ConfigCommand::Line(line) => Ok(vec![line]),
+            ConfigCommand::Args(args) => Ok(args),
+        };
+    }
+}
+
+/// A named rule in a config file, written as a `[rules.NAME]` table. It runs its own command when any of its paths
+/// change, on its own schedule
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RuleConfig {
+    /// The exact file paths to watch
+    #[serde(default)]
+    pub exact: Vec<String>,
+
+    /// The file paths to watch using extended glob patterns, which can include captures like `{name}`
+    #[serde(default)]
+    pub glob: Vec<String>,
+
+    /// The command to run when one of the rule's paths changes
+    pub cmd: ConfigCommand,
+
+    /// Seconds to wait between non-changing checks of the rule's paths, instead of the `interval`
+    pub interval: Option<f32>,
+
+    /// Seconds to wait after running the command, instead of the `sleep`
+    pub sleep: Option<f32>,
+
+    /// Run the command once per check with every changed path, instead of following `batch`
+    pub batch: Option<bool>,
+}
+
+/// The options of a config file. Its keys are the long names of the cli options, and any option that's left out
+/// keeps its cli value
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    pub exact: Option<Vec<String>>,
+    pub glob: Option<Vec<String>>,
+    pub exclude: Option<Vec<String>>,
+    pub gitignore: Option<bool>,
+    pub rule: Option<Vec<Spanned<String>>>,
+    pub interval: Option<f32>,
+    pub sleep: Option<f32>,
+    pub batch: Option<bool>,
+    pub restart: Option<bool>,
+    pub stop_signal: Option<Signal>,
+    pub stop_timeout: Option<f32>,
+    pub stdin: Option<StdinPaths>,
+    pub no_shell: Option<bool>,
+    pub separator: Option<String>,
+    pub hash: Option<bool>,
+    pub hash_limit: Option<u64>,
+    pub backend: Option<Backend>,
+    pub reconcile: Option<f32>,
+    pub cmd: Option<ConfigCommand>,
+
+    /// The named rules, ordered by name
+    pub rules: BTreeMap<String, Spanned<RuleConfig>>,
+}
+
+/// Describes an `error` at the `span` of the config file's `text` by its line and column. The position follows the
+/// first line of the `error`, before any details like where a pattern is invalid
+fn error_at(text: &str, span: Range<usize>, error: &str) -> String {
+    let before = &text[..span.start];
+    let line = before.lines().count().max(1) + usize::from(before.ends_with('\n'));
+    let column = before.chars().rev().take_while(|c| *c != '\n').count() + 1;
+
+    let (summary, details) = match error.split_once('\n') {
+        Some((summary, details)) => (summary, format!("\n{details}")),
+        None => (error, String::new()),
+    };
+    return format!("{summary} at line {line}, column {column}{details}");
+}
+
+/// Resolves a relative `path` or pattern from a config file against the config file's directory `dir`, so the same
+/// config file watches the same paths from any working directory. Paths starting with `~` or an environment variable
+/// are left as they are
+fn resolve_path(dir: &Path, path: &str) -> String {
+    if dir.as_os_str().is_empty() || path.starts_with(['/', '~', '$']) {
+        return path.to_string();
+    }
+    let path = path.strip_prefix("./").unwrap_or(path);
+    return dir.join(path).to_string_lossy().to_string();
+}
+
+/// Replaces the `value` of an option with the one from a config file, unless the option was given on the command line
+fn merge<T>(matches: &ArgMatches, id: &str, value: &mut T, config_value: Option<T>) {
+    if matches.value_source(id) == Some(ValueSource::CommandLine) {
+        return;
+    }
+    if let Some(config_value) = config_value {
+        *value = config_value;
+    }
+}
+
+/// The config file to read. This is the `arg` if one was given, or otherwise the default config file if it exists
+pub fn find(arg: Option<&Path>) -> Option<PathBuf> {
+    if let Some(path) = arg {
+        return Some(path.to_path_buf());
+    }
+
+    let path = Path::new(DEFAULT_CONFIG_FILE);
+    return path.is_file().then(|| path.to_path_buf());
+}
+
+impl Config {
+    /// Parses the `text` of a config file. Errors describe the line and column where the problem is
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let config: Config = toml::from_str(text).map_err(|error| error.to_string())?;
+
+        for rule in config.rule.iter().flatten() {
+            Rule::from_cli_arg(rule.get_ref(), false)
+                .map_err(|error| error_at(text, rule.span(), &error))?;
+        }
+        for (name, rule) in config.rules.iter() {
+            let span = rule.span();
+            let rule = rule.get_ref();
+            if rule.exact.is_empty() && rule.glob.is_empty() {
+                return Err(error_at(
+                    text,
+                    span,
+                    &format!("Rule '{name}' has no `exact` paths or `glob` patterns"),
+                ));
+            }
+            if rule.cmd.is_empty() {
+                return Err(error_at(
+                    text,
+                    span,
+                    &format!("Rule '{name}' has no command"),
+                ));
+            }
+            if let Err(error) = Self::explorers(rule, false) {
+                return Err(error_at(text, span, &format!("Rule '{name}': {error}")));
+            }
+        }
+
+        return Ok(config);
+    }
+
+    /// Reads and parses the config file at `path`. Its relative paths and patterns are resolved against the
+    /// directory that contains it
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|error| format!("Cannot read config file '{}': {}", path.display(), error))?;
+        let mut config = Self::parse(&text)
+            .map_err(|error| format!("Invalid config file '{}': {}", path.display(), error))?;
+        config.resolve_paths(path.parent().unwrap_or(Path::new("")));
+        return Ok(config);
+    }
+
+    /// Resolves every relative path and pattern against the directory `dir`. Exclusions without a slash match a file
+    /// name in any directory, so they're left as they are
+    fn resolve_paths(&mut self, dir: &Path) {
+        let resolve_all = |paths: &mut Vec<String>| {
+            for path in paths.iter_mut() {
+                *path = resolve_path(dir, path);
+            }
+        };
+
+        self.exact.iter_mut().for_each(resolve_all);
+        self.glob.iter_mut().for_each(resolve_all);
+        for exclude in self.exclude.iter_mut().flatten() {
+            if exclude.contains('/') {
+                *exclude = resolve_path(dir, exclude);
+            }
+        }
+        for rule in self.rule.iter_mut().flatten() {
+            // validated by `parse`, so every rule has a separator
+            if let Some((pattern, command)) = rule.get_ref().split_once(RULE_SEPARATOR) {
+                let resolved = format!(
+                    "{} {RULE_SEPARATOR} {}",
+                    resolve_path(dir, pattern.trim()),
+                    command.trim()
+                );
+                *rule.get_mut() = resolved;
+            }
+        }
+        for rule in self.rules.values_mut() {
+            let rule = rule.get_mut();
+            resolve_all(&mut rule.exact);
+            resolve_all(&mut rule.glob);
+        }
+    }
+
+    /// Fills in the options of `cli` with the config's values, except for those given on the command line according
+    /// to the `matches` that `cli` was parsed from. Errors if the command is a line while `no-shell` is set
+    pub fn merge_into(&self, cli: &mut WatchArgs, matches: &ArgMatches) -> Result<(), String> {
+        let config = self.clone();
+        merge(matches, "exact", &mut cli.exact, config.exact);
+        merge(matches, "glob", &mut cli.glob, config.glob);
+        merge(matches, "exclude", &mut cli.exclude, config.exclude);
+        merge(matches, "gitignore", &mut cli.gitignore, config.gitignore);
+        merge(
+            matches,
+            "rule",
+            &mut cli.rule,
+            config
+                .rule
+                .map(|rules| rules.into_iter().map(Spanned::into_inner).collect()),
+        );
+        merge(matches, "interval", &mut cli.interval, config.interval);
+        merge(matches, "sleep", &mut cli.sleep, config.sleep.map(Some));
+        merge(matches, "batch", &mut cli.batch, config.batch);
+        merge(matches, "restart", &mut cli.restart, config.restart);
+        merge(
+            matches,
+            "stop_signal",
+            &mut cli.stop_signal,
+            config.stop_signal,
+        );
+        merge(
+            matches,
+            "stop_timeout",
+            &mut cli.stop_timeout,
+            config.stop_timeout,
+        );
+        merge(matches, "stdin", &mut cli.stdin, config.stdin.map(Some));
+        merge(matches, "no_shell", &mut cli.no_shell, config.no_shell);
+        merge(matches, "separator", &mut cli.separator, config.separator);
+        merge(matches, "hash", &mut cli.hash, config.hash);
+        merge(
+            matches,
+            "hash_limit",
+            &mut cli.hash_limit,
+            config.hash_limit,
+        );
+        merge(matches, "backend", &mut cli.backend, config.backend);
+        merge(
+            matches,
+            "reconcile",
+            &mut cli.reconcile,
+            config.reconcile.map(Some),
+        );
+        let cmd = match config.cmd {
+            Some(cmd) if matches.value_source("cmd") != Some(ValueSource::CommandLine) => {
+                Some(cmd.into_args(!cli.no_shell)?)
+            }
+            _ => None,
+        };
+        merge(matches, "cmd", &mut cli.cmd, cmd);
+        return Ok(());
+    }
+
+    /// Constructs the explorers of a named rule, or describes its first invalid path or pattern
+    fn explorers(
+        rule: &RuleConfig,
+        respect_ignore_files: bool,
+    ) -> Result<Vec<Box<dyn Explorer>>, PatternError> {
+        let mut explorers: Vec<Box<dyn Explorer>> =
+            Vec::with_capacity(rule.exact.len() + rule.glob.len());
+        for arg in rule.exact.iter() {
+            explorers.push(Box::new(ExactExplorer::from_cli_arg(arg)?));
+        }
+        for arg in rule.glob.iter() {
+            explorers.push(Box::new(
+                GlobExplorer::from_rule_pattern(arg)?.with_ignore_files(respect_ignore_files),
+            ));
+        }
+        return Ok(explorers);
+    }
+
+    /// Constructs the named rules. Their glob patterns can include captures like `{name}`, the same as a `--rule`.
+    /// Their commands must be lists of arguments when they're run without a `shell`
+    pub fn rules(&self, respect_ignore_files: bool, shell: bool) -> Result<Vec<Rule>, String> {
+        let mut rules = Vec::with_capacity(self.rules.len());
+
+        for (name, rule) in self.rules.iter() {
+            let rule = rule.get_ref();
+            let explorers = Self::explorers(rule, respect_ignore_files)
+                .map_err(|error| format!("Rule '{name}': {error}"))?;
+            let cmd = rule
+                .cmd
+                .clone()
+                .into_args(shell)
+                .map_err(|error| format!("Rule '{name}': {error}"))?;
+
+            rules.push(Rule {
+                name: Some(name.clone()),
+                interval: rule.interval,
+                sleep: rule.sleep,
+                batch: rule.batch,
+                captures: true,
+                ..Rule::new(explorers, cmd)
+            });
+        }
+
+        return Ok(rules);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use clap::{CommandFactory, FromArgMatches};
+    use rstest::rstest;
+
+    use super::*;
+    use crate::cli::Cli;
+
+    fn parse_cli(args: Vec<&str>) -> (WatchArgs, ArgMatches) {
+        let matches = Cli::command().try_get_matches_from(args).unwrap();
+        let cli = WatchArgs::from_arg_matches(&matches).unwrap();
+        return (cli, matches);
+    }
+
+    #[test]
+    fn given_every_option_when_parse_then_ok() {
+        let config = Config::parse(
+            r#"
+            exact = ["Cargo.toml"]
+            glob = ["src/**/*.rs"]
+            exclude = ["*.swp"]
+            gitignore = true
+            rule = ["docs/{name}.md -> pandoc $path -o out/{name}.html"]
+            interval = 0.5
+            sleep = 2
+            batch = true
+            restart = true
+            stop-signal = "SIGINT"
+            stop-timeout = 1.5
+            stdin = "nul"
+            no-shell = true
+            separator = ","
+            hash = true
+            hash-limit = 1024
+            backend = "inotify"
+            reconcile = 60.0
+            cmd = ["cargo", "test"]
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.interval, Some(0.5));
+        assert_eq!(config.sleep, Some(2.0));
+        assert_eq!(config.stop_signal, Some(Signal::from_str("INT").unwrap()));
+        assert_eq!(config.stdin, Some(StdinPaths::Nul));
+        assert_eq!(config.backend, Some(Backend::Inotify));
+        assert_eq!(
+            config.cmd,
+            Some(ConfigCommand::Args(vec![
+                "cargo".to_string(),
+                "test".to_string()
+            ]))
+        );
+    }
+
+    #[test]
+    fn given_named_rules_when_rules_then_constructs_each_rule() {
+        let config = Config::parse(
+            r#"
+            [rules.tests]
+            glob = ["src/**/*.rs"]
+            exact = ["Cargo.toml"]
+            cmd = "cargo test"
+
+            [rules.migrations]
+            glob = ["migrations/{name}.sql"]
+            cmd = "migrate {name}"
+            interval = 1.0
+            batch = false
+            "#,
+        )
+        .unwrap();
+
+        let rules = config.rules(false, true).unwrap();
+
+        assert_eq!(rules.len(), 2);
+        assert_eq!(rules[0].describe(), "migrations");
+        assert_eq!(rules[0].cmd, vec!["migrate {name}"]);
+        assert_eq!(rules[0].interval, Some(1.0));
+        assert_eq!(rules[0].batch, Some(false));
+        assert!(rules[0].captures);
+        assert_eq!(rules[1].describe(), "tests");
+        assert_eq!(rules[1].explorers.len(), 2);
+        assert_eq!(rules[1].interval, None);
+    }
+
+    #[rstest]
+    #[case("interval = \"fast\"\n", "line 1, column 12")]
+    #[case("\n\nbackend = \"kqueue\"\n", "line 3, column 11")]
+    #[case("stop-signal = \"SIGNOPE\"\n", "line 1, column 15")]
+    #[case("glob = [\"*.rs\"]\nintervall = 1\n", "line 2, column 1")]
+    #[case(
+        "rule = [\n  \"*.md -> echo $path\",\n  \"*.rs\",\n]\n",
+        "line 3, column 3"
+    )]
+    #[case("[rules.tests]\nglob = [\"*.rs\"]\n", "line 1, column 1")]
+    #[case("\n[rules.tests]\ncmd = \"cargo test\"\n", "line 2, column 1")]
+    #[case("[rules.tests]\nglob = [\"*.rs\"]\ncmd = []\n", "line 1, column 1")]
+    #[case(
+        "\n[rules.tests]\nglob = [\"src/{a,b\"]\ncmd = \"cargo test\"\n",
+        "line 2, column 1"
+    )]
+    #[case("rule = [\"src/*.md} -> echo $path\"]\n", "line 1, column 9")]
+    fn given_invalid_config_when_parse_then_err_describes_position(
+        #[case] text: &str,
+        #[case] position: &str,
+    ) {
+        let error = Config::parse(text).unwrap_err();
+        assert!(error.contains(position), "{error}");
+    }
+
+    #[test]
+    fn given_config_when_merge_into_cli_then_command_line_options_take_precedence() {
+        let config = Config::parse(
+            r#"
+            glob = ["src/**/*.rs"]
+            exclude = ["*.swp"]
+            interval = 0.5
+            sleep = 2.0
+            backend = "inotify"
+            cmd = "cargo test"
+            "#,
+        )
+        .unwrap();
+        let (mut cli, matches) = parse_cli(vec!["jfswatch", "--interval", "1", "-x", "*.tmp"]);
+
+        config.merge_into(&mut cli, &matches).unwrap();
+
+        assert_eq!(cli.glob, vec!["src/**/*.rs"]);
+        assert_eq!(cli.exclude, vec!["*.tmp"]);
+        assert_eq!(cli.interval, 1.0);
+        assert_eq!(cli.sleep, Some(2.0));
+        assert_eq!(cli.backend, Backend::Inotify);
+        assert_eq!(cli.cmd, vec!["cargo test"]);
+        assert_eq!(cli.separator, " ");
+    }
+
+    #[test]
+    fn given_command_on_command_line_when_merge_into_cli_then_replaces_config_command() {
+        let config = Config::parse("cmd = \"cargo test\"\n").unwrap();
+        let (mut cli, matches) = parse_cli(vec!["jfswatch", "-g", "*.rs", "cargo", "build"]);
+
+        config.merge_into(&mut cli, &matches).unwrap();
+
+        assert_eq!(cli.cmd, vec!["cargo", "build"]);
+    }
+
+    #[test]
+    fn given_command_line_when_merge_into_cli_then_keeps_it_whole_for_the_shell() {
+        let config = Config::parse("cmd = \"echo 'a  b' | tr a c\"\n").unwrap();
+        let (mut cli, matches) = parse_cli(vec!["jfswatch", "-g", "*.rs"]);
+
+        config.merge_into(&mut cli, &matches).unwrap();
+
+        assert_eq!(cli.cmd, vec!["echo 'a  b' | tr a c"]);
+    }
+
+    #[rstest]
+    #[case("cmd = \"cargo test\"\n", vec!["jfswatch", "--no-shell"], false)]
+    #[case("no-shell = true\ncmd = \"cargo test\"\n", vec!["jfswatch"], false)]
+    #[case("no-shell = true\ncmd = [\"cargo\", \"test\"]\n", vec!["jfswatch"], true)]
+    #[case("no-shell = true\ncmd = \"cargo test\"\n", vec!["jfswatch", "cargo", "build"], true)]
+    fn given_no_shell_when_merge_into_cli_then_command_must_be_a_list(
+        #[case] text: &str,
+        #[case] args: Vec<&str>,
+        #[case] expected_ok: bool,
+    ) {
+        let config = Config::parse(text).unwrap();
+        let (mut cli, matches) = parse_cli(args);
+
+        let result = config.merge_into(&mut cli, &matches);
+
+        assert_eq!(result.is_ok(), expected_ok, "{result:?}");
+    }
+
+    #[test]
+    fn given_no_shell_and_named_rule_with_command_line_when_rules_then_err() {
+        let config = Config::parse(
+            r#"
+            [rules.tests]
+            glob = ["src/**/*.rs"]
+            cmd = "cargo test"
+            "#,
+        )
+        .unwrap();
+
+        let Err(error) = config.rules(false, false) else {
+            panic!("a command line can't be run without a shell");
+        };
+
+        assert!(error.starts_with("Rule 'tests': "), "{error}");
+        assert!(error.contains("must be a list of arguments"), "{error}");
+    }
+
+    #[test]
+    fn given_config_arg_when_find_then_uses_it() {
+        assert_eq!(
+            find(Some(Path::new("other.toml"))),
+            Some(PathBuf::from("other.toml"))
+        );
+    }
+
+    #[test]
+    fn given_config_in_other_directory_when_load_then_resolves_paths_against_it() {
+        let tmp = tempfile::tempdir_in(".").unwrap();
+        let dir = tmp.path().join("project");
+        crate::test_utils::utils::make_files(&dir, vec!["src/main.rs", "Cargo.toml"]);
+        let path = dir.join(DEFAULT_CONFIG_FILE);
+        std::fs::write(
+            &path,
+            r#"
+            exact = ["./Cargo.toml", "/etc/hosts", "~/.bashrc"]
+            glob = ["src/**/*.rs"]
+            exclude = ["*.swp", "src/gen/**"]
+            rule = ["docs/{name}.md -> pandoc $path"]
+
+            [rules.tests]
+            glob = ["tests/*.rs"]
+            cmd = "cargo test"
+            "#,
+        )
+        .unwrap();
+        let dir = dir.to_string_lossy().to_string();
+
+        let config = Config::load(&path).unwrap();
+
+        assert_eq!(
+            config.exact,
+            Some(vec![
+                format!("{dir}/Cargo.toml"),
+                "/etc/hosts".to_string(),
+                "~/.bashrc".to_string()
+            ])
+        );
+        assert_eq!(config.glob, Some(vec![format!("{dir}/src/**/*.rs")]));
+        assert_eq!(
+            config.exclude,
+            Some(vec!["*.swp".to_string(), format!("{dir}/src/gen/**")])
+        );
+        assert_eq!(
+            config.rule.unwrap()[0].get_ref(),
+            &format!("{dir}/docs/{{name}}.md -> pandoc $path")
+        );
+        assert_eq!(
+            config.rules["tests"].get_ref().glob,
+            vec![format!("{dir}/tests/*.rs")]
+        );
+
+        // the working directory isn't the config file's directory, but its paths are still found
+        let explorer = GlobExplorer::from_cli_arg(&config.glob.unwrap()[0]).unwrap();
+        let mut watched_fs = crate::watched_fs::WatchedFS::new(0);
+        explorer.explore(&mut watched_fs);
+        assert_eq!(
+            watched_fs.paths().cloned().collect::<Vec<String>>(),
+            vec![format!("{dir}/src/main.rs")]
+        );
+    }
+
+    #[test]
+    fn given_missing_file_when_load_then_err() {
+        let error = Config::load(Path::new("does/not/exist.toml")).unwrap_err();
+        assert!(error.contains("does/not/exist.toml"), "{error}");
+    }
+}
@@ -0,0 +1,169 @@
+use std::path::{Path, PathBuf};
+
+use crate::explorers::{ExtendedGlobPattern, PatternError};
+
+/// Extended glob patterns for paths that should never be watched, even when an explorer finds them.
+///
+/// A pattern without a slash (e.g. `*.swp`) matches a file name in any directory, while a pattern with a slash (e.g.
+/// `src/generated/**`) matches the whole path. Excluding a directory also excludes everything inside of it. Paths and
+/// patterns inside of the current working directory are matched relative to it, so `src/generated/**` also excludes
+/// the paths found by `./src/**` or `$PWD/src/**`.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Exclusions {
+    /// Patterns which match against the whole path
+    path_patterns: Vec<ExtendedGlobPattern>,
+
+    /// Patterns which match against the file name of the path or any of its ancestors
+    name_patterns: Vec<ExtendedGlobPattern>,
+
+    /// The current working directory when the exclusions were constructed
+    cwd: PathBuf,
+}
+
+/// Strips a leading `./` and `cwd/` from the `path`
+fn relative_to<'a>(path: &'a Path, cwd: &Path) -> &'a Path {
+    let path = path.strip_prefix(".").unwrap_or(path);
+    if cwd.as_os_str().is_empty() {
+        return path;
+    }
+    return path.strip_prefix(cwd).unwrap_or(path);
+}
+
+impl Exclusions {
+    /// Construct the exclusions from every cli argument, or describe the first invalid pattern
+    pub fn from_cli_args(args: &[String]) -> Result<Self, PatternError> {
+        let mut exclusions = Self {
+            cwd: std::env::current_dir().unwrap_or_default(),
+            ..Self::default()
+        };
+
+        for arg in args {
+            if arg.contains('/') {
+                // parsed as given first, so the offset of an error is in the pattern as it was given
+                let mut pattern = ExtendedGlobPattern::new(arg)?;
+                let relative = relative_to(Path::new(arg), &exclusions.cwd);
+                if relative != Path::new(arg) {
+                    if let Some(relative) = relative.to_str() {
+                        pattern = ExtendedGlobPattern::new(relative)?;
+                    }
+                }
+                exclusions.path_patterns.push(pattern);
+            } else {
+                exclusions
+                    .name_patterns
+                    .push(ExtendedGlobPattern::new(arg)?);
+            }
+        }
+
+        return Ok(exclusions);
+    }
+
+    /// Whether there are no exclusions
+    pub fn is_empty(&self) -> bool {
+        return self.path_patterns.is_empty() && self.name_patterns.is_empty();
+    }
+
+    /// Whether `path`, or any directory containing it, is excluded
+    pub fn excludes(&self, path: &Path) -> bool {
+        if self.is_empty() {
+            return false;
+        }
+
+        let path = relative_to(path, &self.cwd);
+        return path.ancestors().any(|ancestor| {
+            let name_excluded = ancestor.file_name().is_some_and(|name| {
+                self.name_patterns
+                    .iter()
+                    .any(|pattern| pattern.matches(Path::new(name)))
+            });
+
+            return name_excluded
+                || self
+                    .path_patterns
+                    .iter()
+                    .any(|pattern| pattern.matches(ancestor));
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    fn exclusions(args: Vec<&str>) -> Exclusions {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        return Exclusions::from_cli_args(&args).unwrap();
+    }
+
+    #[test]
+    fn given_no_exclusions_when_excludes_then_nothing_is_excluded() {
+        let exclusions = exclusions(vec![]);
+
+        assert!(exclusions.is_empty());
+        assert!(!exclusions.excludes(Path::new("src/main.rs")));
+    }
+
+    #[rstest]
+    #[case("*.swp", "file.swp", true)]
+    #[case("*.swp", "src/nested/.file.swp", true)]
+    #[case("*.swp", "src/main.rs", false)]
+    #[case("src/generated/**", "src/generated/mod.rs", true)]
+    #[case("src/generated/**", "src/generated/very/deeply/mod.rs", true)]
+    #[case("src/generated/**", "src/main.rs", false)]
+    #[case("src/generated/**", "other/src/generated/mod.rs", false)]
+    #[case("src/generated", "src/generated/mod.rs", true)]
+    #[case("node_modules", "web/node_modules/pkg/index.js", true)]
+    #[case("{target,node_modules}", "target/debug/jfswatch", true)]
+    #[case("*.{swp,tmp}", "src/main.rs.tmp", true)]
+    fn given_exclusion_when_excludes_then_matches_path_or_ancestor(
+        #[case] arg: &str,
+        #[case] path: &str,
+        #[case] expected: bool,
+    ) {
+        let exclusions = exclusions(vec![arg]);
+        assert_eq!(exclusions.excludes(Path::new(path)), expected);
+    }
+
+    #[rstest]
+    #[case("src/generated/**", "./src/generated/mod.rs")]
+    #[case("./src/generated/**", "src/generated/mod.rs")]
+    #[case("./src/generated/**", "./src/generated/mod.rs")]
+    fn given_dot_slash_prefix_when_excludes_then_matches_relative_path(
+        #[case] arg: &str,
+        #[case] path: &str,
+    ) {
+        let exclusions = exclusions(vec![arg]);
+        assert!(exclusions.excludes(Path::new(path)));
+    }
+
+    #[test]
+    fn given_absolute_path_in_cwd_when_excludes_then_matches_relative_pattern() {
+        let cwd = std::env::current_dir().unwrap();
+        let exclusions = exclusions(vec!["src/generated/**"]);
+
+        assert!(exclusions.excludes(&cwd.join("src/generated/mod.rs")));
+        assert!(!exclusions.excludes(&cwd.join("src/main.rs")));
+        assert!(!exclusions.excludes(Path::new("/elsewhere/src/generated/mod.rs")));
+    }
+
+    #[test]
+    fn given_absolute_pattern_in_cwd_when_excludes_then_matches_relative_path() {
+        let cwd = std::env::current_dir().unwrap();
+        let pattern = format!("{}/src/generated/**", cwd.display());
+        let exclusions = exclusions(vec![&pattern]);
+
+        assert!(exclusions.excludes(Path::new("src/generated/mod.rs")));
+        assert!(exclusions.excludes(&cwd.join("src/generated/mod.rs")));
+    }
+
+    #[test]
+    fn given_invalid_exclusion_when_from_cli_args_then_err() {
+        let args = vec!["*.swp".to_string(), "src/{generated".to_string()];
+
+        let error = Exclusions::from_cli_args(&args).unwrap_err();
+        assert_eq!(error.pattern, "src/{generated");
+        assert_eq!(error.offset, 4);
+    }
+}
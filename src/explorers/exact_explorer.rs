@@ -1,6 +1,8 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-use crate::explorers::Explorer;
+use crate::explorers::{
+    expand_home_and_variables, Explorer, PathCaptures, PatternError, WatchRoot,
+};
 use crate::watched_fs::WatchedFS;
 
 /// A file system explorer that looks for a simple path on the file system.
@@ -9,15 +11,59 @@ pub struct ExactExplorer {
     path: PathBuf,
 }
 
+impl ExactExplorer {
+    /// Construct an explorer for a path which is used as it is, without expanding anything in it
+    pub fn new(path: PathBuf) -> Self {
+        return Self { path };
+    }
+}
+
 impl Explorer for ExactExplorer {
-    fn from_cli_arg(arg: &str) -> Self {
-        let p = PathBuf::from(arg);
-        return Self { path: p };
+    fn from_cli_arg(arg: &str) -> Result<Self, PatternError> {
+        let path = expand_home_and_variables(arg)?;
+        if path.is_empty() {
+            return Err(PatternError::new(arg, 0, "the path is empty"));
+        }
+
+        return Ok(Self::new(PathBuf::from(path)));
     }
 
     fn explore(&self, watched_fs: &mut WatchedFS) {
         watched_fs.find(&self.path);
     }
+
+    fn matches(&self, path: &Path) -> bool {
+        return path == self.path;
+    }
+
+    fn roots(&self) -> Vec<WatchRoot> {
+        // the parent notices the path being created, deleted, or modified. if the path is a directory, watching it
+        // also notices its mtime changing as entries come and go
+        let parent = self.path.parent().unwrap_or(&self.path);
+        return vec![
+            WatchRoot {
+                dir: parent.to_path_buf(),
+                depth: Some(0),
+            },
+            WatchRoot {
+                dir: self.path.clone(),
+                depth: Some(0),
+            },
+        ];
+    }
+
+    fn pattern(&self) -> String {
+        return self.path.to_string_lossy().to_string();
+    }
+
+    fn matching_pattern(&self, path: &Path) -> Option<String> {
+        return self.matches(path).then(|| self.pattern());
+    }
+
+    fn captures(&self, path: &Path) -> Option<PathCaptures> {
+        // an exact path has no wildcards to capture anything
+        return self.matches(path).then(PathCaptures::default);
+    }
 }
 
 #[cfg(test)]
@@ -55,4 +101,54 @@ mod tests {
 
         assert_eq!(watched.len(), 1);
     }
+
+    #[test]
+    fn given_exact_explorer_when_matches_then_only_matches_that_path() {
+        let explorer = ExactExplorer::from_cli_arg("src/main.rs").unwrap();
+
+        assert!(explorer.matches(Path::new("src/main.rs")));
+        assert!(!explorer.matches(Path::new("src/cli.rs")));
+        assert!(!explorer.matches(Path::new("src")));
+    }
+
+    #[test]
+    fn given_environment_variable_when_from_cli_arg_then_expands_path() {
+        let home = std::env::var("HOME").unwrap();
+
+        let explorer = ExactExplorer::from_cli_arg("${HOME}/app.yml").unwrap();
+
+        assert_eq!(explorer.pattern(), format!("{home}/app.yml"));
+        assert!(explorer.matches(&Path::new(&home).join("app.yml")));
+    }
+
+    #[test]
+    fn given_undefined_variable_when_from_cli_arg_then_err() {
+        let error =
+            ExactExplorer::from_cli_arg("~/${JFSWATCH_TEST_EXACT_UNDEFINED}.yml").unwrap_err();
+
+        assert_eq!(error.offset, 2);
+        assert_eq!(
+            error.reason,
+            "the environment variable 'JFSWATCH_TEST_EXACT_UNDEFINED' isn't set"
+        );
+    }
+
+    #[test]
+    fn given_exact_explorer_when_roots_then_watches_parent_and_itself() {
+        let explorer = ExactExplorer::from_cli_arg("src/main.rs").unwrap();
+
+        assert_eq!(
+            explorer.roots(),
+            vec![
+                WatchRoot {
+                    dir: PathBuf::from("src"),
+                    depth: Some(0)
+                },
+                WatchRoot {
+                    dir: PathBuf::from("src/main.rs"),
+                    depth: Some(0)
+                },
+            ]
+        );
+    }
 }
@@ -1,55 +1,221 @@
-use crate::explorers::glob_explorer::extend::ExtendedGlobPatternBuilder;
-use crate::explorers::Explorer;
+use std::path::{Path, PathBuf};
+
+use crate::explorers::glob_explorer::extend::EXPANSION_LIMIT;
+use crate::explorers::glob_explorer::ignore_files::{self, IgnoreMatcher};
+use crate::explorers::glob_explorer::pattern::{ExtendedGlobPattern, PathCaptures};
+use crate::explorers::{expand_home_and_variables, Explorer, PatternError, WatchRoot};
 use crate::watched_fs::WatchedFS;
 
 #[derive(Debug)]
 pub struct GlobExplorer {
-    patterns: Vec<String>,
+    pattern: ExtendedGlobPattern,
+
+    /// The directories to walk while exploring, which are found once since expanding the braces to find them isn't
+    /// free
+    roots: Vec<WatchRoot>,
+
+    /// Decides which paths are ignored by `.gitignore`, `.ignore`, and `.git/info/exclude` files, when they're
+    /// skipped
+    ignore_files: Option<IgnoreMatcher>,
+}
+
+impl GlobExplorer {
+    /// Construct an explorer for a rule's pattern, where braces around a bare name like `{name}` are a named capture
+    /// which matches like `*`. `~` and environment variables are expanded first, like with `::from_cli_arg`
+    pub fn from_rule_pattern(arg: &str) -> Result<Self, PatternError> {
+        let arg = expand_home_and_variables(arg)?;
+        return Ok(Self::new(ExtendedGlobPattern::with_captures(&arg)?));
+    }
+
+    fn new(pattern: ExtendedGlobPattern) -> Self {
+        return Self {
+            roots: watch_roots(&pattern),
+            pattern,
+            ignore_files: None,
+        };
+    }
+
+    /// Skip paths ignored by `.gitignore`, `.ignore`, and `.git/info/exclude` files (and `.git` directories). Ignored
+    /// directories are never explored
+    pub fn with_ignore_files(mut self, respect_ignore_files: bool) -> Self {
+        self.ignore_files = respect_ignore_files
+            .then(|| IgnoreMatcher::new(std::env::current_dir().unwrap_or_default()));
+        return self;
+    }
+
+    /// Whether exploring skips the path because of ignore files. It's checked from each root that the path is in,
+    /// since the ignore files only apply to the directories below where the walk starts
+    fn is_ignored(&self, path: &Path) -> bool {
+        let Some(ignore_files) = &self.ignore_files else {
+            return false;
+        };
+
+        let mut roots = self
+            .roots
+            .iter()
+            .map(|root| root.dir.as_path())
+            .filter(|dir| path.starts_with(dir))
+            .peekable();
+        if roots.peek().is_none() {
+            return ignore_files.is_ignored(path.parent().unwrap_or(path), path);
+        }
+        return roots.all(|dir| ignore_files.is_ignored(dir, path));
+    }
 }
 
-/// An explorer that uses extended glob patterns to find paths on the file system.
+/// Whether a path component of a basic glob pattern has any wildcards or extglob operators
+fn has_wildcards(component: &str) -> bool {
+    return component.contains(['*', '?', '['])
+        || ["!(", "@(", "+("]
+            .iter()
+            .any(|operator| component.contains(operator));
+}
+
+/// Finds the directory to watch for a basic glob pattern: the leading path components without any wildcards
+fn watch_root(pattern: &str) -> WatchRoot {
+    let components: Vec<&str> = pattern.split('/').collect();
+    let literal_count = components
+        .iter()
+        .take_while(|component| !has_wildcards(component))
+        .count();
+
+    if literal_count == components.len() {
+        // no wildcards, so this is just an exact path
+        let path = Path::new(pattern);
+        return WatchRoot {
+            dir: path.parent().unwrap_or(path).to_path_buf(),
+            depth: Some(0),
+        };
+    }
+
+    let mut dir = components[..literal_count].join("/");
+    if dir.is_empty() && pattern.starts_with('/') {
+        dir = "/".to_string();
+    }
+
+    let wildcard_components = &components[literal_count..];
+    let depth = if wildcard_components
+        .iter()
+        .any(|component| component.contains("**"))
+    {
+        None
+    } else {
+        Some(wildcard_components.len() - 1)
+    };
+
+    return WatchRoot {
+        dir: PathBuf::from(dir),
+        depth,
+    };
+}
+
+/// Finds the directories to watch for an extended glob pattern, from the basic patterns that its braces expand into.
+/// A directory which is already walked for another one is left out, so each directory is only walked once. When the
+/// braces expand into more than `EXPANSION_LIMIT` basic patterns, everything below the pattern's leading literal
+/// directory is watched instead
+fn watch_roots(pattern: &ExtendedGlobPattern) -> Vec<WatchRoot> {
+    let mut roots: Vec<WatchRoot> = Vec::new();
+    let mut patterns = pattern.patterns();
+
+    for root in patterns
+        .by_ref()
+        .take(EXPANSION_LIMIT)
+        .map(|pattern| watch_root(&pattern))
+    {
+        if roots.iter().any(|other| other.covers(&root)) {
+            continue;
+        }
+        roots.retain(|other| !root.covers(other));
+        roots.push(root);
+    }
+
+    if patterns.next().is_some() {
+        let prefix = pattern.literal_prefix();
+        let dir = match prefix.rfind('/') {
+            Some(0) => "/",
+            Some(end) => &prefix[..end],
+            None => "",
+        };
+        return vec![WatchRoot {
+            dir: PathBuf::from(dir),
+            depth: None,
+        }];
+    }
+    return roots;
+}
+
+/// An explorer that uses extended glob patterns to find paths on the file system. Patterns are matched against the
+/// whole path by the crate's own matcher, which accepts:
+///
+/// > ? matches any single character, and * matches any (possibly empty) sequence of characters. Neither matches `/`,
+/// >    but both match a leading `.`.
+/// > ** matches any number of directories, including none. It must form a whole path component, so both **a and b**
+/// >    are invalid, as is a sequence of more than two consecutive *.
+/// > [...] matches any character inside the brackets, and ranges like [0-9] match any character between the ends
+/// >    inclusive. [!...] matches any character not in the brackets. A ] right after [ or [! is part of the class, and
+/// >    a - at the start or end is matched as-is, e.g. []] or [abc-]. An unclosed bracket is invalid.
+/// > The metacharacters ?, *, [, and { can be matched by using brackets (e.g. [?]). A backslash is a literal
+/// >    character, and also keeps the character after it from being special.
+///
+/// Braces match one of their comma separated alternatives, e.g. {sub1,sub2}. They can be nested, and each alternative
+/// can span several path components, e.g. {src/*.rs,Cargo.toml}. Braces with a range match one of its values like in
+/// bash: {1..10}, {a..f}, {0..100..5} with a step, or {01..12} padded with zeros to the width of the wider end. Ranges
+/// expand into at most `EXPANSION_LIMIT` values. In a rule's pattern, braces around a bare name like {name} are a
+/// named capture which matches like *.
 ///
-/// From the glob docs: https://docs.rs/glob/latest/glob/struct.Pattern.html
+/// Bash's extglob operators match within a single path component, and their alternatives are separated by `|`:
 ///
-/// > ? matches any single character.
-/// > * matches any (possibly empty) sequence of characters.
-/// > ** matches the current directory and arbitrary subdirectories. This sequence must form a single path component,
-/// >    so both **a and b** are invalid and will result in an error. A sequence of more than two consecutive *
-/// >    characters is also invalid.
-/// > [...] matches any character inside the brackets. Character sequences can also specify ranges of characters, as
-/// >    ordered by Unicode, so e.g. [0-9] specifies any character between 0 and 9 inclusive. An unclosed bracket is invalid.
-/// > [!...] is the negation of [...], i.e. it matches any characters not in the brackets.
-/// > The metacharacters ?, *, [, ] can be matched by using brackets (e.g. [?]). When a ] occurs immediately following
-/// >    [ or [! then it is interpreted as being part of, rather then ending, the character set, so ] and NOT ] can be
-/// >    matched by []] and [!]] respectively. The - character can be specified inside a character sequence pattern by
-/// >    placing it at the start or the end, e.g. [abc-].
+/// > !(a|b) matches anything except one of the alternatives, e.g. src/!(*.test).ts
+/// > @(a|b) matches exactly one of the alternatives
+/// > ?(a|b) matches nothing or one of the alternatives
+/// > *(a|b) matches any number of the alternatives
+/// > +(a|b) matches at least one of the alternatives, e.g. part-+([0-9]).csv
 ///
-/// There is also extended support for disjunctive subpatterns using {sub1,sub2} syntax.
+/// Braces aren't expanded to explore: the directories that matching paths could be in are walked once, and each path
+/// is matched against the whole pattern.
 impl Explorer for GlobExplorer {
-    fn from_cli_arg(arg: &str) -> Self {
-        let patterns: Vec<String> = ExtendedGlobPatternBuilder::from_pattern(arg)
-            .build()
-            .into_iter()
-            .collect();
+    fn from_cli_arg(arg: &str) -> Result<Self, PatternError> {
+        let arg = expand_home_and_variables(arg)?;
+        return Ok(Self::new(ExtendedGlobPattern::new(&arg)?));
+    }
 
-        for pattern in &patterns {
-            if let Err(error) = glob::Pattern::new(pattern) {
-                panic!(
-                    "Glob pattern from '{arg}' is invalid: '{}'",
-                    error.to_string()
-                );
+    fn explore(&self, watched_fs: &mut WatchedFS) {
+        if let Some(ignore_files) = &self.ignore_files {
+            ignore_files.clear();
+        }
+        for root in self.roots.iter() {
+            for path in ignore_files::walk(root, self.ignore_files.is_some()) {
+                if self.pattern.matches(&path) {
+                    watched_fs.find(&path);
+                }
             }
         }
+    }
 
-        return Self { patterns };
+    fn matches(&self, path: &Path) -> bool {
+        return self.pattern.matches(path) && !self.is_ignored(path);
     }
 
-    fn explore(&self, watched_fs: &mut WatchedFS) {
-        for pattern in self.patterns.iter() {
-            for path in glob::glob(pattern).unwrap().filter_map(Result::ok) {
-                watched_fs.find(&path);
-            }
+    fn roots(&self) -> Vec<WatchRoot> {
+        return self.roots.clone();
+    }
+
+    fn pattern(&self) -> String {
+        return self.pattern.as_str().to_string();
+    }
+
+    fn matching_pattern(&self, path: &Path) -> Option<String> {
+        if !self.matches(path) {
+            return None;
+        }
+        return self.pattern.matching_pattern(path);
+    }
+
+    fn captures(&self, path: &Path) -> Option<PathCaptures> {
+        if !self.matches(path) {
+            return None;
         }
+        return self.pattern.captures(path);
     }
 }
 
@@ -72,7 +238,7 @@ mod tests {
         // to use the temporary 'basedir', we must make the glob patterns relative to this directory and not
         // the current working directory. this is accomplished by translating it to an absolute path
         let glob_pattern = format!("{}/{}", basedir.to_string_lossy(), glob_pattern);
-        let explorer = GlobExplorer::from_cli_arg(&glob_pattern);
+        let explorer = GlobExplorer::from_cli_arg(&glob_pattern).unwrap();
         explorer.explore(&mut watched_fs);
 
         assert_eq!(watched_fs.len(), expected_relative_paths.len());
@@ -87,12 +253,36 @@ mod tests {
     }
 
     #[rstest]
-    #[case("[")]
-    #[case("**a")]
-    #[case("a**")]
-    #[should_panic]
-    fn given_invalid_glob_pattern_when_new_glob_explorer_then_panics(#[case] pattern: &str) {
-        GlobExplorer::from_cli_arg(pattern);
+    #[case("[", 0)]
+    #[case("**a", 2)]
+    #[case("a**", 0)]
+    #[case("}", 0)]
+    #[case("a}", 1)]
+    #[case("src/{a,b", 4)]
+    #[case("src/!(*.test.ts", 4)]
+    fn given_invalid_glob_pattern_when_new_glob_explorer_then_err_at_offset(
+        #[case] pattern: &str,
+        #[case] offset: usize,
+    ) {
+        let error = GlobExplorer::from_cli_arg(pattern).unwrap_err();
+        assert_eq!(error.pattern, pattern);
+        assert_eq!(error.offset, offset);
+    }
+
+    #[test]
+    fn given_tilde_when_from_cli_arg_then_watches_home_directory() {
+        let home = std::env::var("HOME").unwrap();
+
+        let explorer = GlobExplorer::from_cli_arg("~/.config/app/*.yml").unwrap();
+
+        assert_eq!(explorer.pattern(), format!("{home}/.config/app/*.yml"));
+        assert_eq!(
+            explorer.roots(),
+            vec![WatchRoot {
+                dir: PathBuf::from(format!("{home}/.config/app")),
+                depth: Some(0)
+            }]
+        );
     }
 
     #[test]
@@ -168,12 +358,212 @@ mod tests {
         );
     }
 
+    #[rstest]
+    #[case("src/!(*.test).ts", vec!["src/main.ts", "src/lib.ts"])]
+    #[case("@(src|lib)/*.ts", vec!["src/main.ts", "src/lib.ts", "src/main.test.ts", "lib/util.ts"])]
+    #[case("part-+([0-9]).csv", vec!["part-1.csv", "part-42.csv"])]
+    #[case("{src,lib}/?(main).ts", vec!["src/main.ts"])]
+    fn given_extglob_pattern_when_explore_then_finds_matches(
+        #[case] glob_pattern: &str,
+        #[case] expected: Vec<&str>,
+    ) {
+        absolute_fs_test(
+            vec![
+                "src/main.ts",
+                "src/lib.ts",
+                "src/main.test.ts",
+                "lib/util.ts",
+                "part-1.csv",
+                "part-42.csv",
+                "part-x.csv",
+            ],
+            glob_pattern,
+            expected,
+        );
+    }
+
+    #[test]
+    fn given_brace_range_when_explore_then_finds_each_padded_value() {
+        absolute_fs_test(
+            vec!["part-000.csv", "part-005.csv", "part-010.csv", "part-5.csv"],
+            "part-{000..010..5}.csv",
+            vec!["part-000.csv", "part-005.csv", "part-010.csv"],
+        );
+    }
+
     #[test]
     fn given_relative_glob_pattern_when_explore_then_finds_relative_matches() {
         let mut watched_fs = WatchedFS::new(10);
 
         // 'cargo test' will always run from the root of the project, alongside the Cargo.toml file
-        let explorer = GlobExplorer::from_cli_arg("src/jfswatch.rs");
+        let explorer = GlobExplorer::from_cli_arg("src/jfswatch.rs").unwrap();
+        explorer.explore(&mut watched_fs);
+
+        let explored_paths: Vec<String> = watched_fs.paths().map(|p| p.to_string()).collect();
+        assert!(
+            explored_paths.contains(&"src/jfswatch.rs".to_string()),
+            "Explored exactly: {:?}",
+            explored_paths
+        );
+    }
+
+    #[rstest]
+    #[case("src/*.rs", "src/main.rs", true)]
+    #[case("src/*.rs", "src/nested/main.rs", false)]
+    #[case("src/**/*.rs", "src/nested/main.rs", true)]
+    #[case("src/**/*.rs", "src/main.rs", true)]
+    #[case("config.{yml,yaml}", "config.yaml", true)]
+    #[case("config.{yml,yaml}", "config.json", false)]
+    fn given_glob_explorer_when_matches_then_agrees_with_explore(
+        #[case] pattern: &str,
+        #[case] path: &str,
+        #[case] expected: bool,
+    ) {
+        let explorer = GlobExplorer::from_cli_arg(pattern).unwrap();
+        assert_eq!(explorer.matches(Path::new(path)), expected);
+    }
+
+    #[rstest]
+    #[case("config.{yml,yaml}", "config.yaml", Some("config.yaml"))]
+    #[case("src/{bin/*,lib}.rs", "src/bin/main.rs", Some("src/bin/*.rs"))]
+    #[case("src/*.rs", "src/main.rs", Some("src/*.rs"))]
+    #[case("config.{yml,yaml}", "config.json", None)]
+    fn given_glob_explorer_when_matching_pattern_then_finds_expanded_subpattern(
+        #[case] pattern: &str,
+        #[case] path: &str,
+        #[case] expected: Option<&str>,
+    ) {
+        let explorer = GlobExplorer::from_cli_arg(pattern).unwrap();
+        assert_eq!(
+            explorer.matching_pattern(Path::new(path)).as_deref(),
+            expected
+        );
+    }
+
+    #[rstest]
+    #[case("Cargo.toml", "", Some(0))]
+    #[case("*.rs", "", Some(0))]
+    #[case("src/*.rs", "src", Some(0))]
+    #[case("src/*/mod.rs", "src", Some(1))]
+    #[case("src/**/*.rs", "src", None)]
+    #[case("/etc/app/**", "/etc/app", None)]
+    #[case("/*.conf", "/", Some(0))]
+    #[case("src/@(bin|lib)/*.rs", "src", Some(1))]
+    #[case("src/!(*.test).ts", "src", Some(0))]
+    fn given_glob_pattern_when_roots_then_watches_leading_literal_directory(
+        #[case] pattern: &str,
+        #[case] dir: &str,
+        #[case] depth: Option<usize>,
+    ) {
+        let explorer = GlobExplorer::from_cli_arg(pattern).unwrap();
+        assert_eq!(
+            explorer.roots(),
+            vec![WatchRoot {
+                dir: PathBuf::from(dir),
+                depth
+            }]
+        );
+    }
+
+    #[rstest]
+    #[case("{src,lib}/*.rs", vec![("src", Some(0)), ("lib", Some(0))])]
+    #[case("src/{*,bin/*}.rs", vec![("src", Some(0)), ("src/bin", Some(0))])]
+    #[case("{src/**,src/bin}/*.rs", vec![("src", None)])]
+    #[case("{a,b,c}/{x,y,z}/**/*.{rs,toml}", vec![
+        ("a/x", None), ("a/y", None), ("a/z", None),
+        ("b/x", None), ("b/y", None), ("b/z", None),
+        ("c/x", None), ("c/y", None), ("c/z", None),
+    ])]
+    #[case("src/{explorers/**,list}.rs", vec![("src/explorers", None), ("src", Some(0))])]
+    #[case("data/{1..100}/{1..100}/*.csv", vec![("data", None)])]
+    fn given_braces_when_roots_then_watches_each_shared_directory_once(
+        #[case] pattern: &str,
+        #[case] expected: Vec<(&str, Option<usize>)>,
+    ) {
+        let explorer = GlobExplorer::from_cli_arg(pattern).unwrap();
+
+        let expected: Vec<WatchRoot> = expected
+            .into_iter()
+            .map(|(dir, depth)| WatchRoot {
+                dir: PathBuf::from(dir),
+                depth,
+            })
+            .collect();
+        assert_eq!(explorer.roots(), expected);
+    }
+
+    #[test]
+    fn given_overlapping_braces_when_explore_then_finds_each_path_once() {
+        absolute_fs_test(
+            vec![
+                "src/main.rs",
+                "src/bin/cli.rs",
+                "src/bin/nested/deep.rs",
+                "lib/lib.rs",
+            ],
+            "{src,src/bin,lib}/{*,**/*}.rs",
+            vec![
+                "src/main.rs",
+                "src/bin/cli.rs",
+                "src/bin/nested/deep.rs",
+                "lib/lib.rs",
+            ],
+        );
+    }
+
+    #[test]
+    fn given_ignore_files_when_explore_then_skips_ignored_paths() {
+        let tmp = tempdir_in(".").unwrap();
+        let basedir = tmp.path().to_owned();
+        make_files(&basedir, vec!["src/main.rs", "target/debug/build.rs"]);
+        std::fs::write(basedir.join(".gitignore"), "target/\n").unwrap();
+
+        let glob_pattern = format!("{}/**/*.rs", basedir.to_string_lossy());
+        let explorer = GlobExplorer::from_cli_arg(&glob_pattern)
+            .unwrap()
+            .with_ignore_files(true);
+        let mut watched_fs = WatchedFS::new(10);
+        explorer.explore(&mut watched_fs);
+
+        let main_rs = basedir.join("src/main.rs");
+        let build_rs = basedir.join("target/debug/build.rs");
+        assert_eq!(
+            watched_fs.paths().cloned().collect::<Vec<String>>(),
+            vec![main_rs.to_string_lossy().to_string()]
+        );
+        assert!(explorer.matches(&main_rs));
+        assert!(!explorer.matches(&build_rs));
+    }
+
+    #[test]
+    fn given_pattern_inside_ignored_directory_when_matches_then_agrees_with_explore() {
+        let tmp = tempdir_in(".").unwrap();
+        let basedir = tmp.path().to_owned();
+        make_files(&basedir, vec!["target/debug/build.rs"]);
+        std::fs::write(basedir.join(".gitignore"), "target/\n").unwrap();
+
+        let glob_pattern = format!("{}/target/debug/*.rs", basedir.to_string_lossy());
+        let explorer = GlobExplorer::from_cli_arg(&glob_pattern)
+            .unwrap()
+            .with_ignore_files(true);
+        let mut watched_fs = WatchedFS::new(10);
+        explorer.explore(&mut watched_fs);
+
+        let build_rs = basedir.join("target/debug/build.rs");
+        assert_eq!(
+            watched_fs.paths().cloned().collect::<Vec<String>>(),
+            vec![build_rs.to_string_lossy().to_string()]
+        );
+        assert!(explorer.matches(&build_rs));
+    }
+
+    #[test]
+    fn given_ignore_files_and_relative_pattern_when_explore_then_finds_relative_matches() {
+        let mut watched_fs = WatchedFS::new(10);
+
+        let explorer = GlobExplorer::from_cli_arg("src/*.rs")
+            .unwrap()
+            .with_ignore_files(true);
         explorer.explore(&mut watched_fs);
 
         let explored_paths: Vec<String> = watched_fs.paths().map(|p| p.to_string()).collect();
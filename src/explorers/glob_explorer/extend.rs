@@ -1,165 +1,470 @@
-use std::collections::HashSet;
+use std::iter;
 
-/// A data type used to help parse extended glob patterns into basic glob patterns.
-#[derive(Debug)]
-enum ExtendGlobToken {
-    /// A simple top-level character. E.g., 'a'
+use crate::explorers::PatternError;
+
+/// The most values that a range like `{1..10}` may expand into, and the most basic patterns that braces are expanded
+/// into to find the directories to watch. Braces are otherwise never expanded, so this keeps a pattern like
+/// `{1..100}/{1..100}/{1..100}` from growing without bounds
+pub const EXPANSION_LIMIT: usize = 4096;
+
+/// Describes why a range inside of braces is invalid
+const INVALID_RANGE: &str =
+    "invalid range, expected {START..END} or {START..END..STEP} with integers or letters";
+
+/// How many times an extglob operator's alternatives may match
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Extglob {
+    /// `!(a|b)` matches anything except one of the alternatives
+    Not,
+
+    /// `@(a|b)` matches exactly one of the alternatives
+    One,
+
+    /// `?(a|b)` matches nothing or one of the alternatives
+    ZeroOrOne,
+
+    /// `*(a|b)` matches the alternatives any number of times
+    ZeroOrMore,
+
+    /// `+(a|b)` matches the alternatives at least once
+    OneOrMore,
+}
+
+impl Extglob {
+    fn from_operator(c: char) -> Option<Self> {
+        return match c {
+            '!' => Some(Self::Not),
+            '@' => Some(Self::One),
+            '?' => Some(Self::ZeroOrOne),
+            '*' => Some(Self::ZeroOrMore),
+            '+' => Some(Self::OneOrMore),
+            _ => None,
+        };
+    }
+}
+
+/// A part of an extended glob pattern
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
     Literal(char),
 
-    /// A glob subpattern. E.g., '{a,b,{00,11}!}' as ["a", "b", "{00,11}!"].
-    /// These patterns are parsed later in another recursive step
-    Subpatterns(Vec<String>),
+    /// `?` matches any single character
+    AnyChar,
+
+    /// `*` matches any sequence of characters, and a named capture like `{name}` matches like it
+    AnyChars(Option<String>),
+
+    /// `**` matches any number of directories
+    Recursive,
+
+    /// `[a-z]` matches any character in one of the ranges, or `[!a-z]` any character outside of them
+    Class {
+        negated: bool,
+        ranges: Vec<(char, char)>,
+        source: String,
+    },
+
+    /// Braces like `{a,b}` or a range like `{1..3}` match one of their alternatives, which may span several path
+    /// components
+    Braces(Vec<Vec<Token>>),
+
+    /// An extglob operator, with the tokens of each of its alternatives
+    Extglob {
+        kind: Extglob,
+        alternatives: Vec<Vec<Token>>,
+        source: String,
+    },
 }
 
-/// A builder-flavoured struct that helps convert extended glob patterns into a collection of basic glob patterns.
-pub struct ExtendedGlobPatternBuilder {
-    /// the individual components of the glob pattern
-    tokens: Vec<ExtendGlobToken>,
+impl Token {
+    /// The token as it's written in a basic glob pattern. Named captures are written as the `*` that they match like
+    fn basic(&self) -> String {
+        return match self {
+            Token::Literal(c) => c.to_string(),
+            Token::AnyChar => "?".to_string(),
+            Token::AnyChars(_) => "*".to_string(),
+            Token::Recursive => "**".to_string(),
+            Token::Class { source, .. } | Token::Extglob { source, .. } => source.clone(),
+            Token::Braces(alternatives) => {
+                let alternatives: Vec<String> = alternatives
+                    .iter()
+                    .map(|alternative| alternative.iter().map(Token::basic).collect())
+                    .collect();
+                format!("{{{}}}", alternatives.join(","))
+            }
+        };
+    }
+}
 
-    /// the current parsing depth (tracks nested {}) - recall; we only parse depth 1 subpatterns in this step, and
-    /// rely on recursive steps to parse nested subpatterns
-    depth: usize,
+/// Whether the text inside of braces is a range like `1..10`, rather than a disjunction. A range has a single
+/// subpattern without any nested braces, escapes, or path separators
+fn is_range(text: &str) -> bool {
+    return text.contains("..") && !text.contains(['{', ',', '\\', '/']);
+}
 
-    /// flags whether the previous character was a backslash (\) or not
-    escaped: bool,
+/// Parses an integer end or step of a range, which may be negative
+fn parse_integer(text: &str) -> Option<i64> {
+    let digits = text.strip_prefix('-').unwrap_or(text);
+    if digits.is_empty() || !digits.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    return text.parse().ok();
 }
 
-impl ExtendedGlobPatternBuilder {
-    /// A helper function that converts an extended glob pattern into a collection of basic glob patterns.
-    pub fn from_pattern(pattern: &str) -> Self {
-        let mut builder = Self::new();
-        for c in pattern.chars() {
-            builder.character(c);
-        }
-        return builder;
+/// Parses a letter end of a range
+fn parse_letter(text: &str) -> Option<char> {
+    let mut chars = text.chars();
+    return match (chars.next(), chars.next()) {
+        (Some(c), None) if c.is_ascii_alphabetic() => Some(c),
+        _ => None,
+    };
+}
+
+/// Every `step`th value from `start` to `end`, counting down when `end` is smaller. Errors if there are more than
+/// `EXPANSION_LIMIT` of them
+fn steps(start: i64, end: i64, step: u64) -> Result<Vec<i64>, String> {
+    let count = (i128::from(end) - i128::from(start)).unsigned_abs() / u128::from(step) + 1;
+    if count > EXPANSION_LIMIT as u128 {
+        return Err(format!(
+            "the range expands into more than {EXPANSION_LIMIT} values"
+        ));
     }
 
-    /// Construct a new empty extended glob pattern builder. Helpful when calling `::character` directly, but
-    /// it's generally more friendly to use `::from_pattern` instead.
-    pub fn new() -> Self {
-        return Self {
-            tokens: Vec::new(),
-            depth: 0,
-            escaped: false,
+    let step = if end < start {
+        -i128::from(step)
+    } else {
+        i128::from(step)
+    };
+    let (end, mut value) = (i128::from(end), i128::from(start));
+
+    let mut values = Vec::new();
+    while (step > 0 && value <= end) || (step < 0 && value >= end) {
+        values.push(value as i64);
+        value += step;
+    }
+    return Ok(values);
+}
+
+/// Expands a bash-style range from inside of braces, like `1..10`, `01..12`, `a..f`, or `0..100..5`. The sign of the
+/// step is ignored, like in bash. When either end of a numeric range has a leading zero, every number is padded with
+/// zeros to the width of the wider end, including its sign
+fn expand_range(range: &str) -> Result<Vec<String>, String> {
+    let parts: Vec<&str> = range.split("..").collect();
+    let step = match parts[..] {
+        [_, _] => 1,
+        [_, _, step] => parse_integer(step)
+            .ok_or(INVALID_RANGE.to_string())?
+            .unsigned_abs(),
+        _ => return Err(INVALID_RANGE.to_string()),
+    };
+    if step == 0 {
+        return Err("the step of a range can't be 0".to_string());
+    }
+
+    if let (Some(start), Some(end)) = (parse_integer(parts[0]), parse_integer(parts[1])) {
+        let padded = parts[..2].iter().any(|part| {
+            let digits = part.strip_prefix('-').unwrap_or(part);
+            return digits.len() > 1 && digits.starts_with('0');
+        });
+        let width = if padded {
+            parts[0].len().max(parts[1].len())
+        } else {
+            0
         };
+
+        return Ok(steps(start, end, step)?
+            .into_iter()
+            .map(|value| {
+                if value < 0 {
+                    return format!(
+                        "-{:0>width$}",
+                        value.unsigned_abs(),
+                        width = width.saturating_sub(1)
+                    );
+                }
+                return format!("{value:0>width$}");
+            })
+            .collect());
     }
 
-    /// Parse a single additional character from the (potentially) extended glob pattern.
-    pub fn character(&mut self, c: char) {
-        if self.escaped {
-            self.escaped = false;
-            self.normal_character(c);
-            return;
+    if let (Some(start), Some(end)) = (parse_letter(parts[0]), parse_letter(parts[1])) {
+        if start.is_ascii_lowercase() != end.is_ascii_lowercase() {
+            return Err("both ends of a letter range must have the same case".to_string());
         }
+        return Ok(steps(start as i64, end as i64, step)?
+            .into_iter()
+            .map(|value| char::from(value as u8).to_string())
+            .collect());
+    }
 
-        match c {
-            '{' => self.open_parenthesis(),
-            '}' => self.close_parenthesis(),
-            ',' => self.comma(),
-            '\\' => {
-                self.escaped = true;
-                self.normal_character(c);
-            }
-            _ => self.normal_character(c),
-        }
+    return Err(INVALID_RANGE.to_string());
+}
+
+/// What the parser is inside of, which decides the characters that end a sequence of tokens
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Context {
+    TopLevel,
+
+    /// A `,` or `}` ends one of the alternatives of braces
+    Braces,
+
+    /// A `|` or `)` ends one of the alternatives of an extglob operator
+    Extglob,
+}
+
+/// Whether `c` can be next to `**`, which must be a whole path component. The delimiters of braces and extglob
+/// operators are allowed too, since the alternatives inside of them may start or end a component
+fn bounds_recursive(c: Option<&char>) -> bool {
+    return c.is_none_or(|c| ['/', '{', '}', ',', '(', ')', '|'].contains(c));
+}
+
+/// Parses an extended glob pattern into tokens, keeping track of the character offsets for errors
+struct ExtendedGlobPatternParser<'a> {
+    pattern: &'a str,
+
+    chars: Vec<char>,
+
+    /// how many characters have been parsed so far
+    offset: usize,
+
+    /// whether braces around a bare name like `{name}` are a named capture
+    captures: bool,
+}
+
+impl ExtendedGlobPatternParser<'_> {
+    fn error(&self, offset: usize, reason: &str) -> PatternError {
+        return PatternError::new(self.pattern, offset, reason);
     }
 
-    /// Converts the tokenized extended glob pattern into a collection of basic glob patterns.
-    pub fn build(self) -> HashSet<String> {
-        let mut basic_glob_patterns: Vec<String> = vec!["".to_owned()];
-        for token in self.tokens {
-            match token {
-                ExtendGlobToken::Literal(c) => {
-                    for pattern in basic_glob_patterns.iter_mut() {
-                        pattern.push(c);
-                    }
-                }
-                ExtendGlobToken::Subpatterns(subpatterns) => {
-                    let mut new_basic_glob_patterns: Vec<String> =
-                        Vec::with_capacity(basic_glob_patterns.len() * subpatterns.len());
-
-                    for pattern in basic_glob_patterns.iter_mut() {
-                        for subpattern in &subpatterns {
-                            new_basic_glob_patterns.push(format!("{pattern}{subpattern}"));
-                        }
-                    }
+    fn peek(&self) -> Option<char> {
+        return self.chars.get(self.offset).copied();
+    }
 
-                    basic_glob_patterns = new_basic_glob_patterns;
-                }
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.offset += 1;
+        return Some(c);
+    }
+
+    /// Parses tokens until the end of the pattern, or until a character which ends the `context`'s sequence. That
+    /// character isn't consumed
+    fn sequence(&mut self, context: Context) -> Result<Vec<Token>, PatternError> {
+        let mut tokens = Vec::new();
+
+        while let Some(c) = self.peek() {
+            match (context, c) {
+                (Context::Braces, ',' | '}') | (Context::Extglob, '|' | ')') => break,
+                _ => {}
             }
-        }
+            let start = self.offset;
+            self.offset += 1;
 
-        return basic_glob_patterns.into_iter().collect();
-    }
+            if let Some(kind) = Extglob::from_operator(c).filter(|_| self.peek() == Some('(')) {
+                tokens.push(self.extglob(start, kind)?);
+                continue;
+            }
 
-    fn comma(&mut self) {
-        if self.depth == 0 {
-            self.tokens.push(ExtendGlobToken::Literal(','));
-        } else if self.depth == 1 {
-            // delimits two subpatterns in the depth 1 disjuction; prepare for the next subpattern
-            match self.tokens.last_mut().unwrap() {
-                ExtendGlobToken::Subpatterns(subpatterns) => {
-                    subpatterns.push("".to_owned());
+            let token = match c {
+                '\\' => {
+                    // the backslash is a literal character itself, and keeps the next one from being special
+                    tokens.push(Token::Literal(c));
+                    match self.next() {
+                        Some(escaped) => Token::Literal(escaped),
+                        None => continue,
+                    }
                 }
-                _ => panic!("Comma was expected to delimit two subpatterns at depth 1. Last token is the wrong type"),
-            }
-        } else {
-            self.push_subpattern_character(',');
+                '{' => self.braces(start)?,
+                '}' if context == Context::TopLevel => {
+                    return Err(self.error(start, "unmatched '}'"));
+                }
+                '*' => self.stars(start)?,
+                '?' => Token::AnyChar,
+                '[' => self.class(start)?,
+                _ => Token::Literal(c),
+            };
+            tokens.push(token);
         }
+
+        return Ok(tokens);
     }
 
-    fn normal_character(&mut self, c: char) {
-        if self.depth == 0 {
-            self.tokens.push(ExtendGlobToken::Literal(c));
-        } else {
-            self.push_subpattern_character(c);
+    /// Parses `*` or `**`, whose first star is at `start` and was already consumed. A star that is followed by an
+    /// opening parenthesis is left to start an extglob operator
+    fn stars(&mut self, start: usize) -> Result<Token, PatternError> {
+        let mut end = start + 1;
+        while self.chars.get(end) == Some(&'*') && self.chars.get(end + 1) != Some(&'(') {
+            end += 1;
         }
+        self.offset = end;
+
+        // `**` must form a whole path component, and longer runs of stars aren't a wildcard
+        return match end - start {
+            1 => Ok(Token::AnyChars(None)),
+            2 if start > 0 && !bounds_recursive(self.chars.get(start - 1)) => Err(self.error(
+                start - 1,
+                "recursive wildcards must form a single path component",
+            )),
+            2 if !bounds_recursive(self.chars.get(end)) => {
+                Err(self.error(end, "recursive wildcards must form a single path component"))
+            }
+            2 => Ok(Token::Recursive),
+            _ => Err(self.error(
+                start + 2,
+                "wildcards are either regular `*` or recursive `**`",
+            )),
+        };
     }
 
-    fn open_parenthesis(&mut self) {
-        self.depth += 1;
+    /// Parses a character class like `[!a-z]`, whose opening bracket is at `start` and was already consumed. A `]`
+    /// right after the opening bracket is part of the class, so `[]]` matches a `]`
+    fn class(&mut self, start: usize) -> Result<Token, PatternError> {
+        let negated = self.peek() == Some('!');
+        let first = start + 1 + usize::from(negated);
+        let end = self
+            .chars
+            .get(first + 1..)
+            .and_then(|rest| rest.iter().position(|&c| c == ']'))
+            .map(|position| first + 1 + position)
+            .ok_or_else(|| self.error(start, "invalid range pattern"))?;
 
-        if self.depth == 1 {
-            // prepare for subpatterns at depth 1
-            self.tokens
-                .push(ExtendGlobToken::Subpatterns(vec!["".to_owned()]));
-        } else {
-            self.push_subpattern_character('{');
+        let members = &self.chars[first..end];
+        let mut ranges = Vec::new();
+        let mut i = 0;
+        while i < members.len() {
+            if i + 2 < members.len() && members[i + 1] == '-' {
+                ranges.push((members[i], members[i + 2]));
+                i += 3;
+            } else {
+                ranges.push((members[i], members[i]));
+                i += 1;
+            }
         }
+
+        self.offset = end + 1;
+        return Ok(Token::Class {
+            negated,
+            ranges,
+            source: self.chars[start..=end].iter().collect(),
+        });
     }
 
-    fn close_parenthesis(&mut self) {
-        self.depth -= 1;
+    /// Parses braces, whose opening brace is at `start` and was already consumed, into a named capture, a range, or
+    /// the alternatives of a disjunction
+    fn braces(&mut self, start: usize) -> Result<Token, PatternError> {
+        let rest = &self.chars[self.offset..];
+        let inside: String = match rest.iter().position(|&c| c == '}') {
+            Some(end) => rest[..end].iter().collect(),
+            None => String::new(),
+        };
+
+        let is_name = !inside.is_empty()
+            && inside
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_')
+            && !inside.starts_with(|c: char| c.is_ascii_digit());
+        if self.captures && is_name {
+            self.offset += inside.chars().count() + 1;
+            return Ok(Token::AnyChars(Some(inside)));
+        }
 
-        if self.depth == 0 {
-            // closing the subpattern at depth 1: extend subpatterns recursively
-            let mut extended_basic_glob_patterns: Vec<String> = Vec::new();
+        if is_range(&inside) {
+            let values = expand_range(&inside).map_err(|reason| self.error(start, &reason))?;
+            self.offset += inside.chars().count() + 1;
+            return Ok(Token::Braces(
+                values
+                    .iter()
+                    .map(|value| value.chars().map(Token::Literal).collect())
+                    .collect(),
+            ));
+        }
 
-            match self.tokens.pop().unwrap() {
-                ExtendGlobToken::Subpatterns(subpatterns) => {
-                    for subpattern in subpatterns {
-                        extended_basic_glob_patterns
-                            .extend(ExtendedGlobPatternBuilder::from_pattern(&subpattern).build());
-                    }
-                }
-                _ => panic!("Cannot close subpattern when last token is not a subpattern"),
+        let mut alternatives = vec![self.sequence(Context::Braces)?];
+        loop {
+            match self.next() {
+                Some(',') => alternatives.push(self.sequence(Context::Braces)?),
+                Some('}') => return Ok(Token::Braces(alternatives)),
+                _ => return Err(self.error(start, "unclosed '{'")),
             }
+        }
+    }
 
-            self.tokens
-                .push(ExtendGlobToken::Subpatterns(extended_basic_glob_patterns));
-        } else {
-            self.push_subpattern_character('}');
+    /// Parses an extglob operator, which is at `start` and was already consumed along with its opening parenthesis
+    fn extglob(&mut self, start: usize, kind: Extglob) -> Result<Token, PatternError> {
+        self.offset += 1;
+
+        let mut alternatives = vec![self.sequence(Context::Extglob)?];
+        loop {
+            match self.next() {
+                Some('|') => alternatives.push(self.sequence(Context::Extglob)?),
+                Some(')') => break,
+                _ => return Err(self.error(start, "unclosed '('")),
+            }
         }
+
+        return Ok(Token::Extglob {
+            kind,
+            alternatives,
+            source: self.chars[start..self.offset].iter().collect(),
+        });
+    }
+}
+
+/// Parses an extended glob pattern into tokens, without expanding its braces. When `captures`, braces around a bare
+/// name like `{name}` are a named capture which matches like `*`. Errors at the offending character if the pattern's
+/// braces, brackets, or extglob operators aren't closed, if a range like `{1..10}` is invalid, or if stars aren't a
+/// single `*` or a `**` that forms a whole path component
+pub fn parse(pattern: &str, captures: bool) -> Result<Vec<Token>, PatternError> {
+    let mut parser = ExtendedGlobPatternParser {
+        pattern,
+        chars: pattern.chars().collect(),
+        offset: 0,
+        captures,
+    };
+    return parser.sequence(Context::TopLevel);
+}
+
+/// Lazily expands the braces of the `tokens` into each of the basic patterns that they stand for, in order
+pub fn expand(tokens: &[Token]) -> Box<dyn Iterator<Item = String> + '_> {
+    let Some((token, rest)) = tokens.split_first() else {
+        return Box::new(iter::once(String::new()));
+    };
+
+    if let Token::Braces(alternatives) = token {
+        return Box::new(alternatives.iter().flat_map(move |alternative| {
+            return expand(alternative)
+                .flat_map(move |head| expand(rest).map(move |tail| format!("{head}{tail}")));
+        }));
     }
 
-    fn push_subpattern_character(&mut self, c: char) {
-        match self.tokens.last_mut().unwrap() {
-            ExtendGlobToken::Subpatterns(subpatterns) => {
-                subpatterns.last_mut().unwrap().push(c);
+    let head = token.basic();
+    return Box::new(expand(rest).map(move |tail| format!("{head}{tail}")));
+}
+
+/// The basic pattern that the `tokens` expand into when their braces take the alternatives in `choices`, in the order
+/// that the braces are written
+pub fn basic_pattern(tokens: &[Token], choices: &mut dyn Iterator<Item = usize>) -> String {
+    let mut pattern = String::new();
+    for token in tokens {
+        match token {
+            Token::Braces(alternatives) => {
+                let alternative = &alternatives[choices.next().unwrap_or(0)];
+                pattern.push_str(&basic_pattern(alternative, choices));
             }
-            _ => panic!("Cannot push subpattern character when last token is not a subpattern"),
+            _ => pattern.push_str(&token.basic()),
         }
     }
+    return pattern;
+}
+
+/// The literal characters that the `tokens` start with, before any wildcard or braces
+pub fn literal_prefix(tokens: &[Token]) -> String {
+    return tokens
+        .iter()
+        .map_while(|token| match token {
+            Token::Literal(c) => Some(*c),
+            _ => None,
+        })
+        .collect();
 }
 
 #[cfg(test)]
@@ -183,18 +488,109 @@ mod tests {
     #[case("{a,b}{1,2}{!,?}", vec!["a1!", "a2!", "b1!", "b2!", "a1?", "a2?", "b1?", "b2?"])]
     #[case("a{b,{c,d}}", vec!["ab", "ac", "ad"])]
     #[case("{aa{bb,cc,dd{e,f}},why even}.", vec!["why even.", "aabb.", "aacc.", "aadde.", "aaddf."])]
+    #[case("part-{000..003}.parquet", vec!["part-000.parquet", "part-001.parquet", "part-002.parquet", "part-003.parquet"])]
+    #[case("{1..10..3}", vec!["1", "4", "7", "10"])]
+    #[case("{10..1..-3}", vec!["10", "7", "4", "1"])]
+    #[case("{8..12}", vec!["8", "9", "10", "11", "12"])]
+    #[case("{1..010..4}", vec!["001", "005", "009"])]
+    #[case("{-2..1}", vec!["-2", "-1", "0", "1"])]
+    #[case("{-05..5..5}", vec!["-05", "000", "005"])]
+    #[case("{a..e..2}", vec!["a", "c", "e"])]
+    #[case("{C..A}", vec!["C", "B", "A"])]
+    #[case("{3..3}", vec!["3"])]
+    #[case("{1..2}{a..b}", vec!["1a", "1b", "2a", "2b"])]
+    #[case("{x,{1..2}}", vec!["x", "1", "2"])]
+    #[case("{1..2,3}", vec!["1..2", "3"])]
     fn given_extended_glob_pattern_when_extend_glob_pattern_then_converts_into_multiple_basic_patterns(
         #[case] pattern: &str,
         #[case] expected: Vec<&str>,
     ) {
         println!("Glob pattern: {pattern}");
         let actual: std::collections::HashSet<String> =
-            ExtendedGlobPatternBuilder::from_pattern(pattern)
-                .build()
-                .into_iter()
-                .collect();
+            expand(&parse(pattern, false).unwrap()).collect();
         let expected: std::collections::HashSet<String> =
             expected.iter().map(|s| s.to_string()).collect();
         assert_eq!(actual, expected);
     }
+
+    #[rstest]
+    #[case("}", 0, "unmatched '}'")]
+    #[case("src/*.rs}", 8, "unmatched '}'")]
+    #[case("{a,b}}", 5, "unmatched '}'")]
+    #[case("config.{yml", 7, "unclosed '{'")]
+    #[case("{a,{b}", 0, "unclosed '{'")]
+    #[case("{a,b}{c,{d", 8, "unclosed '{'")]
+    #[case("data/{1..}", 5, INVALID_RANGE)]
+    #[case("{a..5}", 0, INVALID_RANGE)]
+    #[case("{aa..c}", 0, INVALID_RANGE)]
+    #[case("{1..2..x}", 0, INVALID_RANGE)]
+    #[case("{1..2..3..4}", 0, INVALID_RANGE)]
+    #[case("x{1..10..0}", 1, "the step of a range can't be 0")]
+    #[case("{a..Z}", 0, "both ends of a letter range must have the same case")]
+    #[case("{x,{1..y}}", 3, INVALID_RANGE)]
+    fn given_invalid_braces_when_extend_glob_pattern_then_err_at_offset(
+        #[case] pattern: &str,
+        #[case] offset: usize,
+        #[case] reason: &str,
+    ) {
+        let error = parse(pattern, false).unwrap_err();
+        assert_eq!(error, PatternError::new(pattern, offset, reason));
+    }
+
+    #[test]
+    fn given_huge_range_when_extend_glob_pattern_then_err_at_limit() {
+        let error = parse("part-{0..4096}.csv", false).unwrap_err();
+
+        assert_eq!(error.offset, 5);
+        assert_eq!(error.reason, "the range expands into more than 4096 values");
+        assert!(parse("part-{1..4096}.csv", false).is_ok());
+    }
+
+    #[test]
+    fn given_many_braces_when_expand_then_expands_lazily_in_order() {
+        let tokens = parse("{1..4000}/{1..4000}/{1..4000}.csv", false).unwrap();
+
+        let first: Vec<String> = expand(&tokens).take(2).collect();
+        assert_eq!(first, vec!["1/1/1.csv", "1/1/2.csv"]);
+    }
+
+    #[rstest]
+    #[case("{dir}/{a,b{c,d}}/*.{rs,toml}", vec![1, 1, 0], "*/bd/*.rs")]
+    #[case("[!.]@(x|{y,z})", vec![], "[!.]@(x|{y,z})")]
+    fn given_choices_when_basic_pattern_then_takes_chosen_alternatives(
+        #[case] pattern: &str,
+        #[case] choices: Vec<usize>,
+        #[case] expected: &str,
+    ) {
+        let tokens = parse(pattern, true).unwrap();
+        assert_eq!(basic_pattern(&tokens, &mut choices.into_iter()), expected);
+    }
+
+    #[rstest]
+    #[case("***", 2, "wildcards are either regular `*` or recursive `**`")]
+    #[case("src/**a", 6, "recursive wildcards must form a single path component")]
+    #[case("a**/b", 0, "recursive wildcards must form a single path component")]
+    #[case("src/[!]", 4, "invalid range pattern")]
+    #[case("src/!(*.test", 4, "unclosed '('")]
+    #[case("@(a|+(b)", 0, "unclosed '('")]
+    #[case("{a,@(b}", 3, "unclosed '('")]
+    fn given_invalid_wildcards_when_parse_then_err_at_offset(
+        #[case] pattern: &str,
+        #[case] offset: usize,
+        #[case] reason: &str,
+    ) {
+        let error = parse(pattern, false).unwrap_err();
+        assert_eq!(error, PatternError::new(pattern, offset, reason));
+    }
+
+    #[rstest]
+    #[case("{src,lib}/**/*.rs")]
+    #[case("@(**|x)")]
+    #[case("a/**(b)")]
+    #[case("src/!(*.test).ts")]
+    #[case("(not an operator)")]
+    #[case("\\@(escaped")]
+    fn given_valid_wildcards_when_parse_then_ok(#[case] pattern: &str) {
+        assert!(parse(pattern, false).is_ok());
+    }
 }
@@ -0,0 +1,295 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use ignore::{Match, WalkBuilder};
+
+use crate::explorers::{os_path, WatchRoot};
+
+/// The matchers for the ignore files in one directory
+#[derive(Debug)]
+struct DirIgnores {
+    /// `.ignore`, which applies to everything inside of the directory
+    ignore: Gitignore,
+
+    /// `.gitignore`, which stops applying outside of the git repository
+    gitignore: Gitignore,
+
+    /// `.git/info/exclude`, which is only in the root of a git repository
+    exclude: Gitignore,
+
+    /// Whether the directory is the root of a git repository, so git's ignore files above it don't apply
+    has_git: bool,
+}
+
+impl DirIgnores {
+    fn read(dir: &Path) -> Self {
+        let build = |file: PathBuf| {
+            if !file.is_file() {
+                return Gitignore::empty();
+            }
+            let mut builder = GitignoreBuilder::new(dir);
+            builder.add(file);
+            return builder.build().unwrap_or_else(|_| Gitignore::empty());
+        };
+
+        return Self {
+            ignore: build(dir.join(".ignore")),
+            gitignore: build(dir.join(".gitignore")),
+            exclude: build(dir.join(".git/info/exclude")),
+            has_git: dir.join(".git").exists(),
+        };
+    }
+}
+
+/// Whether a match ignores the path, or `None` if the ignore file says nothing about it
+fn decide<T>(matched: Match<T>) -> Option<bool> {
+    return match matched {
+        Match::Ignore(_) => Some(true),
+        Match::Whitelist(_) => Some(false),
+        Match::None => None,
+    };
+}
+
+/// Decides whether paths are ignored the same way that `walk` skips them: by the `.ignore`, `.gitignore`, and
+/// `.git/info/exclude` files in every directory containing them, including those above the working directory, and by
+/// the user's global gitignore. The ignore files of each directory are read once, until `clear` is called
+#[derive(Debug)]
+pub struct IgnoreMatcher {
+    /// The working directory, which relative paths are resolved against
+    cwd: PathBuf,
+
+    /// The user's global gitignore, which has the lowest precedence
+    global: Gitignore,
+
+    /// The matchers of each directory that a path was checked in
+    dirs: RefCell<HashMap<PathBuf, Rc<DirIgnores>>>,
+}
+
+impl IgnoreMatcher {
+    /// Resolves relative paths against the working directory `cwd`
+    pub fn new(cwd: PathBuf) -> Self {
+        let (global, _) = GitignoreBuilder::new(&cwd).build_global();
+        return Self {
+            cwd,
+            global,
+            dirs: RefCell::default(),
+        };
+    }
+
+    /// Forgets the ignore files that were read, so changes to them are noticed
+    pub fn clear(&self) {
+        self.dirs.borrow_mut().clear();
+    }
+
+    /// The absolute path, without any `.` components
+    fn absolute(&self, path: &Path) -> PathBuf {
+        return self.cwd.join(path).components().collect();
+    }
+
+    fn dir(&self, dir: &Path) -> Rc<DirIgnores> {
+        let mut dirs = self.dirs.borrow_mut();
+        let ignores = dirs
+            .entry(dir.to_path_buf())
+            .or_insert_with(|| Rc::new(DirIgnores::read(dir)));
+        return Rc::clone(ignores);
+    }
+
+    /// Whether the absolute `path` itself is ignored. Rules in deeper directories take precedence, so they can
+    /// re-include paths ignored higher up, and `.ignore` files take precedence over git's ignore files
+    fn ignores(&self, path: &Path, is_dir: bool) -> bool {
+        let (mut ignore, mut gitignore, mut exclude) = (None, None, None);
+        let mut saw_git = false;
+
+        for dir in path.ancestors().skip(1) {
+            let ignores = self.dir(dir);
+            ignore = ignore.or_else(|| decide(ignores.ignore.matched(path, is_dir)));
+            if !saw_git {
+                gitignore = gitignore.or_else(|| decide(ignores.gitignore.matched(path, is_dir)));
+                exclude = exclude.or_else(|| decide(ignores.exclude.matched(path, is_dir)));
+            }
+            saw_git |= ignores.has_git;
+        }
+
+        return ignore
+            .or(gitignore)
+            .or(exclude)
+            .or_else(|| decide(self.global.matched(path, is_dir)))
+            .unwrap_or(false);
+    }
+
+    /// Whether walking the directory `root` skips `path`: it's inside of a `.git` directory, or it or a directory
+    /// containing it below `root` is ignored
+    pub fn is_ignored(&self, root: &Path, path: &Path) -> bool {
+        if path
+            .components()
+            .any(|component| component.as_os_str() == ".git")
+        {
+            return true;
+        }
+
+        let path = self.absolute(path);
+        let root = self.absolute(root);
+        if !path.starts_with(&root) {
+            return self.ignores(&path, path.is_dir());
+        }
+
+        return path
+            .ancestors()
+            .take_while(|ancestor| *ancestor != root)
+            .any(|ancestor| {
+                let is_dir = ancestor != path || path.is_dir();
+                return self.ignores(ancestor, is_dir);
+            });
+    }
+}
+
+/// Walks the directories within `root`. When `respect_ignore_files`, everything that is ignored by ignore files is
+/// skipped, and ignored directories are never entered
+pub fn walk(root: &WatchRoot, respect_ignore_files: bool) -> impl Iterator<Item = PathBuf> + '_ {
+    let mut builder = WalkBuilder::new(os_path(&root.dir));
+    builder
+        .standard_filters(respect_ignore_files)
+        .hidden(false)
+        .require_git(false)
+        .follow_links(true)
+        .max_depth(root.depth.map(|depth| depth + 1));
+    if respect_ignore_files {
+        builder.filter_entry(|entry| entry.file_name() != ".git");
+    }
+    let walker = builder.build();
+
+    return walker.filter_map(Result::ok).map(|entry| {
+        if root.dir.as_os_str().is_empty() {
+            // keep paths relative to the current working directory without a leading `./`, like their patterns
+            if let Ok(path) = entry.path().strip_prefix(".") {
+                return path.to_path_buf();
+            }
+        }
+        return entry.into_path();
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::fs;
+
+    use tempfile::tempdir_in;
+
+    use super::*;
+    use crate::test_utils::utils::make_files;
+
+    fn make_tree() -> tempfile::TempDir {
+        let tmp = tempdir_in(".").unwrap();
+        let basedir = tmp.path().to_owned();
+        make_files(
+            &basedir,
+            vec![
+                "src/main.rs",
+                "src/generated/mod.rs",
+                "src/generated/keep.rs",
+                "target/debug/build.rs",
+                "notes.swp",
+                ".git/HEAD",
+            ],
+        );
+        fs::write(basedir.join(".gitignore"), "target/\n*.swp\n").unwrap();
+        fs::write(basedir.join("src/.ignore"), "generated/*\n!keep.rs\n").unwrap();
+        return tmp;
+    }
+
+    #[test]
+    fn given_ignore_files_when_is_ignored_then_follows_nested_and_negated_rules() {
+        let tmp = make_tree();
+        let basedir = tmp.path().to_owned();
+        let matcher = IgnoreMatcher::new(std::env::current_dir().unwrap());
+
+        assert!(!matcher.is_ignored(&basedir, &basedir.join("src/main.rs")));
+        assert!(matcher.is_ignored(&basedir, &basedir.join("src/generated/mod.rs")));
+        assert!(!matcher.is_ignored(&basedir, &basedir.join("src/generated/keep.rs")));
+        assert!(matcher.is_ignored(&basedir, &basedir.join("target/debug/build.rs")));
+        assert!(matcher.is_ignored(&basedir, &basedir.join("notes.swp")));
+        assert!(matcher.is_ignored(&basedir, &basedir.join(".git/HEAD")));
+    }
+
+    #[test]
+    fn given_ignore_file_above_cwd_when_is_ignored_then_applies_it_to_relative_paths() {
+        let tmp = tempdir_in(".").unwrap();
+        let basedir = tmp.path().canonicalize().unwrap();
+        make_files(
+            &basedir,
+            vec!["sub/gen/x.rs", "sub/src/main.rs", ".git/HEAD"],
+        );
+        fs::write(basedir.join(".gitignore"), "gen/\n").unwrap();
+        let matcher = IgnoreMatcher::new(basedir.join("sub"));
+
+        assert!(matcher.is_ignored(Path::new(""), Path::new("gen/x.rs")));
+        assert!(!matcher.is_ignored(Path::new(""), Path::new("src/main.rs")));
+    }
+
+    #[test]
+    fn given_walk_root_when_is_ignored_then_agrees_with_walk() {
+        let tmp = make_tree();
+        let basedir = tmp.path().to_owned();
+        let matcher = IgnoreMatcher::new(std::env::current_dir().unwrap());
+
+        for dir in ["", "src", "target/debug"] {
+            let root = WatchRoot {
+                dir: basedir.join(dir),
+                depth: None,
+            };
+            let walked: HashSet<PathBuf> = walk(&root, true).collect();
+
+            for path in walk(&root, false).filter(|path| path != &root.dir) {
+                assert_eq!(
+                    matcher.is_ignored(&root.dir, &path),
+                    !walked.contains(&path),
+                    "{path:?} within {dir:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn given_ignore_files_when_walk_then_skips_ignored_paths() {
+        let tmp = make_tree();
+        let basedir = tmp.path().to_owned();
+        let root = WatchRoot {
+            dir: basedir.clone(),
+            depth: None,
+        };
+
+        let walked: HashSet<PathBuf> = walk(&root, true)
+            .filter(|path| path.is_file())
+            .filter_map(|path| Some(path.strip_prefix(&basedir).ok()?.to_path_buf()))
+            .collect();
+
+        assert_eq!(
+            walked,
+            HashSet::from([
+                PathBuf::from(".gitignore"),
+                PathBuf::from("src/.ignore"),
+                PathBuf::from("src/main.rs"),
+                PathBuf::from("src/generated/keep.rs"),
+            ])
+        );
+    }
+
+    #[test]
+    fn given_limited_depth_when_walk_then_does_not_descend_further() {
+        let tmp = make_tree();
+        let basedir = tmp.path().to_owned();
+        let root = WatchRoot {
+            dir: basedir.join("src"),
+            depth: Some(0),
+        };
+
+        let walked: HashSet<PathBuf> = walk(&root, true).collect();
+
+        assert!(walked.contains(&basedir.join("src/main.rs")));
+        assert!(!walked.contains(&basedir.join("src/generated/keep.rs")));
+    }
+}
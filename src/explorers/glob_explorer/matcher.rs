@@ -0,0 +1,326 @@
+use std::path::Path;
+
+use crate::explorers::glob_explorer::extend::{Extglob, Token};
+use crate::explorers::glob_explorer::pattern::PathCaptures;
+
+/// Something that was decided while matching, which is undone when backtracking
+#[derive(Debug, Clone, PartialEq)]
+enum Event {
+    /// What a wildcard matched, and its name if it's a named capture
+    Capture(Option<String>, String),
+
+    /// Which alternative of braces matched
+    Choice(usize),
+}
+
+/// The tokens which are left to match after the current ones, so braces can continue with what comes after them
+/// without copying it
+enum Rest<'a> {
+    End,
+    Then(&'a [Token], &'a Rest<'a>),
+}
+
+/// How a pattern matched a path
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PathMatch {
+    pub captures: PathCaptures,
+
+    /// Which alternative of each of the braces that were matched was taken, in the order the braces are written
+    pub choices: Vec<usize>,
+}
+
+fn capture(name: Option<String>, text: &[char]) -> Event {
+    return Event::Capture(name, text.iter().collect());
+}
+
+/// The tokens after the path separator that comes next, if the next token is a separator
+fn after_separator<'a>(
+    tokens: &'a [Token],
+    rest: &'a Rest<'a>,
+) -> Option<(&'a [Token], &'a Rest<'a>)> {
+    return match (tokens.split_first(), rest) {
+        (Some((Token::Literal('/'), tokens)), _) => Some((tokens, rest)),
+        (Some(_), _) | (None, Rest::End) => None,
+        (None, Rest::Then(tokens, rest)) => after_separator(tokens, rest),
+    };
+}
+
+/// Whether the `tokens`, followed by the `rest`, match all of the `text`. What each wildcard matched and which
+/// alternative each of the braces took is pushed onto `events`, which is left unchanged when they don't match.
+/// Wildcards match as much as they can, like a greedy regex, and only `**` matches a path separator
+fn match_tokens(tokens: &[Token], rest: &Rest, text: &[char], events: &mut Vec<Event>) -> bool {
+    let Some((token, tokens)) = tokens.split_first() else {
+        return match rest {
+            Rest::End => text.is_empty(),
+            Rest::Then(tokens, rest) => match_tokens(tokens, rest, text, events),
+        };
+    };
+
+    let before = events.len();
+    let attempt = |event: Option<Event>, length: usize, events: &mut Vec<Event>| {
+        events.extend(event);
+        if match_tokens(tokens, rest, &text[length..], events) {
+            return true;
+        }
+        events.truncate(before);
+        return false;
+    };
+
+    // the wildcards of a single component can't match past the next separator
+    let component = text.iter().position(|&c| c == '/').unwrap_or(text.len());
+    let in_class = |negated: bool, ranges: &[(char, char)]| {
+        return text[..component].first().is_some_and(|c| {
+            return ranges.iter().any(|(start, end)| start <= c && c <= end) != negated;
+        });
+    };
+
+    return match token {
+        Token::Literal(c) => text.first() == Some(c) && attempt(None, 1, events),
+        Token::AnyChar => component > 0 && attempt(Some(capture(None, &text[..1])), 1, events),
+        Token::Class {
+            negated, ranges, ..
+        } => in_class(*negated, ranges) && attempt(Some(capture(None, &text[..1])), 1, events),
+        Token::AnyChars(name) => (0..=component).rev().any(|length| {
+            return attempt(Some(capture(name.clone(), &text[..length])), length, events);
+        }),
+        Token::Extglob {
+            kind, alternatives, ..
+        } => {
+            let mut extglob = ExtglobMatch::new(*kind, alternatives, &text[..component]);
+            (0..=component).rev().any(|length| {
+                return extglob.matches(length)
+                    && attempt(Some(capture(None, &text[..length])), length, events);
+            })
+        }
+        Token::Recursive => {
+            let matched = (0..=text.len()).rev().any(|length| {
+                return attempt(Some(capture(None, &text[..length])), length, events);
+            });
+            // the recursive wildcard and the separator after it can match no directories at all, e.g. `src/**/*.rs`
+            // matches `src/main.rs`
+            matched
+                || after_separator(tokens, rest).is_some_and(|(tokens, rest)| {
+                    events.push(capture(None, &[]));
+                    if match_tokens(tokens, rest, text, events) {
+                        return true;
+                    }
+                    events.truncate(before);
+                    return false;
+                })
+        }
+        Token::Braces(alternatives) => {
+            let rest = Rest::Then(tokens, rest);
+            alternatives.iter().enumerate().any(|(i, alternative)| {
+                events.push(Event::Choice(i));
+                if match_tokens(alternative, &rest, text, events) {
+                    return true;
+                }
+                events.truncate(before);
+                return false;
+            })
+        }
+    };
+}
+
+/// Matches an extglob operator against the start of a path component's `text`. Whether the alternatives match each
+/// part of the text is remembered, since the operator is tried at every length and repetitions like `*(a|aa)` try the
+/// same parts many times. This keeps long names from taking exponential time
+struct ExtglobMatch<'a> {
+    kind: Extglob,
+    alternatives: &'a [Vec<Token>],
+    text: &'a [char],
+
+    /// Whether one of the alternatives matches `text[start..end]`, at `start * (text.len() + 1) + end`
+    alternative: Vec<Option<bool>>,
+
+    /// Whether the alternatives repeated any number of times match `text[start..end]`, indexed like `alternative`
+    repeated: Vec<Option<bool>>,
+}
+
+impl<'a> ExtglobMatch<'a> {
+    fn new(kind: Extglob, alternatives: &'a [Vec<Token>], text: &'a [char]) -> Self {
+        let parts = (text.len() + 1) * (text.len() + 1);
+        return Self {
+            kind,
+            alternatives,
+            text,
+            alternative: vec![None; parts],
+            repeated: vec![None; parts],
+        };
+    }
+
+    /// Whether any of the alternatives match all of `text[start..end]`
+    fn match_alternative(&mut self, start: usize, end: usize) -> bool {
+        let index = start * (self.text.len() + 1) + end;
+        if let Some(matched) = self.alternative[index] {
+            return matched;
+        }
+
+        // wildcards inside of an extglob operator aren't captured on their own
+        let text = &self.text[start..end];
+        let matched = self
+            .alternatives
+            .iter()
+            .any(|alternative| match_tokens(alternative, &Rest::End, text, &mut Vec::new()));
+        self.alternative[index] = Some(matched);
+        return matched;
+    }
+
+    /// Whether the alternatives match all of `text[start..end]` when repeated any number of times, including none
+    fn match_repeated(&mut self, start: usize, end: usize) -> bool {
+        if start == end {
+            return true;
+        }
+        let index = start * (self.text.len() + 1) + end;
+        if let Some(matched) = self.repeated[index] {
+            return matched;
+        }
+
+        let matched = (start + 1..=end)
+            .any(|split| self.match_alternative(start, split) && self.match_repeated(split, end));
+        self.repeated[index] = Some(matched);
+        return matched;
+    }
+
+    /// Whether the operator matches all of the first `length` characters of the text
+    fn matches(&mut self, length: usize) -> bool {
+        return match self.kind {
+            Extglob::Not => !self.match_alternative(0, length),
+            Extglob::One => self.match_alternative(0, length),
+            Extglob::ZeroOrOne => length == 0 || self.match_alternative(0, length),
+            Extglob::ZeroOrMore => self.match_repeated(0, length),
+            Extglob::OneOrMore => (0..=length).any(|split| {
+                self.match_alternative(0, split) && self.match_repeated(split, length)
+            }),
+        };
+    }
+}
+
+/// Matches the whole `path` against the tokens of an extended glob pattern at once, trying each alternative of its
+/// braces in place rather than expanding them. Returns what each wildcard matched and which alternatives were taken,
+/// or `None` if it doesn't match. An extglob operator is a single wildcard, which captures everything that it matched
+pub fn match_path(tokens: &[Token], path: &Path) -> Option<PathMatch> {
+    let text: Vec<char> = path.to_string_lossy().chars().collect();
+    let mut events = Vec::new();
+    if !match_tokens(tokens, &Rest::End, &text, &mut events) {
+        return None;
+    }
+
+    let mut matched = PathMatch::default();
+    for event in events {
+        match event {
+            Event::Capture(name, value) => {
+                if let Some(name) = name {
+                    // the first capture with the same name wins
+                    matched
+                        .captures
+                        .named
+                        .entry(name)
+                        .or_insert_with(|| value.clone());
+                }
+                matched.captures.wildcards.push(value);
+            }
+            Event::Choice(choice) => matched.choices.push(choice),
+        }
+    }
+    return Some(matched);
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+    use crate::explorers::glob_explorer::extend::parse;
+
+    #[rstest]
+    #[case("src/!(*.test).ts", "src/main.ts", true)]
+    #[case("src/!(*.test).ts", "src/main.test.ts", false)]
+    #[case("src/!(*.test).ts", "src/nested/main.ts", false)]
+    #[case("!(x*)*", "xy", true)]
+    #[case("!(target|node_modules)/*.js", "src/index.js", true)]
+    #[case("!(target|node_modules)/*.js", "target/index.js", false)]
+    #[case("@(config|settings).yml", "settings.yml", true)]
+    #[case("@(config|settings).yml", "other.yml", false)]
+    #[case("file?(s).txt", "file.txt", true)]
+    #[case("file?(s).txt", "files.txt", true)]
+    #[case("file?(s).txt", "filess.txt", false)]
+    #[case("*(ab).md", ".md", true)]
+    #[case("*(ab).md", "ababab.md", true)]
+    #[case("*(ab).md", "aba.md", false)]
+    #[case("part-+([0-9]).csv", "part-42.csv", true)]
+    #[case("part-+([0-9]).csv", "part-.csv", false)]
+    #[case("part-+([0-9]).csv", "part-4a.csv", false)]
+    #[case("@(*.rs|+(x))", "xxx", true)]
+    #[case("src/**/!(mod).rs", "src/a/b/lib.rs", true)]
+    #[case("src/**/!(mod).rs", "src/a/b/mod.rs", false)]
+    #[case("src/**/!(mod).rs", "src/lib.rs", true)]
+    #[case("logs/**", "logs", false)]
+    #[case("[!a]@(b)", "cb", true)]
+    #[case("[!a]@(b)", "ab", false)]
+    #[case("[(/]@(b)", "(b", true)]
+    #[case("\\@(b)!(c)", "\\@(b)d", true)]
+    fn given_extglob_pattern_when_matches_then_follows_operators(
+        #[case] pattern: &str,
+        #[case] path: &str,
+        #[case] expected: bool,
+    ) {
+        let tokens = parse(pattern, false).unwrap();
+        assert_eq!(match_path(&tokens, Path::new(path)).is_some(), expected);
+    }
+
+    #[rstest]
+    #[case("*(a|aa)c", "b", false)]
+    #[case("*(a|aa)c", "c", true)]
+    #[case("+(a|aa)", "b", false)]
+    #[case("!(*(a|aa))", "b", true)]
+    fn given_repetition_and_long_name_when_matches_then_does_not_backtrack_exponentially(
+        #[case] pattern: &str,
+        #[case] suffix: &str,
+        #[case] expected: bool,
+    ) {
+        let tokens = parse(pattern, false).unwrap();
+        let name = format!("{}{suffix}", "a".repeat(200));
+
+        assert_eq!(match_path(&tokens, Path::new(&name)).is_some(), expected);
+    }
+
+    #[test]
+    fn given_extglob_pattern_when_captures_then_operators_are_single_wildcards() {
+        let tokens = parse("{dir}/**/!(*.test).@(ts|tsx)", true).unwrap();
+
+        let captures = match_path(&tokens, Path::new("web/src/app/main.tsx"))
+            .unwrap()
+            .captures;
+        assert_eq!(captures.wildcards, vec!["web", "src/app", "main", "tsx"]);
+        assert_eq!(captures.get("dir"), Some("web"));
+    }
+
+    #[rstest]
+    #[case("{src,lib}/{bin/*,main}.rs", "lib/bin/cli.rs", vec![1, 0], vec!["cli"])]
+    #[case("{src,lib}/{bin/*,main}.rs", "src/main.rs", vec![0, 1], vec![])]
+    #[case("{a,{b,c}d}/**/*.rs", "cd/x.rs", vec![1, 1], vec!["", "x"])]
+    #[case("{**/,}*.md", "docs/guide/intro.md", vec![0], vec!["docs/guide", "intro"])]
+    #[case("{*,a}*b", "xb", vec![0], vec!["x", ""])]
+    fn given_braces_when_match_path_then_chooses_alternatives_in_place(
+        #[case] pattern: &str,
+        #[case] path: &str,
+        #[case] choices: Vec<usize>,
+        #[case] wildcards: Vec<&str>,
+    ) {
+        let tokens = parse(pattern, false).unwrap();
+
+        let matched = match_path(&tokens, Path::new(path)).unwrap();
+        assert_eq!(matched.choices, choices);
+        assert_eq!(matched.captures.wildcards, wildcards);
+    }
+
+    #[rstest]
+    #[case("{src,lib}/*.rs", "src/nested/main.rs")]
+    #[case("{*,a}*b", "x/b")]
+    #[case("part-{1..3}.csv", "part-4.csv")]
+    fn given_braces_when_path_does_not_match_then_none(#[case] pattern: &str, #[case] path: &str) {
+        let tokens = parse(pattern, false).unwrap();
+        assert_eq!(match_path(&tokens, Path::new(path)), None);
+    }
+}
@@ -1,4 +1,8 @@
 mod explorer;
 mod extend;
+pub mod ignore_files;
+mod matcher;
+mod pattern;
 
 pub use explorer::GlobExplorer;
+pub use pattern::{ExtendedGlobPattern, PathCaptures};
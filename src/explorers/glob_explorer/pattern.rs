@@ -0,0 +1,227 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::explorers::glob_explorer::extend::{self, Token};
+use crate::explorers::glob_explorer::matcher;
+use crate::explorers::PatternError;
+
+/// The parts of a path that were matched by the wildcards of a pattern
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PathCaptures {
+    /// What each wildcard matched, in order. `**` matches directories without their trailing slash
+    pub wildcards: Vec<String>,
+
+    /// What each named capture like `{name}` matched
+    pub named: HashMap<String, String>,
+}
+
+impl PathCaptures {
+    /// What the named capture matched, or what the numbered wildcard matched when counting from 1
+    pub fn get(&self, name: &str) -> Option<&str> {
+        if let Ok(number) = name.parse::<usize>() {
+            return self
+                .wildcards
+                .get(number.checked_sub(1)?)
+                .map(String::as_str);
+        }
+        return self.named.get(name).map(String::as_str);
+    }
+}
+
+/// An extended glob pattern, compiled into a single matcher. Its braces are matched in place, and only expanded into
+/// basic patterns lazily, when they're asked for
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExtendedGlobPattern {
+    /// The extended pattern, as it was given
+    source: String,
+
+    tokens: Vec<Token>,
+}
+
+impl ExtendedGlobPattern {
+    /// Compiles an extended glob pattern. Errors if its braces, brackets, or extglob operators aren't closed, or if
+    /// its wildcards are invalid
+    pub fn new(arg: &str) -> Result<Self, PatternError> {
+        return Ok(Self {
+            source: arg.to_string(),
+            tokens: extend::parse(arg, false)?,
+        });
+    }
+
+    /// Like `::new`, but braces around a bare name like `{name}` are a named capture which matches like `*`, rather
+    /// than a disjunction with a single subpattern
+    pub fn with_captures(arg: &str) -> Result<Self, PatternError> {
+        return Ok(Self {
+            source: arg.to_string(),
+            tokens: extend::parse(arg, true)?,
+        });
+    }
+
+    /// The extended pattern, as it was given
+    pub fn as_str(&self) -> &str {
+        return &self.source;
+    }
+
+    /// Lazily expands the braces into the basic patterns that the extended pattern stands for, with named captures
+    /// written as `*`
+    pub fn patterns(&self) -> impl Iterator<Item = String> + '_ {
+        return extend::expand(&self.tokens);
+    }
+
+    /// The literal characters that the pattern starts with, before any wildcard or braces
+    pub fn literal_prefix(&self) -> String {
+        return extend::literal_prefix(&self.tokens);
+    }
+
+    /// Whether the pattern matches the `path`
+    pub fn matches(&self, path: &Path) -> bool {
+        return matcher::match_path(&self.tokens, path).is_some();
+    }
+
+    /// The basic pattern that the braces expand into which matches the `path`, or `None` if it doesn't match
+    pub fn matching_pattern(&self, path: &Path) -> Option<String> {
+        let matched = matcher::match_path(&self.tokens, path)?;
+        return Some(extend::basic_pattern(
+            &self.tokens,
+            &mut matched.choices.into_iter(),
+        ));
+    }
+
+    /// What each wildcard matched in the `path`, or `None` if it doesn't match. Only the wildcards of the braces'
+    /// alternatives that matched are counted
+    pub fn captures(&self, path: &Path) -> Option<PathCaptures> {
+        return matcher::match_path(&self.tokens, path).map(|matched| matched.captures);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    #[rstest]
+    #[case("src/*.rs", "src/main.rs", true)]
+    #[case("src/*.rs", "src/nested/main.rs", false)]
+    #[case("src/**/*.rs", "src/nested/main.rs", true)]
+    #[case("src/**/*.rs", "src/main.rs", true)]
+    #[case("config.{yml,yaml}", "config.yaml", true)]
+    #[case("config.{yml,yaml}", "config.json", false)]
+    fn given_extended_glob_pattern_when_matches_then_wildcards_do_not_cross_separators(
+        #[case] pattern: &str,
+        #[case] path: &str,
+        #[case] expected: bool,
+    ) {
+        let pattern = ExtendedGlobPattern::new(pattern).unwrap();
+        assert_eq!(pattern.matches(Path::new(path)), expected);
+    }
+
+    #[test]
+    fn given_braces_when_new_then_expands_into_basic_patterns() {
+        let pattern = ExtendedGlobPattern::new("*.{yml,yaml}").unwrap();
+
+        let basic_patterns: Vec<String> = pattern.patterns().collect();
+        assert_eq!(basic_patterns, vec!["*.yml", "*.yaml"]);
+    }
+
+    #[rstest]
+    #[case("[", 0, "invalid range pattern")]
+    #[case("src/[a-z.rs", 4, "invalid range pattern")]
+    #[case("src/**a", 6, "recursive wildcards must form a single path component")]
+    #[case("src/*.rs}", 8, "unmatched '}'")]
+    #[case("src/{a,b", 4, "unclosed '{'")]
+    fn given_invalid_pattern_when_new_then_err_at_offset(
+        #[case] pattern: &str,
+        #[case] offset: usize,
+        #[case] reason: &str,
+    ) {
+        let error = ExtendedGlobPattern::new(pattern).unwrap_err();
+        assert_eq!(error, PatternError::new(pattern, offset, reason));
+    }
+
+    #[test]
+    fn given_braces_joining_wildcards_when_new_then_matches_within_component() {
+        let pattern = ExtendedGlobPattern::new("{*,a}*b").unwrap();
+
+        assert!(pattern.matches(Path::new("xb")));
+        assert!(pattern.matches(Path::new("ab")));
+        assert!(!pattern.matches(Path::new("x/b")));
+    }
+
+    #[test]
+    fn given_capture_before_error_when_with_captures_then_offset_counts_capture() {
+        let error = ExtendedGlobPattern::with_captures("{dir}/{name}}.md").unwrap_err();
+
+        assert_eq!(error.offset, 12);
+    }
+
+    #[rstest]
+    #[case("src/{name}.md", "src/intro.md", vec!["intro"], vec![("name", "intro")])]
+    #[case("{dir}/{name}.{ext}", "docs/a.b.md", vec!["docs", "a.b", "md"], vec![("dir", "docs"), ("ext", "md")])]
+    #[case("src/**/*.rs", "src/a/b/main.rs", vec!["a/b", "main"], vec![])]
+    #[case("src/**/*.rs", "src/main.rs", vec!["", "main"], vec![])]
+    #[case("part-??.[a-c]sv", "part-07.csv", vec!["0", "7", "c"], vec![])]
+    #[case("[!.]*.{yml,yaml}", "config.yaml", vec!["c", "onfig"], vec![])]
+    #[case("logs/**", "logs/2024/01.log", vec!["2024/01.log"], vec![])]
+    fn given_pattern_with_captures_when_captures_then_finds_what_each_wildcard_matched(
+        #[case] pattern: &str,
+        #[case] path: &str,
+        #[case] wildcards: Vec<&str>,
+        #[case] named: Vec<(&str, &str)>,
+    ) {
+        let pattern = ExtendedGlobPattern::with_captures(pattern).unwrap();
+        let captures = pattern.captures(Path::new(path)).unwrap();
+
+        assert_eq!(captures.wildcards, wildcards);
+        for (name, value) in named {
+            assert_eq!(captures.get(name), Some(value));
+        }
+    }
+
+    #[test]
+    fn given_captures_when_get_then_numbers_count_from_one() {
+        let pattern = ExtendedGlobPattern::with_captures("{section}/*.md").unwrap();
+        let captures = pattern.captures(Path::new("guide/intro.md")).unwrap();
+
+        assert_eq!(captures.get("section"), Some("guide"));
+        assert_eq!(captures.get("1"), Some("guide"));
+        assert_eq!(captures.get("2"), Some("intro"));
+        assert_eq!(captures.get("0"), None);
+        assert_eq!(captures.get("3"), None);
+        assert_eq!(captures.get("missing"), None);
+    }
+
+    #[rstest]
+    #[case("src/{name}.md", "src/notes.txt")]
+    #[case("src/{name}.md", "src/nested/notes.md")]
+    fn given_pattern_with_captures_when_path_does_not_match_then_no_captures(
+        #[case] pattern: &str,
+        #[case] path: &str,
+    ) {
+        let pattern = ExtendedGlobPattern::with_captures(pattern).unwrap();
+        assert!(!pattern.matches(Path::new(path)));
+        assert_eq!(pattern.captures(Path::new(path)), None);
+    }
+
+    #[rstest]
+    #[case("config.{yml}", "config.yml", true)]
+    #[case("config.\\{yml\\}", "config.yml", false)]
+    #[case("{a,{name}}.md", "anything.md", true)]
+    #[case("{1}.md", "1.md", true)]
+    fn given_braces_around_bare_name_when_with_captures_then_matches_like_star(
+        #[case] pattern: &str,
+        #[case] path: &str,
+        #[case] expected: bool,
+    ) {
+        let pattern = ExtendedGlobPattern::with_captures(pattern).unwrap();
+        assert_eq!(pattern.matches(Path::new(path)), expected);
+    }
+
+    #[test]
+    fn given_braces_around_bare_name_when_new_then_still_expands_braces() {
+        let pattern = ExtendedGlobPattern::new("config.{yml}").unwrap();
+
+        assert!(pattern.matches(Path::new("config.yml")));
+        assert!(!pattern.matches(Path::new("config.yaml")));
+    }
+}
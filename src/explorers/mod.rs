@@ -1,17 +1,208 @@
 mod exact_explorer;
 mod glob_explorer;
+mod variables;
 
 pub use exact_explorer::ExactExplorer;
-pub use glob_explorer::GlobExplorer;
+pub use glob_explorer::{ignore_files, ExtendedGlobPattern, GlobExplorer, PathCaptures};
+
+use std::fmt::Display;
+use std::path::{Component, Path, PathBuf};
 
 use crate::watched_fs::WatchedFS;
 
+pub use variables::expand_home_and_variables;
+
+/// The path to give the operating system for a directory. The current working directory is tracked as an empty path
+/// so that paths joined onto it look like the relative patterns that find them, e.g. `src/main.rs`
+pub fn os_path(dir: &Path) -> &Path {
+    if dir.as_os_str().is_empty() {
+        return Path::new(".");
+    }
+    return dir;
+}
+
+/// Why a path or pattern that was given to an explorer is invalid
+#[derive(Debug, Clone, PartialEq)]
+pub struct PatternError {
+    /// The path or pattern, as it was given
+    pub pattern: String,
+
+    /// How many characters into the pattern the problem is
+    pub offset: usize,
+
+    /// What the problem is
+    pub reason: String,
+}
+
+impl PatternError {
+    pub fn new(pattern: &str, offset: usize, reason: &str) -> Self {
+        return Self {
+            pattern: pattern.to_string(),
+            offset,
+            reason: reason.to_string(),
+        };
+    }
+}
+
+impl Display for PatternError {
+    /// Describes the problem, and then shows the pattern with a caret pointing at where the problem is
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        return write!(
+            f,
+            "Invalid pattern '{}': {}\n\n    {}\n    {}^",
+            self.pattern,
+            self.reason,
+            self.pattern,
+            " ".repeat(self.offset)
+        );
+    }
+}
+
+/// A directory that must be watched for file system events to notice every path an explorer could find
+#[derive(Debug, Clone, PartialEq)]
+pub struct WatchRoot {
+    /// The deepest directory that contains every path the explorer could find
+    pub dir: PathBuf,
+
+    /// How many levels of subdirectories below `dir` must also be watched. `None` means unlimited
+    pub depth: Option<usize>,
+}
+
+impl WatchRoot {
+    /// Whether `dir` must be watched. This includes the ancestors of the root directory, since they are needed to
+    /// notice the root directory being created
+    pub fn contains(&self, dir: &Path) -> bool {
+        if self.dir.starts_with(dir) {
+            return true;
+        }
+
+        if let Ok(relative) = dir.strip_prefix(&self.dir) {
+            let depth = relative.components().count();
+            return self.depth.is_none_or(|max_depth| depth <= max_depth);
+        }
+
+        return false;
+    }
+
+    /// Whether walking this root also walks every path that walking the `other` root would
+    pub fn covers(&self, other: &WatchRoot) -> bool {
+        if self.dir.is_absolute() != other.dir.is_absolute() {
+            return false;
+        }
+        let Ok(relative) = other.dir.strip_prefix(&self.dir) else {
+            return false;
+        };
+        if relative
+            .components()
+            .any(|component| !matches!(component, Component::Normal(_)))
+        {
+            return false;
+        }
+
+        let levels = relative.components().count();
+        return match (self.depth, other.depth) {
+            (None, _) => true,
+            (Some(_), None) => false,
+            (Some(depth), Some(other_depth)) => levels + other_depth <= depth,
+        };
+    }
+}
+
 pub trait Explorer {
-    /// Construct an instance of a particular file system explorer given the cli argument
-    fn from_cli_arg(arg: &str) -> Self
+    /// Construct an instance of a particular file system explorer given the cli argument, after expanding `~` and
+    /// environment variables in it, or describe why the argument is invalid
+    fn from_cli_arg(arg: &str) -> Result<Self, PatternError>
     where
         Self: Sized;
 
     /// Explore the file system for file path(s) matching the pattern
     fn explore(&self, watched_fs: &mut WatchedFS);
+
+    /// Whether the given path would be found by exploring the file system
+    fn matches(&self, path: &Path) -> bool;
+
+    /// The directories to watch for file system events in order to notice changes to any matching path
+    fn roots(&self) -> Vec<WatchRoot>;
+
+    /// The path or pattern which the explorer looks for, as it was given once `~` and environment variables were
+    /// expanded
+    fn pattern(&self) -> String;
+
+    /// The basic pattern that the given path matches, after any braces in the pattern are expanded, or `None` if the
+    /// path doesn't match
+    fn matching_pattern(&self, path: &Path) -> Option<String>;
+
+    /// What each wildcard of the pattern matched in the given path, or `None` if the path doesn't match
+    fn captures(&self, path: &Path) -> Option<PathCaptures>;
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    #[rstest]
+    #[case("src", Some(0), "src", true)]
+    #[case("src", Some(0), "", true)]
+    #[case("src/nested", Some(0), "src", true)]
+    #[case("src", Some(0), "src/nested", false)]
+    #[case("src", Some(1), "src/nested", true)]
+    #[case("src", Some(1), "src/nested/deeply", false)]
+    #[case("src", None, "src/nested/deeply", true)]
+    #[case("src", None, "target", false)]
+    #[case("", Some(0), "", true)]
+    #[case("", Some(0), "src", false)]
+    #[case("/etc/app", None, "/", true)]
+    #[case("/etc/app", None, "/etc/other", false)]
+    fn given_watch_root_when_contains_then_includes_ancestors_and_subdirectories_up_to_depth(
+        #[case] root_dir: &str,
+        #[case] depth: Option<usize>,
+        #[case] dir: &str,
+        #[case] expected: bool,
+    ) {
+        let root = WatchRoot {
+            dir: PathBuf::from(root_dir),
+            depth,
+        };
+        assert_eq!(root.contains(Path::new(dir)), expected);
+    }
+
+    #[rstest]
+    #[case("src", Some(0), "src", Some(0), true)]
+    #[case("src", Some(1), "src/bin", Some(0), true)]
+    #[case("src", Some(0), "src/bin", Some(0), false)]
+    #[case("src", None, "src/bin", None, true)]
+    #[case("src", Some(3), "src/bin", None, false)]
+    #[case("", None, "src", None, true)]
+    #[case("", None, "../src", None, false)]
+    #[case("", None, "/etc", None, false)]
+    #[case("src/bin", None, "src", Some(0), false)]
+    fn given_watch_roots_when_covers_then_compares_directories_and_depths(
+        #[case] root_dir: &str,
+        #[case] depth: Option<usize>,
+        #[case] other_dir: &str,
+        #[case] other_depth: Option<usize>,
+        #[case] expected: bool,
+    ) {
+        let root = WatchRoot {
+            dir: PathBuf::from(root_dir),
+            depth,
+        };
+        let other = WatchRoot {
+            dir: PathBuf::from(other_dir),
+            depth: other_depth,
+        };
+        assert_eq!(root.covers(&other), expected);
+    }
+
+    #[test]
+    fn given_pattern_error_when_display_then_points_at_offset() {
+        let error = PatternError::new("src/*.rs}", 8, "unmatched '}'");
+
+        assert_eq!(
+            error.to_string(),
+            "Invalid pattern 'src/*.rs}': unmatched '}'\n\n    src/*.rs}\n            ^"
+        );
+    }
 }
@@ -0,0 +1,291 @@
+use std::env;
+
+use crate::explorers::PatternError;
+
+/// Describes why the inside of `${...}` is invalid
+const INVALID_EXPANSION: &str = "invalid variable, expected ${NAME} or ${NAME:-DEFAULT}";
+
+/// Whether `name` can be the name of an environment variable
+fn is_name(name: &str) -> bool {
+    return name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+}
+
+/// The value of the environment variable, or `None` if it isn't set
+fn variable(name: &str) -> Option<String> {
+    return env::var_os(name).map(|value| value.to_string_lossy().to_string());
+}
+
+/// The home directory of the user with the given name, or `None` if there's no such user
+#[cfg(unix)]
+fn user_home(name: &str) -> Option<String> {
+    let name = std::ffi::CString::new(name).ok()?;
+    let mut buffer: Vec<libc::c_char> = vec![0; 1024];
+
+    loop {
+        // SAFETY: every field of `passwd` is an integer or a pointer, which may be zero
+        let mut passwd: libc::passwd = unsafe { std::mem::zeroed() };
+        let mut result: *mut libc::passwd = std::ptr::null_mut();
+        // SAFETY: every pointer is valid for the duration of the call, and `buffer` is as long as the length given
+        let status = unsafe {
+            libc::getpwnam_r(
+                name.as_ptr(),
+                &mut passwd,
+                buffer.as_mut_ptr(),
+                buffer.len(),
+                &mut result,
+            )
+        };
+
+        if status == libc::ERANGE {
+            buffer.resize(buffer.len() * 2, 0);
+            continue;
+        }
+        if status != 0 || result.is_null() {
+            return None;
+        }
+        // SAFETY: on success, `pw_dir` points to a nul-terminated string inside of `buffer`
+        let home = unsafe { std::ffi::CStr::from_ptr(passwd.pw_dir) };
+        return Some(home.to_string_lossy().to_string());
+    }
+}
+
+/// Other users' home directories can't be looked up where there are no user databases
+#[cfg(not(unix))]
+fn user_home(_name: &str) -> Option<String> {
+    return None;
+}
+
+/// The index of the `}` which closes braces whose inside starts at `start`, skipping over nested braces and escapes
+fn closing_brace(chars: &[char], start: usize) -> Option<usize> {
+    let mut depth = 0;
+    let mut i = start;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => i += 1,
+            '{' => depth += 1,
+            '}' if depth == 0 => return Some(i),
+            '}' => depth -= 1,
+            _ => {}
+        }
+        i += 1;
+    }
+    return None;
+}
+
+/// Expands the variables in `chars`, which are `offset` characters into the `arg`, with the values from `variable`
+fn expand_variables(
+    arg: &str,
+    chars: &[char],
+    offset: usize,
+    variable: &dyn Fn(&str) -> Option<String>,
+) -> Result<String, PatternError> {
+    let error = |i: usize, reason: &str| PatternError::new(arg, offset + i, reason);
+    let unset = |i: usize, name: &str| {
+        return error(i, &format!("the environment variable '{name}' isn't set"));
+    };
+
+    let mut expanded = String::new();
+    let mut i = 0;
+    while i < chars.len() {
+        match (chars[i], chars.get(i + 1)) {
+            ('\\', Some(&escaped)) => {
+                // the backslash is only removed where it keeps a `$` or `~` literal, since it's an escape in globs too
+                if escaped != '$' && escaped != '~' {
+                    expanded.push('\\');
+                }
+                expanded.push(escaped);
+                i += 2;
+            }
+            ('$', Some('{')) => {
+                let end = closing_brace(chars, i + 2).ok_or_else(|| error(i, "unclosed '${'"))?;
+                let inside: String = chars[i + 2..end].iter().collect();
+                let (name, default) = match inside.split_once(":-") {
+                    Some((name, default)) => (name, Some(default)),
+                    None => (inside.as_str(), None),
+                };
+                if !is_name(name) {
+                    return Err(error(i, INVALID_EXPANSION));
+                }
+
+                match (variable(name), default) {
+                    (Some(value), None) => expanded.push_str(&value),
+                    (Some(value), Some(_)) if !value.is_empty() => expanded.push_str(&value),
+                    (_, Some(_)) => {
+                        // like in a shell, the default may use other variables
+                        let start = i + 2 + name.len() + 2;
+                        expanded.push_str(&expand_variables(
+                            arg,
+                            &chars[start..end],
+                            offset + start,
+                            variable,
+                        )?);
+                    }
+                    (None, None) => return Err(unset(i, name)),
+                }
+                i = end + 1;
+            }
+            ('$', Some(&c)) if c.is_ascii_alphabetic() || c == '_' => {
+                let end = chars[i + 1..]
+                    .iter()
+                    .position(|&c| !(c.is_ascii_alphanumeric() || c == '_'))
+                    .map_or(chars.len(), |length| i + 1 + length);
+                let name: String = chars[i + 1..end].iter().collect();
+                expanded.push_str(&variable(&name).ok_or_else(|| unset(i, &name))?);
+                i = end;
+            }
+            (c, _) => {
+                expanded.push(c);
+                i += 1;
+            }
+        }
+    }
+
+    return Ok(expanded);
+}
+
+/// Expands `~`, `~user`, `$VAR`, `${VAR}`, and `${VAR:-default}` in a path or pattern like a shell would, since no
+/// shell has when jfswatch is started by a service manager or from a config file. `~` only expands at the start, and
+/// a default is used when the variable isn't set or is empty. A backslash before a `$` or `~` keeps it literal.
+/// Errors at the offending character if a variable isn't set, or if a user doesn't exist
+pub fn expand_home_and_variables(arg: &str) -> Result<String, PatternError> {
+    return expand_home_and(arg, &variable);
+}
+
+/// Expands `~`, `~user`, and variables in `arg` like `expand_home_and_variables`, with the values from `variable`
+/// instead of the environment
+fn expand_home_and(
+    arg: &str,
+    variable: &dyn Fn(&str) -> Option<String>,
+) -> Result<String, PatternError> {
+    let chars: Vec<char> = arg.chars().collect();
+    if chars.first() != Some(&'~') {
+        return expand_variables(arg, &chars, 0, variable);
+    }
+
+    let end = chars.iter().position(|&c| c == '/').unwrap_or(chars.len());
+    let user: String = chars[1..end].iter().collect();
+    let home = if user.is_empty() {
+        variable("HOME").ok_or_else(|| {
+            return PatternError::new(arg, 0, "can't expand '~' since HOME isn't set");
+        })?
+    } else if user
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || ['.', '_', '-'].contains(&c))
+    {
+        user_home(&user).ok_or_else(|| {
+            return PatternError::new(arg, 0, &format!("the user '{user}' doesn't exist"));
+        })?
+    } else {
+        // not a user name, like `~*`, so the tilde is literal
+        return expand_variables(arg, &chars, 0, variable);
+    };
+
+    return Ok(home + &expand_variables(arg, &chars[end..], end, variable)?);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use rstest::rstest;
+
+    use super::*;
+
+    /// Expands `arg` with the given variables instead of the environment, which tests running in parallel share
+    fn expand(arg: &str, variables: &[(&str, &str)]) -> Result<String, PatternError> {
+        let variables: HashMap<&str, &str> = variables.iter().copied().collect();
+        return expand_home_and(arg, &|name| {
+            return variables.get(name).map(|value| value.to_string());
+        });
+    }
+
+    #[rstest]
+    #[case("src/*.rs", "src/*.rs")]
+    #[case("$JFSWATCH_TEST_DIR/*.yml", "/srv/app/*.yml")]
+    #[case("${JFSWATCH_TEST_DIR}-old/{a,b}", "/srv/app-old/{a,b}")]
+    #[case("${JFSWATCH_TEST_UNSET:-/etc}/app", "/etc/app")]
+    #[case("${JFSWATCH_TEST_EMPTY:-$JFSWATCH_TEST_DIR}", "/srv/app")]
+    #[case("${JFSWATCH_TEST_DIR:-/etc}", "/srv/app")]
+    #[case("${JFSWATCH_TEST_UNSET:-{a,b}}", "{a,b}")]
+    #[case("$JFSWATCH_TEST_EMPTY/x", "/x")]
+    #[case("\\$JFSWATCH_TEST_UNSET", "$JFSWATCH_TEST_UNSET")]
+    #[case("\\~/notes", "~/notes")]
+    #[case("\\{a,b\\}", "\\{a,b\\}")]
+    #[case("costs $5", "costs $5")]
+    #[case("a/~/b", "a/~/b")]
+    #[case("~*.md", "~*.md")]
+    fn given_variables_when_expand_then_substitutes_values(
+        #[case] arg: &str,
+        #[case] expected: &str,
+    ) {
+        let variables = [
+            ("JFSWATCH_TEST_DIR", "/srv/app"),
+            ("JFSWATCH_TEST_EMPTY", ""),
+        ];
+
+        assert_eq!(expand(arg, &variables).unwrap(), expected);
+    }
+
+    #[rstest]
+    #[case("~", "")]
+    #[case("~/.config/app/*.yml", "/.config/app/*.yml")]
+    fn given_tilde_when_expand_then_uses_home(#[case] arg: &str, #[case] rest: &str) {
+        let expanded = expand(arg, &[("HOME", "/home/me")]).unwrap();
+
+        assert_eq!(expanded, format!("/home/me{rest}"));
+    }
+
+    #[test]
+    fn given_tilde_without_home_when_expand_then_err() {
+        let error = expand("~/notes", &[]).unwrap_err();
+
+        assert_eq!(
+            error,
+            PatternError::new("~/notes", 0, "can't expand '~' since HOME isn't set")
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn given_tilde_with_user_when_expand_then_uses_their_home() {
+        let expanded = expand("~root/notes", &[]).unwrap();
+
+        assert!(expanded.starts_with('/'), "{expanded}");
+        assert!(expanded.ends_with("/notes"), "{expanded}");
+        assert_ne!(expanded, "~root/notes");
+    }
+
+    #[rstest]
+    #[case(
+        "$JFSWATCH_TEST_MISSING/*.yml",
+        0,
+        "the environment variable 'JFSWATCH_TEST_MISSING' isn't set"
+    )]
+    #[case(
+        "src/${JFSWATCH_TEST_MISSING}",
+        4,
+        "the environment variable 'JFSWATCH_TEST_MISSING' isn't set"
+    )]
+    #[case(
+        "${JFSWATCH_TEST_MISSING:-x/$JFSWATCH_TEST_MISSING}",
+        27,
+        "the environment variable 'JFSWATCH_TEST_MISSING' isn't set"
+    )]
+    #[case("src/${HOME", 4, "unclosed '${'")]
+    #[case("${1abc}", 0, INVALID_EXPANSION)]
+    #[case("${HOME:?x}", 0, INVALID_EXPANSION)]
+    #[case(
+        "~jfswatch-no-such-user/x",
+        0,
+        "the user 'jfswatch-no-such-user' doesn't exist"
+    )]
+    fn given_invalid_variable_when_expand_then_err_at_offset(
+        #[case] arg: &str,
+        #[case] offset: usize,
+        #[case] reason: &str,
+    ) {
+        let error = expand(arg, &[("HOME", "/home/me")]).unwrap_err();
+        assert_eq!(error, PatternError::new(arg, offset, reason));
+    }
+}
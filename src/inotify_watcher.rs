@@ -0,0 +1,353 @@
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::os::fd::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::Duration;
+
+use inotify::{EventMask, Inotify, WatchDescriptor, WatchMask};
+
+use crate::exclusions::Exclusions;
+use crate::explorers::{os_path, WatchRoot};
+
+/// The paths that the kernel reported as possibly changed
+#[derive(Debug, Default, PartialEq)]
+pub struct FSEvents {
+    /// Paths which might have been created, modified, or deleted. Directories are included when their entries change
+    pub paths: HashSet<PathBuf>,
+
+    /// Whether the kernel dropped events, in which case `paths` is incomplete and a full exploration is needed
+    pub overflowed: bool,
+}
+
+/// Blocks until `fd` is readable. Returns false if it isn't readable before the `timeout`
+fn poll_readable(fd: i32, timeout: Option<Duration>) -> io::Result<bool> {
+    let mut pollfd = libc::pollfd {
+        fd,
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    let timeout_ms = timeout.map_or(-1, |t| t.as_millis().min(i32::MAX as u128) as i32);
+
+    // SAFETY: `pollfd` is a valid pointer to exactly one pollfd struct for the duration of the call
+    let result = unsafe { libc::poll(&mut pollfd, 1, timeout_ms) };
+    if result < 0 {
+        let error = io::Error::last_os_error();
+        if error.kind() == io::ErrorKind::Interrupted {
+            return Ok(false);
+        }
+        return Err(error);
+    }
+
+    return Ok(result > 0);
+}
+
+/// Watches directories using Linux's inotify, so changes are pushed by the kernel rather than found by exploring
+pub struct InotifyWatcher {
+    inotify: Inotify,
+
+    /// The directories which need to be watched
+    roots: Vec<WatchRoot>,
+
+    /// Directories which are never watched
+    exclusions: Rc<Exclusions>,
+
+    /// The directory watched by each watch descriptor
+    watched_dirs: HashMap<WatchDescriptor, PathBuf>,
+
+    /// For reading raw events from the kernel
+    buffer: Vec<u8>,
+}
+
+impl InotifyWatcher {
+    /// Creates a new watcher which watches every existing directory within the `roots`, except for the `exclusions`
+    pub fn new(roots: Vec<WatchRoot>, exclusions: Rc<Exclusions>) -> io::Result<Self> {
+        let mut watcher = InotifyWatcher {
+            inotify: Inotify::init()?,
+            roots,
+            exclusions,
+            watched_dirs: HashMap::new(),
+            buffer: vec![0; 64 * 1024],
+        };
+        watcher.watch_roots()?;
+        return Ok(watcher);
+    }
+
+    /// How many directories are being watched
+    pub fn len(&self) -> usize {
+        return self.watched_dirs.len();
+    }
+
+    /// Replaces the directories which need to be watched, e.g. after the rules changed, and watches the new ones.
+    /// Directories outside of the new roots stay watched, but their events don't concern any rule
+    pub fn set_roots(&mut self, roots: Vec<WatchRoot>) -> io::Result<()> {
+        self.roots = roots;
+        return self.watch_roots();
+    }
+
+    /// Watches every existing directory within the roots. Safe to call again, e.g. after events were dropped
+    pub fn watch_roots(&mut self) -> io::Result<()> {
+        for root in self.roots.clone() {
+            // if the root directory doesn't exist yet, watch its nearest ancestor to notice when it's created
+            let mut dir = root.dir.as_path();
+            while !os_path(dir).is_dir() {
+                match dir.parent() {
+                    Some(parent) => dir = parent,
+                    None => break,
+                }
+            }
+
+            self.watch_tree(dir, &mut HashSet::new())?;
+        }
+
+        return Ok(());
+    }
+
+    /// Blocks until at least one event is available, and then waits another `settle` duration so that a burst of
+    /// events (e.g. from a `git checkout`) is collected together. Returns no events if none arrive before `timeout`
+    pub fn wait(&mut self, settle: Duration, timeout: Option<Duration>) -> io::Result<FSEvents> {
+        if !poll_readable(self.inotify.as_raw_fd(), timeout)? {
+            return Ok(FSEvents::default());
+        }
+
+        std::thread::sleep(settle);
+        return self.read();
+    }
+
+    /// Reads every queued event without blocking
+    pub fn read(&mut self) -> io::Result<FSEvents> {
+        let mut events = FSEvents::default();
+        let mut new_dirs: Vec<PathBuf> = Vec::new();
+
+        loop {
+            let batch = match self.inotify.read_events(&mut self.buffer) {
+                Ok(batch) => batch,
+                Err(error) if error.kind() == io::ErrorKind::WouldBlock => break,
+                Err(error) => return Err(error),
+            };
+
+            for event in batch {
+                if event.mask.contains(EventMask::Q_OVERFLOW) {
+                    events.overflowed = true;
+                    continue;
+                }
+
+                if event.mask.contains(EventMask::IGNORED) {
+                    // the kernel stopped watching the directory, e.g. because it was deleted
+                    self.watched_dirs.remove(&event.wd);
+                    continue;
+                }
+
+                let Some(dir) = self.watched_dirs.get(&event.wd) else {
+                    continue;
+                };
+                events.paths.insert(dir.clone());
+
+                if let Some(name) = event.name {
+                    let path = dir.join(name);
+
+                    if event.mask.contains(EventMask::ISDIR) {
+                        if event
+                            .mask
+                            .intersects(EventMask::CREATE | EventMask::MOVED_TO)
+                        {
+                            new_dirs.push(path.clone());
+                        } else if event.mask.contains(EventMask::MOVED_FROM) {
+                            // the watches follow the directory, so they'd report events under the wrong path
+                            let moved: Vec<WatchDescriptor> = self
+                                .watched_dirs
+                                .iter()
+                                .filter(|(_, watched)| watched.starts_with(&path))
+                                .map(|(wd, _)| wd.clone())
+                                .collect();
+                            for wd in moved {
+                                self.watched_dirs.remove(&wd);
+                                let _ = self.inotify.watches().remove(wd);
+                            }
+                        }
+                    }
+
+                    events.paths.insert(path);
+                }
+            }
+        }
+
+        // paths inside new directories were likely created before the directory could be watched
+        for dir in new_dirs {
+            self.watch_tree(&dir, &mut events.paths)?;
+        }
+
+        return Ok(events);
+    }
+
+    /// Watches `dir` and its subdirectories within the roots. Every path found along the way is added to `found`
+    fn watch_tree(&mut self, dir: &Path, found: &mut HashSet<PathBuf>) -> io::Result<()> {
+        if !self.roots.iter().any(|root| root.contains(dir)) || self.exclusions.excludes(dir) {
+            return Ok(());
+        }
+
+        let mask = WatchMask::CREATE
+            | WatchMask::DELETE
+            | WatchMask::MODIFY
+            | WatchMask::ATTRIB
+            | WatchMask::CLOSE_WRITE
+            | WatchMask::MOVED_FROM
+            | WatchMask::MOVED_TO
+            | WatchMask::ONLYDIR;
+
+        match self.inotify.watches().add(os_path(dir), mask) {
+            Ok(wd) => {
+                self.watched_dirs.insert(wd, dir.to_path_buf());
+            }
+            Err(error) if error.raw_os_error() == Some(libc::ENOSPC) => {
+                // the kernel's limit on the number of watches was reached
+                return Err(error);
+            }
+            Err(_) => {
+                // not a directory, or it no longer exists
+                return Ok(());
+            }
+        }
+
+        let Ok(entries) = std::fs::read_dir(os_path(dir)) else {
+            return Ok(());
+        };
+
+        for entry in entries.filter_map(Result::ok) {
+            let path = dir.join(entry.file_name());
+            if entry.file_type().is_ok_and(|file_type| file_type.is_dir()) {
+                self.watch_tree(&path, found)?;
+            }
+            found.insert(path);
+        }
+
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir_in;
+
+    use super::*;
+
+    const SETTLE: Duration = Duration::from_millis(10);
+    const TIMEOUT: Option<Duration> = Some(Duration::from_secs(5));
+
+    fn watcher_for(dir: &Path, depth: Option<usize>) -> InotifyWatcher {
+        let roots = vec![WatchRoot {
+            dir: dir.to_path_buf(),
+            depth,
+        }];
+        return InotifyWatcher::new(roots, Rc::new(Exclusions::default())).unwrap();
+    }
+
+    #[test]
+    fn given_watched_dir_when_file_created_then_reports_file_and_dir() {
+        let tmp = tempdir_in(".").unwrap();
+        let basedir = tmp.path().to_owned();
+        let mut watcher = watcher_for(&basedir, Some(0));
+
+        let path = basedir.join("file.txt");
+        std::fs::write(&path, "contents").unwrap();
+
+        let events = watcher.wait(SETTLE, TIMEOUT).unwrap();
+        assert!(!events.overflowed);
+        assert!(events.paths.contains(&path));
+        assert!(events.paths.contains(&basedir));
+    }
+
+    #[test]
+    fn given_watched_dir_when_nested_dir_created_then_reports_its_contents_and_watches_it() {
+        let tmp = tempdir_in(".").unwrap();
+        let basedir = tmp.path().to_owned();
+        let mut watcher = watcher_for(&basedir, None);
+        assert_eq!(watcher.len(), 1);
+
+        let nested_file = basedir.join("nested/very/deeply/file.txt");
+        std::fs::create_dir_all(nested_file.parent().unwrap()).unwrap();
+        std::fs::write(&nested_file, "contents").unwrap();
+
+        let events = watcher.wait(SETTLE, TIMEOUT).unwrap();
+        assert!(events.paths.contains(&nested_file));
+        assert_eq!(watcher.len(), 4);
+
+        std::fs::write(&nested_file, "modified").unwrap();
+        let events = watcher.wait(SETTLE, TIMEOUT).unwrap();
+        assert!(events.paths.contains(&nested_file));
+    }
+
+    #[test]
+    fn given_limited_depth_when_nested_dir_created_then_does_not_watch_it() {
+        let tmp = tempdir_in(".").unwrap();
+        let basedir = tmp.path().to_owned();
+        let mut watcher = watcher_for(&basedir, Some(0));
+
+        std::fs::create_dir(basedir.join("nested")).unwrap();
+
+        let events = watcher.wait(SETTLE, TIMEOUT).unwrap();
+        assert!(events.paths.contains(&basedir.join("nested")));
+        assert_eq!(watcher.len(), 1);
+    }
+
+    #[test]
+    fn given_missing_root_dir_when_created_then_starts_watching_it() {
+        let tmp = tempdir_in(".").unwrap();
+        let basedir = tmp.path().to_owned();
+        let rootdir = basedir.join("not/yet");
+        let mut watcher = watcher_for(&rootdir, Some(0));
+        assert_eq!(watcher.len(), 1);
+
+        let path = rootdir.join("file.txt");
+        std::fs::create_dir_all(&rootdir).unwrap();
+        std::fs::write(&path, "contents").unwrap();
+
+        let events = watcher.wait(SETTLE, TIMEOUT).unwrap();
+        assert!(events.paths.contains(&path));
+        assert_eq!(watcher.len(), 3);
+    }
+
+    #[test]
+    fn given_watched_dir_when_file_deleted_then_reports_file() {
+        let tmp = tempdir_in(".").unwrap();
+        let basedir = tmp.path().to_owned();
+        let path = basedir.join("file.txt");
+        std::fs::write(&path, "contents").unwrap();
+        let mut watcher = watcher_for(&basedir, Some(0));
+
+        std::fs::remove_file(&path).unwrap();
+
+        let events = watcher.wait(SETTLE, TIMEOUT).unwrap();
+        assert!(events.paths.contains(&path));
+    }
+
+    #[test]
+    fn given_no_changes_when_wait_with_timeout_then_returns_no_events() {
+        let tmp = tempdir_in(".").unwrap();
+        let basedir = tmp.path().to_owned();
+        let mut watcher = watcher_for(&basedir, Some(0));
+
+        let events = watcher
+            .wait(SETTLE, Some(Duration::from_millis(10)))
+            .unwrap();
+        assert_eq!(events, FSEvents::default());
+    }
+
+    #[test]
+    fn given_excluded_dir_when_created_then_does_not_watch_it() {
+        let tmp = tempdir_in(".").unwrap();
+        let basedir = tmp.path().to_owned();
+        let roots = vec![WatchRoot {
+            dir: basedir.clone(),
+            depth: None,
+        }];
+        let exclusions = Exclusions::from_cli_args(&["target".to_string()]).unwrap();
+        let mut watcher = InotifyWatcher::new(roots, Rc::new(exclusions)).unwrap();
+
+        std::fs::create_dir_all(basedir.join("target/debug")).unwrap();
+        std::fs::create_dir_all(basedir.join("src/nested")).unwrap();
+
+        watcher.wait(SETTLE, TIMEOUT).unwrap();
+        assert_eq!(watcher.len(), 3);
+    }
+}
@@ -1,364 +1,2397 @@
-use std::process::Command;
-use std::thread::sleep;
-use std::time::Duration;
+#[cfg(target_os = "linux")]
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
 
-use crate::explorers::Explorer;
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, Local};
+
+use crate::exclusions::Exclusions;
+#[cfg(target_os = "linux")]
+use crate::explorers::WatchRoot;
+use crate::explorers::{ExactExplorer, Explorer, PathCaptures};
+#[cfg(target_os = "linux")]
+use crate::inotify_watcher::{FSEvents, InotifyWatcher};
+use crate::rule::Rule;
+use crate::runner::{shell_quote, CommandLine, Runner, StopOptions};
+use crate::shutdown::Shutdown;
 use crate::watched_fs::FSDifference;
 use crate::watched_fs::WatchedFS;
 
-/// The format for writing DateTime<Local>'s
-const LOCAL_DATE_FORMAT: &str = "%Y-%m-%d %H:%M:%S%.3f";
+/// The format for writing DateTime<Local>'s
+const LOCAL_DATE_FORMAT: &str = "%Y-%m-%d %H:%M:%S%.3f";
+
+/// A variable in a command, like `${path:q}`, or a capture of the pattern which found the path, like `{name}`
+struct Substitution<'a> {
+    /// The backslash that escapes the variable, or nothing
+    prefix: &'a str,
+
+    /// The full variable, e.g. `${path:q}`
+    variable: &'a str,
+
+    /// The bare variable name, e.g. `path`
+    name: &'a str,
+
+    /// The optional modifier, e.g. `q`
+    modifier: Option<&'a str>,
+
+    /// Whether the variable is a capture like `{name}` rather than a variable like `$name`
+    capture: bool,
+}
+
+/// Splits a substitution pattern match into its parts. Braces after a dollar sign like the shell's `${1}` are a
+/// variable rather than a capture
+fn split_substitution<'a>(caps: &regex::Captures<'a>) -> Substitution<'a> {
+    let prefix = caps.get(1).unwrap();
+    let capture = caps.get(6);
+    return Substitution {
+        prefix: prefix.as_str(),
+        variable: &caps.get(0).unwrap().as_str()[prefix.len()..],
+        name: capture.or(caps.get(3)).or(caps.get(2)).unwrap().as_str(),
+        modifier: caps.get(4).map(|modifier| modifier.as_str()),
+        capture: capture.is_some() && caps.get(5).is_some_and(|dollar| dollar.is_empty()),
+    };
+}
+
+/// Which changes are included by a list variable like `$new_paths`
+fn list_filter(name: &str) -> Option<fn(&FSDifference) -> bool> {
+    return match name {
+        "paths" => Some(|_| true),
+        "new_paths" => Some(|c| matches!(c, FSDifference::New { .. })),
+        "modified_paths" => Some(|c| matches!(c, FSDifference::Modified { .. })),
+        "deleted_paths" => Some(|c| matches!(c, FSDifference::Deleted { .. })),
+        _ => None,
+    };
+}
+
+/// Logs a single detected difference in a human-readable way
+fn log_difference(diff: &FSDifference) {
+    match diff {
+        FSDifference::Modified { path, mtime, .. } => info!(
+            "'{}' was modified at {}",
+            path,
+            mtime.format(LOCAL_DATE_FORMAT)
+        ),
+        FSDifference::New { path, mtime } => info!(
+            "'{}' is new since {}",
+            path,
+            mtime.format(LOCAL_DATE_FORMAT)
+        ),
+        FSDifference::Deleted { path, .. } => info!("'{}' was deleted", path),
+    }
+}
+
+/// How often the config file is checked for changes while polling, when one is used
+const CONFIG_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The longest the inotify backend waits for events without checking whether a shutdown was requested. Signals
+/// interrupt the wait anyway, so this only matters when one arrives just before waiting
+#[cfg(target_os = "linux")]
+const SHUTDOWN_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Variables that describe a single changed path
+const SINGLE_CHANGE_VARIABLES: [&str; 12] = [
+    "diff",
+    "path",
+    "mtime",
+    "old_mtime",
+    "dir",
+    "basename",
+    "stem",
+    "ext",
+    "relpath",
+    "abspath",
+    "size",
+    "pattern",
+];
+
+/// Variables that describe every change in a check
+const LIST_VARIABLES: [&str; 5] = [
+    "paths",
+    "new_paths",
+    "modified_paths",
+    "deleted_paths",
+    "count",
+];
+
+/// Variables that can be formatted with a time modifier like `%s` or `rfc3339`
+const TIME_VARIABLES: [&str; 2] = ["mtime", "old_mtime"];
+
+/// Environment variables that describe the changes to the command
+const ENVIRONMENT_VARIABLES: [&str; 6] = [
+    "JFSWATCH_DIFF",
+    "JFSWATCH_PATH",
+    "JFSWATCH_MTIME",
+    "JFSWATCH_OLD_MTIME",
+    "JFSWATCH_PATHS",
+    "JFSWATCH_COUNT",
+];
+
+/// Checks that every variable in the `command` is known and can be used, unless it's left for the shell to expand.
+/// Unknown variables with a modifier like `${pth:q}`, or with the `JFSWATCH_` prefix, are typos rather than shell
+/// variables. `batch` commands can only use the variables that describe every change in a check
+fn validate_command(
+    substitution_pattern: &regex::Regex,
+    command: &str,
+    batch: bool,
+    shell: bool,
+) -> Result<(), String> {
+    for caps in substitution_pattern.captures_iter(command) {
+        let substitution = split_substitution(&caps);
+        let (variable, name) = (substitution.variable, substitution.name);
+        if substitution.prefix == "\\" || substitution.capture {
+            continue;
+        }
+
+        if !SINGLE_CHANGE_VARIABLES.contains(&name) && !LIST_VARIABLES.contains(&name) {
+            // the shell's own modifiers start with an operator or offset, e.g. `${HOME:-/root}` or `${HOME:1}`
+            let modified = substitution.modifier.is_some_and(|modifier| {
+                modifier.starts_with(|c: char| c.is_alphabetic() || c == '%')
+            });
+            let environment =
+                name.starts_with("JFSWATCH_") && !ENVIRONMENT_VARIABLES.contains(&name);
+            if shell && !modified && !environment {
+                // left for the shell to expand, e.g. `$HOME`
+                continue;
+            }
+            return Err(format!(
+                "Unknown variable {variable}. Escape it like \\{variable} to pass it through as-is"
+            ));
+        }
+        if batch && SINGLE_CHANGE_VARIABLES.contains(&name) {
+            return Err(format!(
+                "${name} is unavailable in batch mode. Use a list variable like $paths instead"
+            ));
+        }
+        if let Some(modifier) = substitution.modifier {
+            validate_modifier(variable, name, modifier, shell)?;
+        }
+    }
+
+    return Ok(());
+}
+
+/// Checks that the `modifier` can be applied to the variable called `name`, which was written as `variable`
+fn validate_modifier(
+    variable: &str,
+    name: &str,
+    modifier: &str,
+    shell: bool,
+) -> Result<(), String> {
+    if modifier == "q" {
+        if !shell {
+            return Err(format!(
+                "{variable} quotes for a shell, but commands are run without a shell"
+            ));
+        }
+        return Ok(());
+    }
+
+    if TIME_VARIABLES.contains(&name) {
+        if modifier == "rfc3339" {
+            return Ok(());
+        }
+        if modifier.starts_with('%') {
+            if StrftimeItems::new(modifier).any(|item| matches!(item, Item::Error)) {
+                return Err(format!("Invalid time format '{modifier}' in {variable}"));
+            }
+            return Ok(());
+        }
+    }
+
+    return Err(format!("Unknown modifier '{modifier}' in {variable}"));
+}
+
+/// How changes on the file system are detected
+#[derive(Debug, Clone, Copy, PartialEq, Default, clap::ValueEnum, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Backend {
+    /// Explore the file system every `interval` seconds and compare it against the previous exploration
+    #[default]
+    Poll,
+
+    /// Wait for Linux's inotify to report events, and only check the paths that the events concern
+    Inotify,
+}
+
+/// How changed paths are written to the command's stdin
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum StdinPaths {
+    /// Each path is followed by a newline
+    Lines,
+
+    /// Each path is followed by a NUL byte, e.g. for `xargs -0`. This is safe for any path
+    Nul,
+}
+
+/// Optional behaviours of the JFSWatch application
+#[derive(Debug, Clone)]
+pub struct WatchOptions {
+    /// Run the command once per check with every change, rather than once per changed path
+    pub batch: bool,
+
+    /// Joins the paths in list variables like `$paths`
+    pub separator: String,
+
+    /// Compare the contents of files up to this many bytes instead of their mtime. `None` disables hashing
+    pub hash_limit: Option<u64>,
+
+    /// How changes on the file system are detected
+    pub backend: Backend,
+
+    /// Seconds between full explorations that catch changes missed by the `inotify` backend. `None` trusts events
+    pub reconcile: Option<f32>,
+
+    /// Paths which are never watched, even when an explorer finds them
+    pub exclusions: Rc<Exclusions>,
+
+    /// Keep the command running in the background, and stop it before running it again, rather than waiting for
+    /// it to exit
+    pub restart: bool,
+
+    /// How the running command is stopped when restarting it or shutting down
+    pub stop: StopOptions,
+
+    /// Write the changed paths to the command's stdin. `None` inherits jfswatch's stdin
+    pub stdin: Option<StdinPaths>,
+
+    /// Run the command with the user's shell. Otherwise, the first argument is executed directly and each argument
+    /// is substituted on its own
+    pub shell: bool,
+}
+
+impl Default for WatchOptions {
+    fn default() -> Self {
+        return WatchOptions {
+            batch: false,
+            separator: " ".to_string(),
+            hash_limit: None,
+            backend: Backend::Poll,
+            reconcile: None,
+            exclusions: Rc::new(Exclusions::default()),
+            restart: false,
+            stop: StopOptions::default(),
+            stdin: None,
+            shell: true,
+        };
+    }
+}
+
+/// A rule along with the state of watching its paths
+struct WatchedRule {
+    rule: Rule,
+
+    /// How long to wait between non-changing checks of the rule's paths
+    interval: Duration,
+
+    /// How long to wait after running the rule's command before checking its paths again
+    sleep: Duration,
+
+    /// Run the command once per check with every change, rather than once per changed path
+    batch: bool,
+
+    /// Runs the rule's command
+    runner: Runner,
+
+    /// The rule's paths as of its last check
+    watched_fs: WatchedFS,
+
+    /// When the rule's paths are due to be checked again
+    next_check: Instant,
+
+    /// Paths that inotify reported events for since the rule's last check
+    #[cfg(target_os = "linux")]
+    pending: HashSet<PathBuf>,
+
+    /// Whether inotify may have missed changes since the rule's last check, so its paths must be explored again
+    #[cfg(target_os = "linux")]
+    reexplore: bool,
+}
+
+impl WatchedRule {
+    /// Whether any of the rule's explorers would find the `path`
+    fn matches(&self, path: &Path) -> bool {
+        return self
+            .rule
+            .explorers
+            .iter()
+            .any(|explorer| explorer.matches(path));
+    }
+}
+
+/// Checks that the `rule` can be watched, using the `interval`, `sleep`, and `batch` mode that apply to it
+fn validate_rule(
+    rule: &Rule,
+    interval: f32,
+    sleep: f32,
+    batch: bool,
+    substitution_pattern: &regex::Regex,
+    shell: bool,
+) -> Result<(), String> {
+    if rule.cmd.is_empty() {
+        return Err("No command was given".to_string());
+    }
+    if rule.explorers.is_empty() {
+        return Err("Empty path discovery list".to_string());
+    }
+    if interval <= 0.0 {
+        return Err("Interval must be a positive number of seconds".to_string());
+    }
+    if sleep <= 0.0 {
+        return Err("Sleep must be a positive number of seconds".to_string());
+    }
+    return validate_command(substitution_pattern, &rule.cmd.join(" "), batch, shell);
+}
+
+/// The rules and options to watch with, e.g. as loaded from the command line and config file
+pub struct WatchSettings {
+    /// The rules to watch
+    pub rules: Vec<Rule>,
+
+    /// Seconds to wait between non-changing checks of the rules without their own interval
+    pub interval: f32,
+
+    /// Seconds to wait after running a command for the rules without their own sleep
+    pub sleep: f32,
+
+    /// Optional behaviours
+    pub options: WatchOptions,
+
+    /// Whether the glob patterns skip paths ignored by ignore files
+    pub respect_ignore_files: bool,
+}
+
+/// Loads the settings again, e.g. after the config file changed
+pub type SettingsLoader = Box<dyn Fn() -> Result<WatchSettings, String>>;
+
+/// A config file which the settings are reloaded from whenever it changes
+struct ConfigFile {
+    /// Finds the config file
+    explorer: ExactExplorer,
+
+    /// The config file as of its last check
+    watched_fs: WatchedFS,
+
+    /// Loads the settings from the config file again
+    load: SettingsLoader,
+}
+
+impl ConfigFile {
+    /// Checks the config file again. Returns whether it changed since its last check
+    fn changed(&mut self) -> bool {
+        let mut watched_fs = WatchedFS::new(1);
+        self.explorer.explore(&mut watched_fs);
+
+        let prev_watched_fs = std::mem::replace(&mut self.watched_fs, watched_fs);
+        return !self.watched_fs.compare_all(prev_watched_fs).is_empty();
+    }
+}
+
+/// Checks the options that apply to every rule
+fn validate_options(options: &WatchOptions) -> Result<(), String> {
+    if let Some(reconcile) = options.reconcile {
+        if reconcile <= 0.0 {
+            return Err("Reconcile must be a positive number of seconds".to_string());
+        }
+        if options.backend != Backend::Inotify {
+            return Err("Reconcile is only supported by the inotify backend".to_string());
+        }
+    }
+    if options.stop.timeout < 0.0 || !options.stop.timeout.is_finite() {
+        return Err("Stop timeout must be a non-negative number of seconds".to_string());
+    }
+    return Ok(());
+}
+
+/// Main data structure to maintain the state of the JFSWatch application
+pub struct JFSWatch {
+    /// The rules to watch, along with the state of watching them
+    rules: Vec<WatchedRule>,
+
+    /// For substituting variables into the commands
+    substitution_pattern: regex::Regex,
+
+    /// Optional behaviours
+    options: WatchOptions,
+
+    /// Requested by SIGINT or SIGTERM to stop watching
+    shutdown: Shutdown,
+
+    /// How many paths were found to be new, modified, and deleted
+    change_counts: [usize; 3],
+
+    /// The config file to reload the settings from when it changes, if one is used
+    config: Option<ConfigFile>,
+
+    /// How many commands were run by the runners of rules which were replaced by reloading the config file
+    retired_commands: usize,
+
+    /// How many of the `retired_commands` failed
+    retired_failures: usize,
+}
+
+impl JFSWatch {
+    /// Watches every rule in the same process. The `interval` and `sleep` seconds apply to the rules without their own
+    pub fn new(
+        rules: Vec<Rule>,
+        interval: f32,
+        sleep: f32,
+        options: WatchOptions,
+    ) -> Result<Self, String> {
+        validate_options(&options)?;
+
+        let identifier = "[A-Za-z_][A-Za-z0-9_]*";
+        let substitution_pattern = regex::Regex::new(&format!(
+            r"(\\?)(?:\$(\{{({identifier})(?::([^{{}}]*))?\}}|{identifier})|(\$?)\{{({identifier}|[0-9]+)\}})"
+        ))
+        .unwrap();
+
+        let mut jfswatch = JFSWatch {
+            rules: Vec::new(),
+            substitution_pattern,
+            shutdown: Shutdown::default(),
+            change_counts: [0; 3],
+            options,
+            config: None,
+            retired_commands: 0,
+            retired_failures: 0,
+        };
+        jfswatch.rules = jfswatch.watch_rules(rules, interval, sleep, &jfswatch.options)?;
+        return Ok(jfswatch);
+    }
+
+    /// Reloads the settings with `load` whenever the config file at `path` changes
+    pub fn with_config(mut self, path: &Path, load: SettingsLoader) -> Self {
+        let explorer = ExactExplorer::new(path.to_path_buf());
+        let mut watched_fs = WatchedFS::new(1);
+        explorer.explore(&mut watched_fs);
+
+        self.config = Some(ConfigFile {
+            explorer,
+            watched_fs,
+            load,
+        });
+        return self;
+    }
+
+    /// Validates the `rules` and prepares to watch them with the `options`. The `interval` and `sleep` seconds apply
+    /// to the rules without their own
+    fn watch_rules(
+        &self,
+        rules: Vec<Rule>,
+        interval: f32,
+        sleep: f32,
+        options: &WatchOptions,
+    ) -> Result<Vec<WatchedRule>, String> {
+        if rules.is_empty() {
+            return Err("No command was given".to_string());
+        }
+
+        let several_rules = rules.len() > 1;
+        let mut watched_rules = Vec::with_capacity(rules.len());
+
+        for rule in rules {
+            let rule_interval = rule.interval.unwrap_or(interval);
+            let rule_sleep = rule.sleep.unwrap_or(sleep);
+            let batch = rule.batch.unwrap_or(options.batch);
+
+            validate_rule(
+                &rule,
+                rule_interval,
+                rule_sleep,
+                batch,
+                &self.substitution_pattern,
+                options.shell,
+            )
+            .map_err(|error| match several_rules {
+                true => format!("{error}, in the rule for {}", rule.describe()),
+                false => error,
+            })?;
+
+            watched_rules.push(WatchedRule {
+                rule,
+                interval: Duration::from_secs_f32(rule_interval),
+                sleep: Duration::from_secs_f32(rule_sleep),
+                batch,
+                runner: Runner::new(options.restart, options.stop, self.shutdown.clone()),
+                watched_fs: WatchedFS::new(0),
+                next_check: Instant::now(),
+                #[cfg(target_os = "linux")]
+                pending: HashSet::new(),
+                #[cfg(target_os = "linux")]
+                reexplore: false,
+            });
+        }
+
+        return Ok(watched_rules);
+    }
+
+    /// The main loop for checking the file system and running the specified commands (blocking call). Returns the
+    /// exit status once SIGINT or SIGTERM is received, after the running commands were stopped
+    pub fn watch(&mut self) -> i32 {
+        let started = Instant::now();
+        if let Err(error) = self.shutdown.register() {
+            warn!("Cannot handle SIGINT and SIGTERM: {}", error);
+        }
+
+        match self.options.backend {
+            Backend::Poll => self.watch_polling(),
+            Backend::Inotify => self.watch_inotify(),
+        }
+
+        // the loops only return once a shutdown was requested
+        let signal = self.shutdown.requested().unwrap();
+        info!("Received {}, shutting down", signal);
+        for rule in self.rules.iter_mut() {
+            rule.runner.stop(signal);
+        }
+
+        let [new, modified, deleted] = self.change_counts;
+        info!(
+            "Watched for {:.1?}: detected {} new, {} modified, and {} deleted paths, and ran {} commands of which {} failed",
+            started.elapsed(),
+            new,
+            modified,
+            deleted,
+            self.retired_commands + self.runners().map(Runner::commands).sum::<usize>(),
+            self.retired_failures + self.runners().map(Runner::failures).sum::<usize>()
+        );
+
+        // like a shell, report being terminated by the signal
+        return 128 + signal.0;
+    }
+
+    /// The runner of every rule that's currently watched
+    fn runners(&self) -> impl Iterator<Item = &Runner> {
+        return self.rules.iter().map(|rule| &rule.runner);
+    }
+
+    /// Explores the paths of each rule every interval, comparing them against the rule's previous exploration
+    fn watch_polling(&mut self) {
+        let found = self.explore_initial();
+        info!("Found {} initial paths", found);
+
+        let now = Instant::now();
+        for rule in self.rules.iter_mut() {
+            rule.next_check = now + rule.interval;
+        }
+
+        while self.shutdown.requested().is_none() {
+            let next_check = self.rules.iter().map(|rule| rule.next_check).min().unwrap();
+            let mut wait = next_check.saturating_duration_since(Instant::now());
+            if self.config.is_some() {
+                wait = wait.min(CONFIG_CHECK_INTERVAL);
+            }
+            if !self.shutdown.sleep(wait) {
+                break;
+            }
+
+            self.check_config();
+            let due = self.due_rules();
+            for (i, differences) in due.iter().zip(self.reexplore(&due)) {
+                if differences.is_empty() {
+                    debug!("No changes in {} paths", self.rules[*i].watched_fs.len());
+                    self.rules[*i].next_check = Instant::now() + self.rules[*i].interval;
+                } else {
+                    self.on_changes(*i, &differences);
+                }
+            }
+        }
+    }
+
+    /// The directories that inotify needs to watch for the paths of every rule
+    #[cfg(target_os = "linux")]
+    fn roots(&self) -> Vec<WatchRoot> {
+        return self
+            .rules
+            .iter()
+            .flat_map(|rule| rule.rule.explorers.iter())
+            .flat_map(|explorer| explorer.roots())
+            .collect();
+    }
+
+    /// Explores the file system once, and then only checks the paths that inotify reports events for
+    #[cfg(target_os = "linux")]
+    fn watch_inotify(&mut self) {
+        // start watching before exploring so that no changes are missed in between
+        let result = InotifyWatcher::new(self.roots(), Rc::clone(&self.options.exclusions))
+            .and_then(|mut watcher| self.watch_events(&mut watcher));
+
+        if let Err(error) = result {
+            warn!(
+                "Cannot watch with inotify, falling back to polling: {}",
+                error
+            );
+            self.watch_polling();
+        }
+    }
+
+    /// The main loop for the inotify backend. Only returns once a shutdown is requested, or if inotify stops working.
+    /// Rules are checked whenever events arrive, unless they're sleeping after running their command, in which case
+    /// the events are kept until they wake up
+    #[cfg(target_os = "linux")]
+    fn watch_events(&mut self, watcher: &mut InotifyWatcher) -> std::io::Result<()> {
+        let found = self.explore_initial();
+        info!(
+            "Found {} initial paths in {} watched directories",
+            found,
+            watcher.len()
+        );
+
+        let reconcile_every = self.options.reconcile.map(Duration::from_secs_f32);
+        let mut next_reconcile = reconcile_every.map(|every| Instant::now() + every);
+        let mut reconciliations: usize = 0;
+        let mut total_missed: usize = 0;
+
+        while self.shutdown.requested().is_none() {
+            let now = Instant::now();
+            let wake_up = self
+                .rules
+                .iter()
+                .map(|rule| rule.next_check)
+                .filter(|next_check| *next_check > now)
+                .min();
+            let timeout = [next_reconcile, wake_up]
+                .into_iter()
+                .flatten()
+                .map(|at| at.saturating_duration_since(now))
+                .fold(SHUTDOWN_CHECK_INTERVAL, Duration::min);
+
+            // the interval gives bursts of events a chance to arrive together
+            let settle = self.rules.iter().map(|rule| rule.interval).min().unwrap();
+            let events = watcher.wait(settle, Some(timeout))?;
+            if events.overflowed {
+                warn!("Some inotify events were dropped, exploring all paths again");
+                watcher.watch_roots()?;
+            }
+            self.queue(events);
+
+            if self.check_config() {
+                watcher.set_roots(self.roots())?;
+            }
+
+            let mut reconciling = false;
+            if let (Some(every), Some(at)) = (reconcile_every, next_reconcile) {
+                if Instant::now() >= at {
+                    // apply events that arrived in the meantime, so they aren't counted as missed
+                    self.queue(watcher.read()?);
+                    watcher.watch_roots()?;
+                    reconciling = true;
+                    next_reconcile = Some(Instant::now() + every);
+                }
+            }
+
+            let due = self.due_rules();
+            let mut differences = self.refresh(&due);
+
+            if reconciling {
+                // sleeping rules catch up once they wake up
+                for rule in self.rules.iter_mut() {
+                    rule.reexplore = true;
+                }
+
+                let missed = self.reexplore(&due);
+                let missed_count: usize = missed.iter().map(Vec::len).sum();
+                reconciliations += 1;
+                total_missed += missed_count;
+                info!(
+                    "Reconciliation found {} changes missed by inotify ({} missed over {} reconciliations)",
+                    missed_count, total_missed, reconciliations
+                );
+
+                for (differences, missed) in differences.iter_mut().zip(missed) {
+                    differences.extend(missed);
+                    differences.sort_by(|a, b| a.path().cmp(b.path()));
+                }
+            }
+
+            for (i, differences) in due.iter().zip(differences) {
+                if differences.is_empty() {
+                    debug!("No changes in {} paths", self.rules[*i].watched_fs.len());
+                } else {
+                    self.on_changes(*i, &differences);
+                }
+            }
+        }
+
+        return Ok(());
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn watch_inotify(&mut self) {
+        warn!("inotify is only available on Linux, falling back to polling");
+        self.watch_polling();
+    }
+
+    /// Reloads the settings when the config file changed since its last check. Returns whether they were reloaded.
+    /// Invalid settings are logged, and the previous settings are kept
+    fn check_config(&mut self) -> bool {
+        let Some(config) = self.config.as_mut() else {
+            return false;
+        };
+        if !config.changed() {
+            return false;
+        }
+
+        let path = config.explorer.pattern();
+        info!("'{}' changed, reloading it", path);
+        let loaded = (config.load)();
+
+        return match loaded.and_then(|settings| self.reload(settings)) {
+            Ok(()) => {
+                info!("Reloaded '{}', watching {} rules", path, self.rules.len());
+                true
+            }
+            Err(error) => {
+                warn!("Keeping the previous config: {}", error);
+                false
+            }
+        };
+    }
+
+    /// Replaces the rules and options with the `settings`. Each new rule starts from what the previous rules knew
+    /// about its paths, so only paths that really changed are reported on its next check. Unchanged rules keep their
+    /// running command, while the commands of the others are stopped
+    fn reload(&mut self, settings: WatchSettings) -> Result<(), String> {
+        let WatchSettings {
+            rules,
+            interval,
+            sleep,
+            mut options,
+            ..
+        } = settings;
+
+        if options.backend != self.options.backend
+            || options.reconcile != self.options.reconcile
+            || options.exclusions != self.options.exclusions
+        {
+            warn!("Changes to the backend, reconcile, and exclude options only apply after restarting jfswatch");
+            options.backend = self.options.backend;
+            options.reconcile = self.options.reconcile;
+            options.exclusions = Rc::clone(&self.options.exclusions);
+        }
+        validate_options(&options)?;
+        let mut rules = self.watch_rules(rules, interval, sleep, &options)?;
+
+        let mut known = self.new_watched_fs(0);
+        let same_runners =
+            options.restart == self.options.restart && options.stop == self.options.stop;
+        let mut kept = vec![false; rules.len()];
+
+        for mut prev_rule in std::mem::take(&mut self.rules) {
+            known.extend(prev_rule.watched_fs);
+
+            let same_rule = (0..rules.len()).find(|&i| {
+                return !kept[i]
+                    && rules[i].rule.describe() == prev_rule.rule.describe()
+                    && rules[i].rule.cmd == prev_rule.rule.cmd;
+            });
+            match same_rule {
+                Some(i) if same_runners => {
+                    std::mem::swap(&mut rules[i].runner, &mut prev_rule.runner);
+                    kept[i] = true;
+                }
+                _ => prev_rule.runner.stop(self.options.stop.signal),
+            }
+            self.retired_commands += prev_rule.runner.commands();
+            self.retired_failures += prev_rule.runner.failures();
+
+            #[cfg(target_os = "linux")]
+            for rule in rules.iter_mut() {
+                rule.pending.extend(prev_rule.pending.iter().cloned());
+                rule.reexplore |= prev_rule.reexplore;
+            }
+        }
+
+        self.rules = rules;
+        self.options = options;
+
+        // paths that were already known keep their previous state, so their changes since are still reported
+        let indices: Vec<usize> = (0..self.rules.len()).collect();
+        let found = self.explore(&indices);
+        let now = Instant::now();
+        for (i, mut watched_fs) in indices.iter().zip(self.split_found(found, &indices)) {
+            let rule = &mut self.rules[*i];
+            watched_fs
+                .extend(known.subset(known.paths().filter(|path| rule.matches(Path::new(path)))));
+            rule.watched_fs = watched_fs;
+            rule.next_check = now + rule.interval;
+        }
+
+        return Ok(());
+    }
+
+    /// The rules which are due to be checked
+    fn due_rules(&self) -> Vec<usize> {
+        let now = Instant::now();
+        return (0..self.rules.len())
+            .filter(|&i| self.rules[i].next_check <= now)
+            .collect();
+    }
+
+    /// Logs the differences found in a check of a rule, runs its command for them, and then lets the rule sleep
+    fn on_changes(&mut self, rule: usize, differences: &[FSDifference]) {
+        if self.rules.len() > 1 {
+            info!(
+                "Detected {} changed paths for {}",
+                differences.len(),
+                self.rules[rule].rule.describe()
+            );
+        } else {
+            info!("Detected {} changed paths", differences.len());
+        }
+        for diff in differences {
+            log_difference(diff);
+        }
+        trace!("Updated paths:\n{}", self.rules[rule].watched_fs);
+
+        for diff in differences {
+            match diff {
+                FSDifference::New { .. } => self.change_counts[0] += 1,
+                FSDifference::Modified { .. } => self.change_counts[1] += 1,
+                FSDifference::Deleted { .. } => self.change_counts[2] += 1,
+            }
+        }
+
+        if self.rules[rule].batch {
+            let command = self.get_batch_command(rule, differences).unwrap();
+            let env = self.get_environment(None, differences);
+            let stdin = self.get_stdin(differences);
+            self.rules[rule].runner.run(command, &env, stdin);
+        } else if self.rules[rule].runner.restarts() {
+            // restarting once per change would stop the commands for the earlier changes straight away
+            let diff = differences.last().unwrap();
+            let command = self.get_command(rule, diff, differences);
+            let env = self.get_environment(Some(diff), differences);
+            let stdin = self.get_stdin(std::slice::from_ref(diff));
+            self.rules[rule].runner.run(command, &env, stdin);
+        } else {
+            for diff in differences {
+                let command = self.get_command(rule, diff, differences);
+                let env = self.get_environment(Some(diff), differences);
+                let stdin = self.get_stdin(std::slice::from_ref(diff));
+                self.rules[rule].runner.run(command, &env, stdin);
+            }
+        }
+
+        self.rules[rule].next_check = Instant::now() + self.rules[rule].sleep;
+    }
+
+    /// Creates an empty WatchedFS for finding paths
+    fn new_watched_fs(&self, size: usize) -> WatchedFS {
+        return WatchedFS::new(size)
+            .with_hash_limit(self.options.hash_limit)
+            .with_exclusions(Rc::clone(&self.options.exclusions));
+    }
+
+    /// Explores the file system for the paths of the `rules`. A path found by several rules is only checked once
+    fn explore(&self, rules: &[usize]) -> WatchedFS {
+        let size = rules
+            .iter()
+            .map(|&i| self.rules[i].watched_fs.len())
+            .sum::<usize>();
+        let mut watched_fs = self.new_watched_fs(size.max(rules.len()));
+
+        for &i in rules {
+            for explorer in self.rules[i].rule.explorers.iter() {
+                explorer.explore(&mut watched_fs);
+            }
+        }
+
+        return watched_fs;
+    }
+
+    /// Explores the paths of every rule for the first time. Returns how many paths were found
+    fn explore_initial(&mut self) -> usize {
+        let rules: Vec<usize> = (0..self.rules.len()).collect();
+        let found = self.explore(&rules);
+        let count = found.len();
+        debug!("Initial paths:\n{}", found);
+
+        for (i, watched_fs) in rules.iter().zip(self.split_found(found, &rules)) {
+            self.rules[*i].watched_fs = watched_fs;
+        }
+        return count;
+    }
+
+    /// Splits the paths `found` by exploring for the `rules` into the paths of each rule
+    fn split_found(&self, found: WatchedFS, rules: &[usize]) -> Vec<WatchedFS> {
+        if let [_] = rules {
+            return vec![found];
+        }
+
+        return rules
+            .iter()
+            .map(|&i| {
+                found.subset(
+                    found
+                        .paths()
+                        .filter(|path| self.rules[i].matches(Path::new(path))),
+                )
+            })
+            .collect();
+    }
+
+    /// Replaces the paths of the `rules` by exploring them again. Returns the differences for each rule
+    fn reexplore(&mut self, rules: &[usize]) -> Vec<Vec<FSDifference>> {
+        let found = self.explore(rules);
+        let mut differences = Vec::with_capacity(rules.len());
+
+        for (i, watched_fs) in rules.iter().zip(self.split_found(found, rules)) {
+            let rule = &mut self.rules[*i];
+            let prev_watched_fs = std::mem::replace(&mut rule.watched_fs, watched_fs);
+            differences.push(rule.watched_fs.compare_all(prev_watched_fs));
+
+            #[cfg(target_os = "linux")]
+            {
+                rule.pending.clear();
+                rule.reexplore = false;
+            }
+        }
+
+        return differences;
+    }
+
+    /// Keeps the paths that events were reported for until each rule is checked next
+    #[cfg(target_os = "linux")]
+    fn queue(&mut self, events: FSEvents) {
+        for rule in self.rules.iter_mut() {
+            rule.reexplore |= events.overflowed;
+            rule.pending.extend(events.paths.iter().cloned());
+        }
+    }
+
+    /// Updates the paths of the `rules` by checking only the paths that events were reported for, or by exploring
+    /// them again if events may have been missed. Returns the differences for each rule
+    #[cfg(target_os = "linux")]
+    fn refresh(&mut self, rules: &[usize]) -> Vec<Vec<FSDifference>> {
+        let reexplored: Vec<usize> = rules
+            .iter()
+            .copied()
+            .filter(|&i| self.rules[i].reexplore)
+            .collect();
+        let mut reexplored_differences = self.reexplore(&reexplored).into_iter();
+
+        // the paths to check for each rule which matches them
+        let mut rule_paths: Vec<Vec<String>> = Vec::with_capacity(rules.len());
+        for &i in rules {
+            let rule = &mut self.rules[i];
+            let mut paths: HashSet<String> = HashSet::with_capacity(rule.pending.len());
+
+            for path in std::mem::take(&mut rule.pending) {
+                if path.as_os_str().is_empty() {
+                    // the current working directory itself is never found by an explorer
+                    continue;
+                }
+
+                if std::fs::symlink_metadata(&path).is_err() {
+                    // a directory that was moved away doesn't report events for the paths inside it
+                    paths.extend(
+                        rule.watched_fs
+                            .paths()
+                            .filter(|found| Path::new(found).starts_with(&path))
+                            .cloned(),
+                    );
+                }
+
+                paths.insert(path.to_string_lossy().to_string());
+            }
+
+            rule_paths.push(paths.into_iter().collect());
+        }
+
+        // a path that several rules match is only checked once
+        let mut found = self.new_watched_fs(rule_paths.iter().map(Vec::len).sum());
+        let mut rule_matches: Vec<Vec<&String>> = Vec::with_capacity(rules.len());
+        for (&i, paths) in rules.iter().zip(rule_paths.iter()) {
+            let matches: Vec<&String> = paths
+                .iter()
+                .filter(|path| self.rules[i].matches(Path::new(path)))
+                .collect();
+            for path in matches.iter() {
+                found.find(&PathBuf::from(path));
+            }
+            rule_matches.push(matches);
+        }
+
+        let mut differences = Vec::with_capacity(rules.len());
+        for ((&i, paths), matches) in rules.iter().zip(rule_paths.iter()).zip(rule_matches) {
+            if reexplored.contains(&i) {
+                differences.push(reexplored_differences.next().unwrap());
+                continue;
+            }
+
+            let rule = &mut self.rules[i];
+            let prev_watched_fs = rule.watched_fs.take(paths.iter());
+            let new_watched_fs = found.subset(matches.into_iter());
+            differences.push(new_watched_fs.compare_all(prev_watched_fs));
+            rule.watched_fs.extend(new_watched_fs);
+        }
+
+        return differences;
+    }
+
+    /// Returns the command to run for a single changed path. Substitutes variables where
+    /// available:
+    /// - $path | ${path}:           the path that changed
+    /// - $diff | ${diff}:           new | modified | deleted
+    /// - $mtime | ${mtime}:         the modified time of the path (note this will not be available for deleted diffs)
+    /// - $old_mtime | ${old_mtime}: the modified time of the path before it changed (not available for new diffs)
+    /// - $dir | ${dir}:             the directory containing the path
+    /// - $basename | ${basename}:   the file name of the path
+    /// - $stem | ${stem}:           the file name without its extension
+    /// - $ext | ${ext}:             the extension of the path without the dot, or nothing
+    /// - $relpath | ${relpath}:     the path relative to the working directory
+    /// - $abspath | ${abspath}:     the absolute path
+    /// - $size | ${size}:           the size of the path in bytes (not available for deleted diffs)
+    /// - $pattern | ${pattern}:     the explorer's path or pattern that matched the path
+    ///
+    /// Times are formatted with the `%`-prefixed strftime modifier or the `rfc3339` modifier, e.g. `${mtime:%s}`.
+    ///
+    /// For rules with captures, also substitutes what the wildcards of the pattern which found the path matched:
+    /// - {name}:      what the named capture `{name}` matched
+    /// - {1}, {2}, …: what each wildcard matched, in order. Named captures are counted too
+    ///
+    /// Captures are escaped with a backslash like variables, e.g. `\{name}`, and `${1}` is always left for the shell.
+    ///
+    /// Along with the list variables described by `::get_batch_command` for all the `changes` in the same check
+    fn get_command(
+        &self,
+        rule: usize,
+        diff: &FSDifference,
+        changes: &[FSDifference],
+    ) -> CommandLine {
+        let path = Path::new(diff.path());
+        let explorers = &self.rules[rule].rule.explorers;
+        let captures = match self.rules[rule].rule.captures {
+            true => explorers
+                .iter()
+                .find_map(|explorer| explorer.captures(path)),
+            false => None,
+        };
+        return self.substitute(rule, Some(diff), changes, captures.as_ref());
+    }
+
+    /// Returns the command to run once for all `changes` in a check, if a command should run. Substitutes variables
+    /// where available:
+    /// - $paths | ${paths}:                   every changed path, joined by the separator
+    /// - $new_paths | ${new_paths}:           every new path, joined by the separator
+    /// - $modified_paths | ${modified_paths}: every modified path, joined by the separator
+    /// - $deleted_paths | ${deleted_paths}:   every deleted path, joined by the separator
+    /// - $count | ${count}:                   the number of changed paths
+    ///
+    /// Any variable can be shell quoted with the `q` modifier, e.g. `${path:q}`
+    fn get_batch_command(&self, rule: usize, changes: &[FSDifference]) -> Option<CommandLine> {
+        if changes.is_empty() {
+            return None;
+        }
+
+        return Some(self.substitute(rule, None, changes, None));
+    }
+
+    /// Returns the environment variables which describe the changes to the command, so scripts can read them without
+    /// any quoting. Variables without a value are removed, e.g. `JFSWATCH_MTIME` for a deleted path:
+    /// - JFSWATCH_DIFF:      new | modified | deleted
+    /// - JFSWATCH_PATH:      the path that changed
+    /// - JFSWATCH_MTIME:     the modified time of the path
+    /// - JFSWATCH_OLD_MTIME: the modified time of the path before it changed
+    /// - JFSWATCH_PATHS:     every path that changed in the same check, one per line
+    /// - JFSWATCH_COUNT:     the number of paths that changed in the same check
+    ///
+    /// The single-change variables are always removed when `diff` is `None`, e.g. in batch mode
+    fn get_environment(
+        &self,
+        diff: Option<&FSDifference>,
+        changes: &[FSDifference],
+    ) -> Vec<(&'static str, Option<String>)> {
+        let format_mtime = |mtime: &DateTime<Local>| mtime.format(LOCAL_DATE_FORMAT).to_string();
+        let paths: Vec<&str> = changes.iter().map(FSDifference::path).collect();
+
+        return vec![
+            ("JFSWATCH_DIFF", diff.map(|diff| diff.kind().to_string())),
+            (
+                "JFSWATCH_PATH",
+                diff.map(FSDifference::path).map(str::to_string),
+            ),
+            (
+                "JFSWATCH_MTIME",
+                diff.and_then(FSDifference::mtime).map(format_mtime),
+            ),
+            (
+                "JFSWATCH_OLD_MTIME",
+                diff.and_then(FSDifference::old_mtime).map(format_mtime),
+            ),
+            ("JFSWATCH_PATHS", Some(paths.join("\n"))),
+            ("JFSWATCH_COUNT", Some(changes.len().to_string())),
+        ];
+    }
+
+    /// Returns the paths of the `changes` to write to the command's stdin, if they should be written
+    fn get_stdin(&self, changes: &[FSDifference]) -> Option<Vec<u8>> {
+        let terminator = match self.options.stdin? {
+            StdinPaths::Lines => b'\n',
+            StdinPaths::Nul => b'\0',
+        };
+
+        let mut stdin = Vec::new();
+        for path in changes.iter().map(FSDifference::path) {
+            stdin.extend_from_slice(path.as_bytes());
+            stdin.push(terminator);
+        }
+        return Some(stdin);
+    }
+
+    /// Substitutes variables and the path's `captures` into the `rule`'s command. Without a shell, each argument is
+    /// substituted on its own, and an argument that is just a list variable like `$paths` becomes one argument per path
+    fn substitute(
+        &self,
+        rule: usize,
+        diff: Option<&FSDifference>,
+        changes: &[FSDifference],
+        captures: Option<&PathCaptures>,
+    ) -> CommandLine {
+        let cmd = &self.rules[rule].rule.cmd;
+        if self.options.shell {
+            return CommandLine::Shell(self.substitute_text(
+                rule,
+                &cmd.join(" "),
+                diff,
+                changes,
+                captures,
+            ));
+        }
+
+        let mut args = Vec::with_capacity(cmd.len());
+        for arg in cmd.iter() {
+            let list = self
+                .substitution_pattern
+                .captures(arg)
+                .filter(|caps| caps.get(0).unwrap().as_str() == arg)
+                .and_then(|caps| {
+                    let substitution = split_substitution(&caps);
+                    if !substitution.prefix.is_empty() || substitution.capture {
+                        return None;
+                    }
+                    return list_filter(substitution.name);
+                });
+
+            match list {
+                Some(include) => args.extend(
+                    changes
+                        .iter()
+                        .filter(|change| include(change))
+                        .map(FSDifference::path)
+                        .map(str::to_string),
+                ),
+                None => args.push(self.substitute_text(rule, arg, diff, changes, captures)),
+            }
+        }
+
+        return CommandLine::Exec(args);
+    }
+
+    /// Substitutes variables and the path's `captures` into the `text`. Variables without a value are left as-is,
+    /// e.g. single-change variables when `diff` is `None`, `$mtime` for a deleted path, or unknown variables meant for
+    /// the shell. Without `captures`, braces like `{name}` are always left as-is
+    fn substitute_text(
+        &self,
+        rule: usize,
+        text: &str,
+        diff: Option<&FSDifference>,
+        changes: &[FSDifference],
+        captures: Option<&PathCaptures>,
+    ) -> String {
+        return self
+            .substitution_pattern
+            .replace_all(text, |caps: &regex::Captures| {
+                let substitution = split_substitution(caps);
+                if substitution.capture && captures.is_none() {
+                    // e.g. batch commands, which don't run for a single path
+                    return caps.get(0).unwrap().as_str().to_string();
+                }
+
+                // escaped case - do not substitute
+                if substitution.prefix == "\\" {
+                    return substitution.variable.to_string();
+                }
+
+                let value = match captures {
+                    Some(captures) if substitution.capture => {
+                        captures.get(substitution.name).map(str::to_string)
+                    }
+                    _ => self.variable_value(
+                        rule,
+                        substitution.name,
+                        substitution.modifier,
+                        diff,
+                        changes,
+                    ),
+                };
+                return value.unwrap_or_else(|| substitution.variable.to_string());
+            })
+            .to_string();
+    }
+
+    /// The value of the variable called `name` with the `modifier` applied in the `rule`'s command, or `None` if it
+    /// has no value
+    fn variable_value(
+        &self,
+        rule: usize,
+        name: &str,
+        modifier: Option<&str>,
+        diff: Option<&FSDifference>,
+        changes: &[FSDifference],
+    ) -> Option<String> {
+        let quote = |value: &str| -> String {
+            return match modifier {
+                Some("q") => shell_quote(value),
+                _ => value.to_string(),
+            };
+        };
+
+        if let Some(include) = list_filter(name) {
+            return Some(
+                changes
+                    .iter()
+                    .filter(|change| include(change))
+                    .map(FSDifference::path)
+                    .map(quote)
+                    .collect::<Vec<String>>()
+                    .join(&self.options.separator),
+            );
+        }
+        if name == "count" {
+            return Some(quote(&changes.len().to_string()));
+        }
+
+        let diff = diff?;
+        let path = Path::new(diff.path());
+        let format_time = |time: &DateTime<Local>| -> String {
+            return match modifier {
+                Some("rfc3339") => time.to_rfc3339(),
+                Some(format) if format.starts_with('%') => time.format(format).to_string(),
+                _ => time.format(LOCAL_DATE_FORMAT).to_string(),
+            };
+        };
+
+        let value = match name {
+            "diff" => diff.kind().to_string(),
+            "path" => path.to_string_lossy().to_string(),
+            "mtime" => format_time(diff.mtime()?),
+            "old_mtime" => format_time(diff.old_mtime()?),
+            "dir" => match path.parent() {
+                Some(parent) if !parent.as_os_str().is_empty() => {
+                    parent.to_string_lossy().to_string()
+                }
+                _ => ".".to_string(),
+            },
+            "basename" => path.file_name()?.to_string_lossy().to_string(),
+            "stem" => path.file_stem()?.to_string_lossy().to_string(),
+            "ext" => path
+                .extension()
+                .map_or(String::new(), |ext| ext.to_string_lossy().to_string()),
+            "relpath" => {
+                let cwd = std::env::current_dir().ok()?;
+                path.strip_prefix(&cwd)
+                    .unwrap_or(path)
+                    .to_string_lossy()
+                    .to_string()
+            }
+            "abspath" => std::path::absolute(path)
+                .ok()?
+                .to_string_lossy()
+                .to_string(),
+            "size" => std::fs::metadata(path).ok()?.len().to_string(),
+            "pattern" => self.rules[rule]
+                .rule
+                .explorers
+                .iter()
+                .find(|explorer| explorer.matches(path))?
+                .pattern(),
+            _ => return None,
+        };
+
+        return Some(quote(&value));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+    use chrono::TimeZone;
+
+    use crate::runner::Signal;
+    use crate::{ExactExplorer, Explorer, GlobExplorer};
+
+    #[test]
+    fn given_all_valid_args_when_new_then_ok() {
+        let jfswatch = new_jfswatch(vec!["path"], vec!["echo", "hello"], WatchOptions::default());
+        assert!(jfswatch.is_ok());
+    }
+
+    #[test]
+    fn given_no_command_when_new_then_err() {
+        let jfswatch = new_jfswatch(vec!["path"], vec![], WatchOptions::default());
+        assert!(jfswatch.is_err());
+    }
+
+    #[rstest]
+    #[case(0.0)]
+    #[case(-1.0)]
+    fn given_non_positive_interval_when_new_then_err(#[case] interval: f32) {
+        let explorers: Vec<Box<dyn Explorer>> =
+            vec![Box::new(ExactExplorer::from_cli_arg("path").unwrap())];
+        let sleep = 0.1;
+        let cmd = vec!["echo".to_string(), "hello".to_string()];
+
+        let jfswatch = JFSWatch::new(
+            vec![Rule::new(explorers, cmd)],
+            interval,
+            sleep,
+            WatchOptions::default(),
+        );
+        assert!(jfswatch.is_err());
+    }
+
+    #[rstest]
+    #[case(0.0)]
+    #[case(-1.0)]
+    fn given_non_positive_sleep_when_new_then_err(#[case] sleep: f32) {
+        let explorers: Vec<Box<dyn Explorer>> =
+            vec![Box::new(ExactExplorer::from_cli_arg("path").unwrap())];
+        let interval = 0.1;
+        let cmd = vec!["echo".to_string(), "hello".to_string()];
+
+        let jfswatch = JFSWatch::new(
+            vec![Rule::new(explorers, cmd)],
+            interval,
+            sleep,
+            WatchOptions::default(),
+        );
+        assert!(jfswatch.is_err());
+    }
+
+    #[test]
+    fn given_no_explorers_when_new_then_err() {
+        let jfswatch = new_jfswatch(vec![], vec!["echo", "hello"], WatchOptions::default());
+        assert!(jfswatch.is_err());
+    }
+
+    /// A watch of the glob `patterns` which runs the `command` with the `options`, checking every 0.1 seconds
+    fn new_jfswatch(
+        patterns: Vec<&str>,
+        command: Vec<&str>,
+        options: WatchOptions,
+    ) -> Result<JFSWatch, String> {
+        let explorers = patterns
+            .iter()
+            .map(|pattern| -> Box<dyn Explorer> {
+                return Box::new(GlobExplorer::from_cli_arg(pattern).unwrap());
+            })
+            .collect();
+        let cmd = command.iter().map(|arg| arg.to_string()).collect();
+        return JFSWatch::new(vec![Rule::new(explorers, cmd)], 0.1, 0.1, options);
+    }
+
+    fn batch_options(separator: &str) -> WatchOptions {
+        return WatchOptions {
+            batch: true,
+            separator: separator.to_string(),
+            ..WatchOptions::default()
+        };
+    }
+
+    fn mock_changes() -> Vec<FSDifference> {
+        let mtime = chrono::Local::now();
+        return vec![
+            FSDifference::Modified {
+                path: "a.txt".to_string(),
+                mtime,
+                old_mtime: mtime,
+            },
+            FSDifference::New {
+                path: "b.txt".to_string(),
+                mtime,
+            },
+            FSDifference::Deleted {
+                path: "c.txt".to_string(),
+                old_mtime: chrono::Local::now(),
+            },
+            FSDifference::New {
+                path: "d.txt".to_string(),
+                mtime,
+            },
+        ];
+    }
+
+    #[test]
+    fn given_new_diff_when_get_command_then_substitutes_all() {
+        let jfswatch = new_jfswatch(
+            vec!["path"],
+            vec!["echo", "$diff", "$path was", "created at $mtime"],
+            WatchOptions::default(),
+        )
+        .unwrap();
+        let mtime = chrono::Local::now();
+        let diff = FSDifference::New {
+            path: "mock/path".to_string(),
+            mtime,
+        };
+        let command = jfswatch
+            .get_command(0, &diff, std::slice::from_ref(&diff))
+            .to_string();
+
+        assert_eq!(
+            command,
+            format!(
+                "echo new mock/path was created at {}",
+                mtime.format(LOCAL_DATE_FORMAT)
+            )
+        );
+    }
+
+    #[test]
+    fn given_modified_diff_when_get_command_then_substitutes_all() {
+        let jfswatch = new_jfswatch(
+            vec!["path"],
+            vec!["echo", "{ diff: $diff, path: $path, mtime: $mtime }"],
+            WatchOptions::default(),
+        )
+        .unwrap();
+        let mtime = chrono::Local::now();
+        let diff = FSDifference::Modified {
+            path: "mock/path".to_string(),
+            mtime,
+            old_mtime: mtime,
+        };
+        let command = jfswatch
+            .get_command(0, &diff, std::slice::from_ref(&diff))
+            .to_string();
+
+        assert_eq!(
+            command,
+            format!(
+                "echo {{ diff: modified, path: mock/path, mtime: {} }}",
+                mtime.format(LOCAL_DATE_FORMAT)
+            )
+        );
+    }
+
+    #[test]
+    fn given_deleted_diff_when_get_command_then_substitutes_all() {
+        let jfswatch = new_jfswatch(
+            vec!["path"],
+            vec!["echo", "{ diff: $diff }", "path: $path\nmtime: $mtime"],
+            WatchOptions::default(),
+        )
+        .unwrap();
+        let diff = FSDifference::Deleted {
+            path: "mock/path".to_string(),
+            old_mtime: chrono::Local::now(),
+        };
+        let command = jfswatch
+            .get_command(0, &diff, std::slice::from_ref(&diff))
+            .to_string();
+
+        assert_eq!(
+            command,
+            format!("echo {{ diff: deleted }} path: mock/path\nmtime: $mtime")
+        );
+    }
+
+    #[rstest]
+    #[case(FSDifference::New { path: "mock/path".to_string(), mtime: chrono::Local::now() })]
+    #[case(FSDifference::Modified { path: "mock/path".to_string(), mtime: chrono::Local::now(), old_mtime: chrono::Local::now() })]
+    #[case(FSDifference::Deleted { path: "mock/path".to_string(), old_mtime: chrono::Local::now() })]
+    fn given_any_diff_when_get_command_then_ignores_escaped_variables(#[case] diff: FSDifference) {
+        let jfswatch = new_jfswatch(
+            vec!["path"],
+            vec!["echo $path \\$path \\${path} ${path}"],
+            WatchOptions::default(),
+        )
+        .unwrap();
+        let command = jfswatch
+            .get_command(0, &diff, std::slice::from_ref(&diff))
+            .to_string();
+
+        assert_eq!(command, "echo mock/path $path ${path} mock/path");
+    }
+
+    #[test]
+    fn given_batch_mode_with_single_change_variable_when_new_then_err() {
+        let jfswatch = new_jfswatch(vec!["path"], vec!["echo", "${path}"], batch_options(" "));
+        assert!(jfswatch.is_err());
+    }
+
+    #[test]
+    fn given_batch_mode_with_escaped_single_change_variable_when_new_then_ok() {
+        let jfswatch = new_jfswatch(
+            vec!["path"],
+            vec!["echo", "\\$path", "$paths"],
+            batch_options(" "),
+        );
+        assert!(jfswatch.is_ok());
+    }
+
+    #[test]
+    fn given_no_changes_when_get_batch_command_then_none() {
+        let jfswatch =
+            new_jfswatch(vec!["path"], vec!["echo", "$paths"], batch_options(" ")).unwrap();
+
+        if jfswatch.get_batch_command(0, &[]).is_some() {
+            panic!("Expected None");
+        }
+    }
+
+    #[test]
+    fn given_many_changes_when_get_batch_command_then_substitutes_all_lists() {
+        let jfswatch = new_jfswatch(
+            vec!["path"],
+            vec![
+                "echo",
+                "$count: $paths | ${new_paths} | $modified_paths | $deleted_paths",
+            ],
+            batch_options(","),
+        )
+        .unwrap();
+        let command = jfswatch
+            .get_batch_command(0, &mock_changes())
+            .unwrap()
+            .to_string();
+
+        assert_eq!(
+            command,
+            "echo 4: a.txt,b.txt,c.txt,d.txt | b.txt,d.txt | a.txt | c.txt"
+        );
+    }
+
+    #[test]
+    fn given_many_changes_when_get_command_then_substitutes_single_change_and_lists() {
+        let jfswatch = new_jfswatch(
+            vec!["path"],
+            vec!["echo", "$diff $path of $count: $paths"],
+            WatchOptions::default(),
+        )
+        .unwrap();
+        let changes = mock_changes();
+        let command = jfswatch.get_command(0, &changes[1], &changes).to_string();
+
+        assert_eq!(command, "echo new b.txt of 4: a.txt b.txt c.txt d.txt");
+    }
+
+    #[test]
+    fn given_no_matching_changes_when_get_batch_command_then_list_is_empty() {
+        let jfswatch = new_jfswatch(
+            vec!["path"],
+            vec!["echo", "[$deleted_paths]"],
+            batch_options(" "),
+        )
+        .unwrap();
+        let changes = vec![FSDifference::New {
+            path: "a.txt".to_string(),
+            mtime: chrono::Local::now(),
+        }];
+        let command = jfswatch.get_batch_command(0, &changes).unwrap().to_string();
+
+        assert_eq!(command, "echo []");
+    }
+
+    #[test]
+    fn given_variables_without_leading_space_when_get_command_then_substitutes_all() {
+        let jfswatch = new_jfswatch(
+            vec!["path"],
+            vec!["$path", "file=$path", "[$paths]"],
+            WatchOptions::default(),
+        )
+        .unwrap();
+        let diff = FSDifference::Deleted {
+            path: "mock/path".to_string(),
+            old_mtime: chrono::Local::now(),
+        };
+        let command = jfswatch
+            .get_command(0, &diff, std::slice::from_ref(&diff))
+            .to_string();
+
+        assert_eq!(command, "mock/path file=mock/path [mock/path]");
+    }
+
+    #[rstest]
+    #[case(Backend::Inotify, Some(10.0), true)]
+    #[case(Backend::Inotify, None, true)]
+    #[case(Backend::Inotify, Some(0.0), false)]
+    #[case(Backend::Inotify, Some(-1.0), false)]
+    #[case(Backend::Poll, Some(10.0), false)]
+    fn given_reconcile_option_when_new_then_requires_positive_seconds_and_inotify(
+        #[case] backend: Backend,
+        #[case] reconcile: Option<f32>,
+        #[case] expected_ok: bool,
+    ) {
+        let options = WatchOptions {
+            backend,
+            reconcile,
+            ..WatchOptions::default()
+        };
+        let jfswatch = new_jfswatch(vec!["path"], vec!["echo", "hello"], options);
+        assert_eq!(jfswatch.is_ok(), expected_ok);
+    }
+
+    #[rstest]
+    #[case(5.0, true)]
+    #[case(0.0, true)]
+    #[case(-1.0, false)]
+    #[case(f32::NAN, false)]
+    fn given_stop_timeout_when_new_then_requires_non_negative_seconds(
+        #[case] timeout: f32,
+        #[case] expected_ok: bool,
+    ) {
+        let options = WatchOptions {
+            stop: StopOptions {
+                timeout,
+                ..StopOptions::default()
+            },
+            ..WatchOptions::default()
+        };
+        let jfswatch = new_jfswatch(vec!["path"], vec!["echo", "hello"], options);
+        assert_eq!(jfswatch.is_ok(), expected_ok);
+    }
+
+    #[rstest]
+    #[case(Backend::Poll)]
+    #[case(Backend::Inotify)]
+    fn given_shutdown_requested_when_watch_then_returns_signal_exit_status(
+        #[case] backend: Backend,
+    ) {
+        let options = WatchOptions {
+            backend,
+            ..WatchOptions::default()
+        };
+        let mut jfswatch = new_jfswatch(vec!["path"], vec!["echo", "hello"], options).unwrap();
+        let shutdown = jfswatch.shutdown.clone();
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(200));
+            shutdown.request(Signal(signal_hook::consts::SIGINT));
+        });
 
-/// Executes the specified command
-fn run_command(command: String) {
-    let shell = std::env::var("SHELL").unwrap_or("sh".to_string());
+        let status = jfswatch.watch();
 
-    info!("$ {}", command);
-    info!("\n{}", "-".repeat(80));
+        assert_eq!(status, 130);
+    }
 
-    let status = Command::new(&shell)
-        .args(["-c", &command])
-        .stderr(std::process::Stdio::inherit())
-        .stdout(std::process::Stdio::inherit())
-        .stdin(std::process::Stdio::inherit())
-        .status();
+    #[test]
+    fn given_quote_modifier_when_get_command_then_values_are_shell_quoted() {
+        let jfswatch = new_jfswatch(
+            vec!["path"],
+            vec!["cat", "${path:q}", "${paths:q}", "${diff:q}"],
+            WatchOptions::default(),
+        )
+        .unwrap();
+        let diff = FSDifference::Deleted {
+            path: "a; rm -rf ~".to_string(),
+            old_mtime: chrono::Local::now(),
+        };
+        let changes = vec![
+            FSDifference::Deleted {
+                path: "a; rm -rf ~".to_string(),
+                old_mtime: chrono::Local::now(),
+            },
+            FSDifference::Deleted {
+                path: "plain.txt".to_string(),
+                old_mtime: chrono::Local::now(),
+            },
+        ];
 
-    info!("\n{}", "-".repeat(80));
+        let command = jfswatch.get_command(0, &diff, &changes);
 
-    match status {
-        Ok(status) => {
-            info!("... Exited with status: {}", status);
-        }
-        Err(error) => {
-            error!("... Error running command: {}", error);
-        }
+        assert_eq!(
+            command,
+            CommandLine::Shell("cat 'a; rm -rf ~' 'a; rm -rf ~' plain.txt deleted".to_string())
+        );
     }
-}
 
-/// Main data structure to maintain the state of the JFSWatch application
-pub struct JFSWatch {
-    /// How to discover paths on the file system
-    explorers: Vec<Box<dyn Explorer>>,
+    #[rstest]
+    #[case(vec!["cat", "${path:x}"], true)]
+    #[case(vec!["cat", "${path:}"], true)]
+    #[case(vec!["cat", "\\${path:x}"], false)]
+    #[case(vec!["cat", "${path:q}"], false)]
+    fn given_modifier_when_new_then_rejects_unknown_modifiers(
+        #[case] command: Vec<&str>,
+        #[case] expected_err: bool,
+    ) {
+        let jfswatch = new_jfswatch(vec!["path"], command, WatchOptions::default());
+        assert_eq!(jfswatch.is_err(), expected_err);
+    }
 
-    /// How long to wait between non-changing checks before exploring again
-    interval: Duration,
+    #[test]
+    fn given_no_shell_when_get_command_then_substitutes_each_argument_on_its_own() {
+        let options = WatchOptions {
+            shell: false,
+            ..WatchOptions::default()
+        };
+        let jfswatch = new_jfswatch(
+            vec!["path"],
+            vec!["cp", "$path", "backup/$diff $path", "\\$path"],
+            options,
+        )
+        .unwrap();
+        let diff = FSDifference::Deleted {
+            path: "with space; rm -rf ~".to_string(),
+            old_mtime: chrono::Local::now(),
+        };
 
-    /// How long to wait after running the command before exploring again
-    sleep: Duration,
+        let command = jfswatch.get_command(0, &diff, std::slice::from_ref(&diff));
 
-    /// The command to run when an explored path changes
-    cmd: Vec<String>,
+        assert_eq!(
+            command,
+            CommandLine::Exec(vec![
+                "cp".to_string(),
+                "with space; rm -rf ~".to_string(),
+                "backup/deleted with space; rm -rf ~".to_string(),
+                "$path".to_string(),
+            ])
+        );
+    }
 
-    /// For substituting variables into the command
-    substitution_pattern: regex::Regex,
-}
+    #[test]
+    fn given_no_shell_when_get_batch_command_then_list_arguments_become_one_argument_per_path() {
+        let options = WatchOptions {
+            shell: false,
+            ..batch_options(",")
+        };
+        let jfswatch = new_jfswatch(
+            vec!["path"],
+            vec![
+                "rustfmt",
+                "$modified_paths",
+                "${paths}",
+                "--",
+                "all=$paths",
+                "$count",
+            ],
+            options,
+        )
+        .unwrap();
 
-impl JFSWatch {
-    pub fn new(
-        explorers: Vec<Box<dyn Explorer>>,
-        interval: f32,
-        sleep: f32,
-        cmd: Vec<String>,
-    ) -> Result<Self, String> {
-        if cmd.len() == 0 {
-            return Err("No command was given".to_string());
-        }
-        if interval <= 0.0 {
-            return Err("Interval must be a positive number of seconds".to_string());
-        }
-        if sleep <= 0.0 {
-            return Err("Sleep must be a positive number of seconds".to_string());
-        }
-        if explorers.len() == 0 {
-            return Err("Empty path discovery list".to_string());
-        }
+        let command = jfswatch.get_batch_command(0, &mock_changes()).unwrap();
 
-        return Ok(JFSWatch {
-            explorers,
-            cmd,
-            interval: Duration::from_secs_f32(interval),
-            sleep: Duration::from_secs_f32(sleep),
-            substitution_pattern: regex::Regex::new(r".?\$(\{(diff|path|mtime)\}|diff|path|mtime)")
-                .unwrap(),
-        });
+        let expected: Vec<String> = [
+            "rustfmt",
+            "a.txt",
+            "a.txt",
+            "b.txt",
+            "c.txt",
+            "d.txt",
+            "--",
+            "all=a.txt,b.txt,c.txt,d.txt",
+            "4",
+        ]
+        .iter()
+        .map(|arg| arg.to_string())
+        .collect();
+        assert_eq!(command, CommandLine::Exec(expected));
     }
 
-    /// The main loop for checking the file system and running the specified command (blocking call)
-    pub fn watch(&mut self) {
-        let mut prev_fs_watch = self.explore(None);
-        info!("Found {} initial paths", prev_fs_watch.len());
-        debug!("Initial paths:\n{}", prev_fs_watch);
-
-        sleep(self.interval);
+    #[test]
+    fn given_no_shell_with_quote_modifier_when_new_then_err() {
+        let options = WatchOptions {
+            shell: false,
+            ..WatchOptions::default()
+        };
+        let jfswatch = new_jfswatch(vec!["path"], vec!["cat", "${path:q}"], options);
+        assert!(jfswatch.is_err());
+    }
 
-        loop {
-            let new_fs_watch = self.explore(Some(prev_fs_watch.len()));
+    #[test]
+    fn given_modified_diff_when_get_environment_then_describes_change_and_check() {
+        let jfswatch = new_jfswatch(vec!["path"], vec!["true"], WatchOptions::default()).unwrap();
+        let changes = mock_changes();
 
-            match new_fs_watch.compare(prev_fs_watch) {
-                FSDifference::Unchanged => {
-                    debug!("No changes in {} paths", new_fs_watch.len());
-                    sleep(self.interval);
-                }
-                changed => {
-                    match changed {
-                        FSDifference::Modified {
-                            ref path,
-                            ref mtime,
-                        } => {
-                            info!(
-                                "'{}' was modified at {}",
-                                path,
-                                mtime.format(LOCAL_DATE_FORMAT)
-                            )
-                        }
-                        FSDifference::New {
-                            ref path,
-                            ref mtime,
-                        } => info!(
-                            "'{}' is new since {}",
-                            path,
-                            mtime.format(LOCAL_DATE_FORMAT)
-                        ),
-                        FSDifference::Deleted { ref path } => info!("'{}' was deleted", path),
-                        FSDifference::Unchanged => unreachable!(),
-                    }
-                    trace!("Updated paths:\n{}", new_fs_watch);
-                    let command = self.get_command(&changed).unwrap();
-                    run_command(command);
-                    sleep(self.sleep);
-                }
-            }
+        let env = jfswatch.get_environment(Some(&changes[0]), &changes);
 
-            prev_fs_watch = new_fs_watch;
-        }
+        let mtime = changes[0]
+            .mtime()
+            .unwrap()
+            .format(LOCAL_DATE_FORMAT)
+            .to_string();
+        assert_eq!(
+            env,
+            vec![
+                ("JFSWATCH_DIFF", Some("modified".to_string())),
+                ("JFSWATCH_PATH", Some("a.txt".to_string())),
+                ("JFSWATCH_MTIME", Some(mtime.clone())),
+                ("JFSWATCH_OLD_MTIME", Some(mtime)),
+                (
+                    "JFSWATCH_PATHS",
+                    Some("a.txt\nb.txt\nc.txt\nd.txt".to_string())
+                ),
+                ("JFSWATCH_COUNT", Some("4".to_string())),
+            ]
+        );
     }
 
-    /// Explores the file system for paths and finds their modified times
-    fn explore(&self, prev_size: Option<usize>) -> WatchedFS {
-        let mut watched_fs = WatchedFS::new(prev_size.unwrap_or(self.explorers.len()));
+    #[test]
+    fn given_deleted_diff_when_get_environment_then_mtime_is_removed() {
+        let jfswatch = new_jfswatch(vec!["path"], vec!["true"], WatchOptions::default()).unwrap();
+        let changes = mock_changes();
 
-        for explorer in self.explorers.iter() {
-            explorer.explore(&mut watched_fs);
-        }
+        let env = jfswatch.get_environment(Some(&changes[2]), &changes);
 
-        return watched_fs;
+        assert_eq!(env[0], ("JFSWATCH_DIFF", Some("deleted".to_string())));
+        assert_eq!(env[2], ("JFSWATCH_MTIME", None));
+        assert!(env[3].1.is_some());
     }
 
-    /// Returns the command to run, if a command should run. Substitutes variables where available:
-    /// - $path | ${path}:   the path that changed
-    /// - $diff | ${diff}:   new | modified | deleted
-    /// - $mtime | ${mtime}: the modified time of the path (note this will not be available for deleted diffs)
-    fn get_command(&self, diff: &FSDifference) -> Option<String> {
-        if let FSDifference::Unchanged = diff {
-            return None;
-        }
+    #[test]
+    fn given_batch_when_get_environment_then_single_change_variables_are_removed() {
+        let jfswatch = new_jfswatch(vec!["path"], vec!["true"], batch_options(" ")).unwrap();
+        let changes = mock_changes();
 
-        let mut command = self.cmd.join(" ");
+        let env = jfswatch.get_environment(None, &changes);
 
-        command = self
-            .substitution_pattern
-            .replace_all(&command, |caps: &regex::Captures| {
-                let first_char = caps.get(0).unwrap().as_str().chars().next().unwrap();
+        assert!(env[..4].iter().all(|(_, value)| value.is_none()));
+        assert_eq!(env[5], ("JFSWATCH_COUNT", Some("4".to_string())));
+    }
 
-                // escaped case - do not substitute
-                if first_char == '\\' {
-                    return caps.get(0).unwrap().as_str()[1..].to_string();
-                }
+    #[rstest]
+    #[case(None, None)]
+    #[case(Some(StdinPaths::Lines), Some("a.txt\nb.txt\nc.txt\nd.txt\n"))]
+    #[case(Some(StdinPaths::Nul), Some("a.txt\0b.txt\0c.txt\0d.txt\0"))]
+    fn given_stdin_option_when_get_stdin_then_terminates_each_path(
+        #[case] stdin: Option<StdinPaths>,
+        #[case] expected: Option<&str>,
+    ) {
+        let options = WatchOptions {
+            stdin,
+            ..WatchOptions::default()
+        };
+        let jfswatch = new_jfswatch(vec!["path"], vec!["xargs", "-0", "ls"], options).unwrap();
 
-                let replacement = match caps
-                    .get(0)
-                    .unwrap()
-                    .as_str()
-                    .trim_matches(['{', '}', ' ', '$'])
-                {
-                    "diff" => match diff {
-                        FSDifference::Modified { .. } => "modified".to_string(),
-                        FSDifference::New { .. } => "new".to_string(),
-                        FSDifference::Deleted { .. } => "deleted".to_string(),
-                        FSDifference::Unchanged => unreachable!(),
-                    },
-                    "path" => match diff {
-                        FSDifference::Modified { path, .. } => path.to_string(),
-                        FSDifference::New { path, .. } => path.to_string(),
-                        FSDifference::Deleted { path } => path.to_string(),
-                        FSDifference::Unchanged => unreachable!(),
-                    },
-                    "mtime" => {
-                        match diff {
-                            FSDifference::Modified { mtime, .. } => {
-                                mtime.format(LOCAL_DATE_FORMAT).to_string()
-                            }
-                            FSDifference::New { mtime, .. } => {
-                                mtime.format(LOCAL_DATE_FORMAT).to_string()
-                            }
-                            FSDifference::Deleted { .. } => {
-                                // no mtime for deleted (use what was previously there)
-                                caps.get(0).unwrap().as_str()[1..].to_string()
-                            }
-                            FSDifference::Unchanged => unreachable!(),
-                        }
-                    }
-                    _ => panic!("Unknown substitution target on {:?}", caps),
-                };
+        let written = jfswatch.get_stdin(&mock_changes());
 
-                return format!("{}{}", first_char, replacement);
-            })
+        assert_eq!(written, expected.map(|e| e.as_bytes().to_vec()));
+    }
+
+    #[test]
+    fn given_path_when_get_command_then_substitutes_derived_variables() {
+        let jfswatch = new_jfswatch(
+            vec!["Cargo.toml", "src/**/*.{rs,gz}"],
+            vec!["$dir|$basename|$stem|$ext|$relpath|${pattern}"],
+            WatchOptions::default(),
+        )
+        .unwrap();
+        let diff = FSDifference::Deleted {
+            path: "src/nested/archive.tar.gz".to_string(),
+            old_mtime: chrono::Local::now(),
+        };
+
+        let command = jfswatch
+            .get_command(0, &diff, std::slice::from_ref(&diff))
             .to_string();
 
-        return Some(command);
+        assert_eq!(
+            command,
+            "src/nested|archive.tar.gz|archive.tar|gz|src/nested/archive.tar.gz|src/**/*.{rs,gz}"
+        );
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use rstest::rstest;
+    #[test]
+    fn given_file_without_dir_or_extension_when_get_command_then_substitutes_defaults() {
+        let jfswatch = new_jfswatch(
+            vec!["path"],
+            vec!["[$dir]", "[$ext]", "[$pattern]"],
+            WatchOptions::default(),
+        )
+        .unwrap();
+        let diff = FSDifference::Deleted {
+            path: "path".to_string(),
+            old_mtime: chrono::Local::now(),
+        };
 
-    use super::*;
-    use crate::ExactExplorer;
+        let command = jfswatch
+            .get_command(0, &diff, std::slice::from_ref(&diff))
+            .to_string();
+
+        assert_eq!(command, "[.] [] [path]");
+    }
 
     #[test]
-    fn given_all_valid_args_when_new_then_ok() {
-        let explorers: Vec<Box<dyn Explorer>> = vec![Box::new(ExactExplorer::from_cli_arg("path"))];
-        let interval = 0.1;
-        let sleep = 0.1;
-        let cmd = vec!["echo".to_string(), "hello".to_string()];
+    fn given_existing_file_when_get_command_then_substitutes_size_and_absolute_paths() {
+        let tmp = tempfile::tempdir_in(".").unwrap();
+        let path = std::path::Path::new(tmp.path().file_name().unwrap()).join("file.txt");
+        std::fs::write(&path, "12345").unwrap();
+        let absolute = std::env::current_dir().unwrap().join(&path);
+        let jfswatch = new_jfswatch(
+            vec!["path"],
+            vec!["$size", "${abspath}", "$relpath"],
+            WatchOptions::default(),
+        )
+        .unwrap();
+        let diff = FSDifference::New {
+            path: absolute.to_string_lossy().to_string(),
+            mtime: chrono::Local::now(),
+        };
 
-        let jfswatch = JFSWatch::new(explorers, interval, sleep, cmd);
-        assert!(jfswatch.is_ok());
+        let command = jfswatch
+            .get_command(0, &diff, std::slice::from_ref(&diff))
+            .to_string();
+
+        assert_eq!(
+            command,
+            format!(
+                "5 {} {}",
+                absolute.to_string_lossy(),
+                path.to_string_lossy()
+            )
+        );
     }
 
     #[test]
-    fn given_no_command_when_new_then_err() {
-        let explorers: Vec<Box<dyn Explorer>> = vec![Box::new(ExactExplorer::from_cli_arg("path"))];
-        let interval = 0.1;
-        let sleep = 0.1;
-        let cmd = vec![];
+    fn given_time_modifiers_when_get_command_then_formats_times() {
+        let jfswatch = new_jfswatch(
+            vec!["path"],
+            vec![
+                "${mtime:%s}",
+                "${old_mtime:rfc3339}",
+                "${mtime:%Y}",
+                "$old_mtime",
+            ],
+            WatchOptions::default(),
+        )
+        .unwrap();
+        let mtime = chrono::Local.timestamp_opt(1_700_000_000, 0).unwrap();
+        let old_mtime = chrono::Local.timestamp_opt(1_600_000_000, 0).unwrap();
+        let diff = FSDifference::Modified {
+            path: "path".to_string(),
+            mtime,
+            old_mtime,
+        };
 
-        let jfswatch = JFSWatch::new(explorers, interval, sleep, cmd);
-        assert!(jfswatch.is_err());
+        let command = jfswatch
+            .get_command(0, &diff, std::slice::from_ref(&diff))
+            .to_string();
+
+        assert_eq!(
+            command,
+            format!(
+                "1700000000 {} {} {}",
+                old_mtime.to_rfc3339(),
+                mtime.format("%Y"),
+                old_mtime.format(LOCAL_DATE_FORMAT)
+            )
+        );
     }
 
     #[rstest]
-    #[case(0.0)]
-    #[case(-1.0)]
-    fn given_non_positive_interval_when_new_then_err(#[case] interval: f32) {
-        let explorers: Vec<Box<dyn Explorer>> = vec![Box::new(ExactExplorer::from_cli_arg("path"))];
-        let sleep = 0.1;
-        let cmd = vec!["echo".to_string(), "hello".to_string()];
+    #[case(vec!["echo", "${mtime:%s}", "${old_mtime:rfc3339}", "${size:q}"], true, true)]
+    #[case(vec!["echo", "$HOME", "${unknown}", "$1"], true, true)]
+    #[case(vec!["echo", "$HOME"], false, false)]
+    #[case(vec!["echo", "\\$HOME"], false, true)]
+    #[case(vec!["echo", "${pth}"], false, false)]
+    #[case(vec!["echo", "${path:%s}"], true, false)]
+    #[case(vec!["echo", "${mtime:rfc2822}"], true, false)]
+    #[case(vec!["echo", "${mtime:%Q}"], true, false)]
+    #[case(vec!["echo", "${pth:q}"], true, false)]
+    #[case(vec!["echo", "${mtme:%s}"], true, false)]
+    #[case(vec!["echo", "${HOME:-/root}", "${HOME:1}"], true, true)]
+    #[case(vec!["echo", "$JFSWATCH_PATH", "${JFSWATCH_COUNT}"], true, true)]
+    #[case(vec!["echo", "$JFSWATCH_PTH"], true, false)]
+    fn given_variables_when_new_then_validates_names_and_modifiers(
+        #[case] command: Vec<&str>,
+        #[case] shell: bool,
+        #[case] expected_ok: bool,
+    ) {
+        let options = WatchOptions {
+            shell,
+            ..WatchOptions::default()
+        };
+        let jfswatch = new_jfswatch(vec!["path"], command, options);
+        assert_eq!(jfswatch.is_ok(), expected_ok, "{:?}", jfswatch.err());
+    }
 
-        let jfswatch = JFSWatch::new(explorers, interval, sleep, cmd);
+    #[test]
+    fn given_batch_mode_with_derived_single_change_variable_when_new_then_err() {
+        let jfswatch = new_jfswatch(vec!["path"], vec!["echo", "$basename"], batch_options(" "));
         assert!(jfswatch.is_err());
     }
 
-    #[rstest]
-    #[case(0.0)]
-    #[case(-1.0)]
-    fn given_non_positive_sleep_when_new_then_err(#[case] sleep: f32) {
-        let explorers: Vec<Box<dyn Explorer>> = vec![Box::new(ExactExplorer::from_cli_arg("path"))];
-        let interval = 0.1;
-        let cmd = vec!["echo".to_string(), "hello".to_string()];
-
-        let jfswatch = JFSWatch::new(explorers, interval, sleep, cmd);
-        assert!(jfswatch.is_err());
+    fn jfswatch_with_rules(rules: Vec<&str>, options: WatchOptions) -> Result<JFSWatch, String> {
+        let rules = rules
+            .iter()
+            .map(|rule| Rule::from_cli_arg(rule, false).unwrap())
+            .collect();
+        return JFSWatch::new(rules, 0.1, 0.1, options);
     }
 
     #[test]
-    fn given_no_explorers_when_new_then_err() {
-        let explorers = vec![];
-        let interval = 0.1;
-        let sleep = 0.1;
-        let cmd = vec!["echo".to_string(), "hello".to_string()];
+    fn given_rule_when_get_command_then_substitutes_captures_and_variables() {
+        let jfswatch = jfswatch_with_rules(
+            vec!["src/{section}/{name}.md -> pandoc $path -o out/{section}/{name}.html # {2} \\{name} {other}"],
+            WatchOptions::default(),
+        )
+        .unwrap();
+        let diff = FSDifference::New {
+            path: "src/guide/intro.md".to_string(),
+            mtime: chrono::Local::now(),
+        };
 
-        let jfswatch = JFSWatch::new(explorers, interval, sleep, cmd);
-        assert!(jfswatch.is_err());
+        let command = jfswatch
+            .get_command(0, &diff, std::slice::from_ref(&diff))
+            .to_string();
+
+        assert_eq!(
+            command,
+            "pandoc src/guide/intro.md -o out/guide/intro.html # intro {name} {other}"
+        );
     }
 
-    fn jfswatch_with_command(command: Vec<&str>) -> JFSWatch {
-        let explorers: Vec<Box<dyn Explorer>> = vec![Box::new(ExactExplorer::from_cli_arg("path"))];
-        let interval = 0.1;
-        let sleep = 0.1;
-        let cmd = command.iter().map(|s| s.to_string()).collect();
-        let jfswatch = JFSWatch::new(explorers, interval, sleep, cmd).unwrap();
-        return jfswatch;
+    #[test]
+    fn given_rule_when_path_does_not_match_then_captures_left_as_is() {
+        let jfswatch = jfswatch_with_rules(
+            vec!["src/{name}.md -> echo {name} $path"],
+            WatchOptions::default(),
+        )
+        .unwrap();
+        let diff = FSDifference::New {
+            path: "src/main.rs".to_string(),
+            mtime: chrono::Local::now(),
+        };
+
+        let command = jfswatch
+            .get_command(0, &diff, std::slice::from_ref(&diff))
+            .to_string();
+
+        assert_eq!(command, "echo {name} src/main.rs");
     }
 
     #[test]
-    fn given_unchanged_diff_when_get_command_then_none() {
-        let jfswatch = jfswatch_with_command(vec!["doesn't", "matter"]);
-        let diff = FSDifference::Unchanged;
+    fn given_rule_with_shell_positional_parameter_when_get_command_then_left_for_shell() {
+        let jfswatch = jfswatch_with_rules(
+            vec!["src/{name}.md -> sh -c 'echo ${1}' _ {1}"],
+            WatchOptions::default(),
+        )
+        .unwrap();
+        let diff = FSDifference::New {
+            path: "src/intro.md".to_string(),
+            mtime: chrono::Local::now(),
+        };
 
-        match jfswatch.get_command(&diff) {
-            Some(_) => panic!("Expected None"),
-            None => {}
-        }
+        let command = jfswatch
+            .get_command(0, &diff, std::slice::from_ref(&diff))
+            .to_string();
+
+        assert_eq!(command, "sh -c 'echo ${1}' _ intro");
     }
 
     #[test]
-    fn given_new_diff_when_get_command_then_substitutes_all() {
+    fn given_no_shell_when_get_command_for_rule_then_each_word_is_an_argument() {
+        let options = WatchOptions {
+            shell: false,
+            ..WatchOptions::default()
+        };
         let jfswatch =
-            jfswatch_with_command(vec!["echo", "$diff", "$path was", "created at $mtime"]);
-        let mtime = chrono::Local::now();
+            jfswatch_with_rules(vec!["{name}.md -> pandoc  $path -o {name}.html"], options)
+                .unwrap();
         let diff = FSDifference::New {
-            path: "mock/path".to_string(),
-            mtime: mtime,
+            path: "my notes.md".to_string(),
+            mtime: chrono::Local::now(),
         };
-        let command = jfswatch.get_command(&diff).unwrap();
+
+        let command = jfswatch.get_command(0, &diff, std::slice::from_ref(&diff));
 
         assert_eq!(
             command,
-            format!(
-                "echo new mock/path was created at {}",
-                mtime.format(LOCAL_DATE_FORMAT)
-            )
+            CommandLine::Exec(vec![
+                "pandoc".to_string(),
+                "my notes.md".to_string(),
+                "-o".to_string(),
+                "my notes.html".to_string(),
+            ])
         );
     }
 
     #[test]
-    fn given_modified_diff_when_get_command_then_substitutes_all() {
-        let jfswatch =
-            jfswatch_with_command(vec!["echo", "{ diff: $diff, path: $path, mtime: $mtime }"]);
-        let mtime = chrono::Local::now();
-        let diff = FSDifference::Modified {
-            path: "mock/path".to_string(),
-            mtime: mtime,
+    fn given_braces_in_command_when_get_command_then_left_as_is() {
+        let jfswatch = new_jfswatch(
+            vec!["path"],
+            vec!["awk", "'{print}'", "\\{name}", "$path"],
+            WatchOptions::default(),
+        )
+        .unwrap();
+        let diff = FSDifference::Deleted {
+            path: "path".to_string(),
+            old_mtime: chrono::Local::now(),
         };
-        let command = jfswatch.get_command(&diff).unwrap();
 
-        assert_eq!(
-            command,
-            format!(
-                "echo {{ diff: modified, path: mock/path, mtime: {} }}",
-                mtime.format(LOCAL_DATE_FORMAT)
-            )
+        let command = jfswatch
+            .get_command(0, &diff, std::slice::from_ref(&diff))
+            .to_string();
+
+        assert_eq!(command, "awk '{print}' \\{name} path");
+    }
+
+    #[rstest]
+    #[case(vec!["src/{name}.md -> echo {name}"], WatchOptions::default(), true)]
+    #[case(vec![], WatchOptions::default(), false)]
+    #[case(vec!["src/{name}.md -> echo $basename"], batch_options(" "), true)]
+    #[case(vec!["src/{name}.md -> echo $pth"], WatchOptions { shell: false, ..WatchOptions::default() }, false)]
+    #[case(vec!["src/{name}.md -> echo {name}"], WatchOptions { restart: true, ..WatchOptions::default() }, true)]
+    #[case(vec!["src/{name}.md -> echo {name}", "*.sql -> migrate $pth"], WatchOptions { shell: false, ..WatchOptions::default() }, false)]
+    fn given_rules_when_new_then_validates_each_rule(
+        #[case] rules: Vec<&str>,
+        #[case] options: WatchOptions,
+        #[case] expected_ok: bool,
+    ) {
+        let jfswatch = jfswatch_with_rules(rules, options);
+        assert_eq!(jfswatch.is_ok(), expected_ok, "{:?}", jfswatch.err());
+    }
+
+    #[test]
+    fn given_several_rules_with_invalid_one_when_new_then_err_describes_rule() {
+        let options = WatchOptions {
+            shell: false,
+            ..WatchOptions::default()
+        };
+        let jfswatch = jfswatch_with_rules(
+            vec!["src/{name}.md -> echo {name}", "*.sql -> migrate $pth"],
+            options,
         );
+
+        assert!(jfswatch
+            .err()
+            .is_some_and(|error| error.ends_with(", in the rule for *.sql")));
     }
 
     #[test]
-    fn given_deleted_diff_when_get_command_then_substitutes_all() {
-        let jfswatch = jfswatch_with_command(vec![
-            "echo",
-            "{ diff: $diff }",
-            "path: $path\nmtime: $mtime",
-        ]);
-        let diff = FSDifference::Deleted {
-            path: "mock/path".to_string(),
+    fn given_rules_and_explorers_without_command_when_new_then_err() {
+        let explorers: Vec<Box<dyn Explorer>> =
+            vec![Box::new(ExactExplorer::from_cli_arg("path").unwrap())];
+        let rules = vec![
+            Rule::new(explorers, vec![]),
+            Rule::from_cli_arg("src/{name}.md -> echo {name}", false).unwrap(),
+        ];
+
+        let jfswatch = JFSWatch::new(rules, 0.1, 0.1, WatchOptions::default());
+        assert!(jfswatch.is_err());
+    }
+
+    #[test]
+    fn given_rules_with_own_timing_when_new_then_validates_own_timing() {
+        let explorers: Vec<Box<dyn Explorer>> =
+            vec![Box::new(ExactExplorer::from_cli_arg("path").unwrap())];
+        let cmd = vec!["echo".to_string(), "$path".to_string()];
+        let rule = Rule {
+            interval: Some(-1.0),
+            ..Rule::new(explorers, cmd)
+        };
+
+        let jfswatch = JFSWatch::new(vec![rule], 0.1, 0.1, WatchOptions::default());
+        assert!(jfswatch.is_err());
+    }
+
+    #[test]
+    fn given_rules_with_own_timing_when_new_then_override_watch_timing() {
+        let explorers: Vec<Box<dyn Explorer>> =
+            vec![Box::new(ExactExplorer::from_cli_arg("path").unwrap())];
+        let cmd = vec!["echo".to_string(), "$path".to_string()];
+        let rule = Rule {
+            sleep: Some(2.0),
+            ..Rule::new(explorers, cmd)
         };
-        let command = jfswatch.get_command(&diff).unwrap();
 
+        let jfswatch = JFSWatch::new(vec![rule], 0.5, 1.0, WatchOptions::default()).unwrap();
+
+        assert_eq!(jfswatch.rules[0].interval, Duration::from_millis(500));
+        assert_eq!(jfswatch.rules[0].sleep, Duration::from_secs(2));
+    }
+
+    #[test]
+    fn given_rules_sharing_paths_when_explore_initial_then_each_rule_has_its_own_paths() {
+        let tmp = tempfile::tempdir_in(".").unwrap();
+        let basedir = tmp.path().to_string_lossy().to_string();
+        crate::test_utils::utils::make_files(
+            tmp.path(),
+            vec!["src/main.rs", "src/lib.rs", "migrations/1.sql"],
+        );
+        let rules = vec![
+            Rule::from_cli_arg(&format!("{basedir}/src/*.rs -> cargo test"), false).unwrap(),
+            Rule::from_cli_arg(&format!("{basedir}/**/*.sql -> migrate"), false).unwrap(),
+            Rule::from_cli_arg(&format!("{basedir}/*/{{name}}.* -> echo {{name}}"), false).unwrap(),
+        ];
+        let mut jfswatch = JFSWatch::new(rules, 0.1, 0.1, WatchOptions::default()).unwrap();
+
+        let found = jfswatch.explore_initial();
+
+        let rule_paths = |rule: usize| -> Vec<String> {
+            let mut paths: Vec<String> = jfswatch.rules[rule]
+                .watched_fs
+                .paths()
+                .map(|path| path.strip_prefix(&basedir).unwrap().to_string())
+                .collect();
+            paths.sort();
+            return paths;
+        };
+        assert_eq!(found, 3);
+        assert_eq!(rule_paths(0), vec!["/src/lib.rs", "/src/main.rs"]);
+        assert_eq!(rule_paths(1), vec!["/migrations/1.sql"]);
         assert_eq!(
-            command,
-            format!("echo {{ diff: deleted }} path: mock/path\nmtime: $mtime")
+            rule_paths(2),
+            vec!["/migrations/1.sql", "/src/lib.rs", "/src/main.rs"]
+        );
+    }
+
+    #[test]
+    fn given_several_rules_when_on_changes_then_each_runs_its_own_command() {
+        let tmp = tempfile::tempdir_in(".").unwrap();
+        let basedir = tmp.path().to_string_lossy().to_string();
+        let explorers: Vec<Box<dyn Explorer>> = vec![Box::new(
+            ExactExplorer::from_cli_arg(&format!("{basedir}/config.toml")).unwrap(),
+        )];
+        let rules = vec![
+            Rule::new(explorers, vec![format!("touch {basedir}/reloaded-$count")]),
+            Rule::from_cli_arg(
+                &format!("{basedir}/{{name}}.md -> touch {basedir}/{{name}}.html"),
+                false,
+            )
+            .unwrap(),
+        ];
+        let options = WatchOptions {
+            batch: true,
+            ..WatchOptions::default()
+        };
+        let mut jfswatch = JFSWatch::new(rules, 0.1, 10.0, options).unwrap();
+
+        let mtime = chrono::Local::now();
+        let difference = |name: &str| FSDifference::New {
+            path: format!("{basedir}/{name}"),
+            mtime,
+        };
+        jfswatch.on_changes(0, &[difference("config.toml")]);
+        jfswatch.on_changes(1, &[difference("intro.md"), difference("outro.md")]);
+
+        let mut created: Vec<String> = std::fs::read_dir(tmp.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        created.sort();
+        assert_eq!(created, vec!["intro.html", "outro.html", "reloaded-1"]);
+        assert!(jfswatch.rules[0].next_check > Instant::now());
+    }
+
+    fn glob_rule(pattern: &str, cmd: &str) -> Rule {
+        return Rule::new(
+            vec![Box::new(GlobExplorer::from_cli_arg(pattern).unwrap())],
+            cmd.split_whitespace().map(str::to_string).collect(),
         );
     }
 
+    fn settings(rules: Vec<Rule>) -> WatchSettings {
+        return WatchSettings {
+            rules,
+            interval: 0.1,
+            sleep: 0.1,
+            options: WatchOptions::default(),
+            respect_ignore_files: false,
+        };
+    }
+
+    #[test]
+    fn given_reloaded_rules_when_reexplored_then_only_real_changes_are_reported() {
+        let tmp = tempfile::tempdir_in(".").unwrap();
+        let basedir = tmp.path().to_string_lossy().to_string();
+        let files = crate::test_utils::utils::make_files(tmp.path(), vec!["a.rs", "b.md"]);
+        let mut jfswatch = new_jfswatch(
+            vec![&format!("{basedir}/*.rs")],
+            vec!["cargo", "test"],
+            WatchOptions::default(),
+        )
+        .unwrap();
+        jfswatch.explore_initial();
+
+        // modified after the last check, but before the reload
+        let mtime = std::time::SystemTime::now() - Duration::from_secs(10);
+        std::fs::File::options()
+            .write(true)
+            .open(&files[0])
+            .unwrap()
+            .set_modified(mtime)
+            .unwrap();
+
+        jfswatch
+            .reload(settings(vec![
+                glob_rule(&format!("{basedir}/*.rs"), "cargo build"),
+                glob_rule(&format!("{basedir}/*.md"), "mdbook build"),
+            ]))
+            .unwrap();
+        let differences = jfswatch.reexplore(&[0, 1]);
+
+        assert_eq!(differences[0].len(), 1);
+        assert_eq!(differences[0][0].kind(), "modified");
+        assert_eq!(differences[1], vec![]);
+        assert_eq!(jfswatch.rules[0].rule.cmd, vec!["cargo", "build"]);
+    }
+
+    #[test]
+    fn given_invalid_settings_when_reload_then_keeps_previous_rules() {
+        let mut jfswatch =
+            new_jfswatch(vec!["path"], vec!["echo", "$path"], WatchOptions::default()).unwrap();
+
+        let result = jfswatch.reload(settings(vec![glob_rule("*.rs", "")]));
+
+        assert!(result.is_err());
+        assert_eq!(jfswatch.rules.len(), 1);
+        assert_eq!(jfswatch.rules[0].rule.cmd, vec!["echo", "$path"]);
+    }
+
+    #[test]
+    fn given_changed_backend_when_reload_then_keeps_previous_backend() {
+        let mut jfswatch =
+            new_jfswatch(vec!["path"], vec!["echo", "$path"], WatchOptions::default()).unwrap();
+        let mut new_settings = settings(vec![glob_rule("*.rs", "cargo test")]);
+        new_settings.options.backend = Backend::Inotify;
+        new_settings.options.separator = ",".to_string();
+
+        jfswatch.reload(new_settings).unwrap();
+
+        assert_eq!(jfswatch.options.backend, Backend::Poll);
+        assert_eq!(jfswatch.options.separator, ",");
+    }
+
     #[rstest]
-    #[case(FSDifference::New { path: "mock/path".to_string(), mtime: chrono::Local::now() })]
-    #[case(FSDifference::Modified { path: "mock/path".to_string(), mtime: chrono::Local::now() })]
-    #[case(FSDifference::Deleted { path: "mock/path".to_string() })]
-    fn given_any_diff_when_get_command_then_ignores_escaped_variables(#[case] diff: FSDifference) {
-        let jfswatch = jfswatch_with_command(vec!["echo $path \\$path \\${path} ${path}"]);
-        let command = jfswatch.get_command(&diff).unwrap();
+    #[case(true, 2)]
+    #[case(false, 1)]
+    fn given_config_file_change_when_check_config_then_reloads_valid_settings(
+        #[case] valid: bool,
+        #[case] expected_rules: usize,
+    ) {
+        let tmp = tempfile::tempdir_in(".").unwrap();
+        let path = tmp.path().join("jfswatch.toml");
+        std::fs::write(&path, "").unwrap();
+        let load: SettingsLoader = Box::new(move || match valid {
+            true => Ok(settings(vec![
+                glob_rule("*.rs", "cargo test"),
+                glob_rule("*.md", "mdbook build"),
+            ])),
+            false => Err("Invalid config file".to_string()),
+        });
+        let mut jfswatch =
+            new_jfswatch(vec!["path"], vec!["echo", "$path"], WatchOptions::default())
+                .unwrap()
+                .with_config(&path, load);
 
-        assert_eq!(command, "echo mock/path $path ${path} mock/path");
+        assert!(!jfswatch.check_config());
+
+        std::fs::File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(std::time::SystemTime::now() - Duration::from_secs(10))
+            .unwrap();
+
+        assert_eq!(jfswatch.check_config(), valid);
+        assert_eq!(jfswatch.rules.len(), expected_rules);
+        assert!(!jfswatch.check_config());
     }
 }
@@ -0,0 +1,409 @@
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use chrono::{DateTime, Local};
+
+use crate::exclusions::Exclusions;
+use crate::explorers::{ignore_files, WatchRoot};
+use crate::rule::Rule;
+use crate::watched_fs::WatchedFS;
+
+/// How the `list` subcommand prints the paths
+#[derive(Debug, Clone, Copy, PartialEq, Default, clap::ValueEnum)]
+pub enum ListFormat {
+    /// Each watched path on its own line
+    #[default]
+    Paths,
+
+    /// A table of every path with its size, modified time, and the pattern that found it
+    Table,
+
+    /// A JSON array of every path with its size, modified time, and the pattern that found it
+    Json,
+}
+
+/// Whether a listed path would be watched
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PathStatus {
+    /// A pattern matches the path, so it's watched
+    Matched,
+
+    /// A pattern matches the path, but it's excluded from being watched
+    Excluded,
+
+    /// The path is inside of a watched directory, but no pattern matches it
+    Unmatched,
+}
+
+/// A path that was found by exploring, or that is inside of a directory watched for a rule
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct ListedPath {
+    pub path: String,
+
+    pub status: PathStatus,
+
+    /// The size in bytes, or `None` if the path's metadata can't be read
+    pub size: Option<u64>,
+
+    /// When the path was last modified, or `None` if the path's metadata can't be read
+    pub mtime: Option<DateTime<Local>>,
+
+    /// The description of the first rule with a pattern that matches the path
+    pub rule: Option<String>,
+
+    /// The first pattern that matches the path, as it was given
+    pub pattern: Option<String>,
+
+    /// The basic pattern that `pattern` expands into which matches the path
+    pub subpattern: Option<String>,
+}
+
+/// Explores the paths of every rule once, the same as watching them would. Returns every path that would be watched,
+/// ordered by path. A path found by several rules is only listed once
+pub fn watched_paths(rules: &[Rule], exclusions: Rc<Exclusions>) -> Vec<String> {
+    let mut watched_fs = WatchedFS::new(0).with_exclusions(exclusions);
+    for explorer in rules.iter().flat_map(|rule| rule.explorers.iter()) {
+        explorer.explore(&mut watched_fs);
+    }
+
+    let mut paths: Vec<String> = watched_fs.paths().cloned().collect();
+    paths.sort();
+    return paths;
+}
+
+/// Resolves the explorers of every rule once, and attributes each path they find to the first pattern that matches
+/// it. The other paths inside of the directories that are watched for the rules are listed as unmatched. Returns the
+/// paths ordered by path. When `respect_ignore_files`, the paths ignored by ignore files aren't listed, the same as
+/// they aren't explored
+pub fn resolve(
+    rules: &[Rule],
+    exclusions: &Exclusions,
+    respect_ignore_files: bool,
+) -> Vec<ListedPath> {
+    // excluded paths are still explored, so they can be listed as excluded
+    let mut watched_fs = WatchedFS::new(0);
+    let mut roots: Vec<WatchRoot> = Vec::new();
+    for explorer in rules.iter().flat_map(|rule| rule.explorers.iter()) {
+        explorer.explore(&mut watched_fs);
+        for root in explorer.roots() {
+            if !roots.contains(&root) {
+                roots.push(root);
+            }
+        }
+    }
+
+    let mut paths: BTreeSet<PathBuf> = watched_fs.paths().map(PathBuf::from).collect();
+    for root in roots.iter() {
+        paths.extend(
+            ignore_files::walk(root, respect_ignore_files)
+                .filter(|path| path != &root.dir && !path.as_os_str().is_empty()),
+        );
+    }
+
+    return paths
+        .into_iter()
+        .map(|path| {
+            let metadata = std::fs::metadata(&path).ok();
+            let found = rules.iter().find_map(|rule| {
+                return rule.explorers.iter().find_map(|explorer| {
+                    let subpattern = explorer.matching_pattern(&path)?;
+                    return Some((rule.describe(), explorer.pattern(), subpattern));
+                });
+            });
+
+            let status = match &found {
+                None => PathStatus::Unmatched,
+                Some(_) if exclusions.excludes(&path) => PathStatus::Excluded,
+                Some(_) => PathStatus::Matched,
+            };
+            let (rule, pattern, subpattern) = match found {
+                Some((rule, pattern, subpattern)) => (Some(rule), Some(pattern), Some(subpattern)),
+                None => (None, None, None),
+            };
+
+            return ListedPath {
+                path: path.to_string_lossy().to_string(),
+                status,
+                size: metadata.as_ref().map(std::fs::Metadata::len),
+                mtime: metadata
+                    .and_then(|metadata| metadata.modified().ok())
+                    .map(DateTime::from),
+                rule,
+                pattern,
+                subpattern,
+            };
+        })
+        .collect();
+}
+
+/// Formats the `paths` as a table with a header, with one path per row
+pub fn table(paths: &[ListedPath]) -> String {
+    let mut rows: Vec<[String; 5]> = vec![[
+        "PATH".to_string(),
+        "STATUS".to_string(),
+        "SIZE".to_string(),
+        "MODIFIED".to_string(),
+        "PATTERN".to_string(),
+    ]];
+
+    for listed in paths {
+        let status = match listed.status {
+            PathStatus::Matched => "matched",
+            PathStatus::Excluded => "excluded",
+            PathStatus::Unmatched => "unmatched",
+        };
+        let pattern = match (&listed.pattern, &listed.subpattern) {
+            (Some(pattern), Some(subpattern)) if pattern != subpattern => {
+                format!("{pattern} ({subpattern})")
+            }
+            (Some(pattern), _) => pattern.clone(),
+            (None, _) => "-".to_string(),
+        };
+
+        rows.push([
+            listed.path.clone(),
+            status.to_string(),
+            listed.size.map_or("-".to_string(), |size| size.to_string()),
+            listed.mtime.map_or("-".to_string(), |mtime| {
+                mtime.format("%Y-%m-%d %H:%M:%S").to_string()
+            }),
+            pattern,
+        ]);
+    }
+
+    let mut widths = [0; 5];
+    for row in rows.iter() {
+        for (width, cell) in widths.iter_mut().zip(row.iter()) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let mut table = String::new();
+    for row in rows.iter() {
+        let cells: Vec<String> = row
+            .iter()
+            .zip(widths.iter())
+            .map(|(cell, width)| format!("{cell:width$}"))
+            .collect();
+        table.push_str(cells.join("  ").trim_end());
+        table.push('\n');
+    }
+    return table;
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir_in;
+
+    use super::*;
+    use crate::explorers::{ExactExplorer, Explorer, GlobExplorer};
+    use crate::test_utils::utils::make_files;
+
+    #[test]
+    fn given_rules_when_watched_paths_then_lists_each_path_once_in_order() {
+        let tmp = tempdir_in(".").unwrap();
+        let basedir = tmp.path().to_string_lossy().to_string();
+        make_files(
+            tmp.path(),
+            vec!["src/main.rs", "src/lib.rs", "src/main.rs.swp", "Cargo.toml"],
+        );
+        let rules = vec![
+            Rule::new(
+                vec![
+                    Box::new(GlobExplorer::from_cli_arg(&format!("{basedir}/src/*")).unwrap()),
+                    Box::new(
+                        ExactExplorer::from_cli_arg(&format!("{basedir}/Cargo.toml")).unwrap(),
+                    ),
+                ],
+                vec!["cargo".to_string(), "test".to_string()],
+            ),
+            Rule::from_cli_arg(
+                &format!("{basedir}/src/{{name}}.rs -> echo {{name}}"),
+                false,
+            )
+            .unwrap(),
+        ];
+        let exclusions = Exclusions::from_cli_args(&["*.swp".to_string()]).unwrap();
+
+        let paths = watched_paths(&rules, Rc::new(exclusions));
+
+        assert_eq!(
+            paths,
+            vec![
+                format!("{basedir}/Cargo.toml"),
+                format!("{basedir}/src/lib.rs"),
+                format!("{basedir}/src/main.rs"),
+            ]
+        );
+    }
+
+    #[test]
+    fn given_relative_exclusion_when_watched_paths_then_excludes_dot_slash_and_absolute_patterns() {
+        let tmp = tempdir_in(".").unwrap();
+        let name = tmp
+            .path()
+            .file_name()
+            .unwrap()
+            .to_string_lossy()
+            .to_string();
+        let cwd = std::env::current_dir()
+            .unwrap()
+            .to_string_lossy()
+            .to_string();
+        make_files(tmp.path(), vec!["src/main.rs", "src/gen/x.rs"]);
+        let exclusions = Exclusions::from_cli_args(&[format!("{name}/src/gen/**")]).unwrap();
+
+        for (glob, exact) in [
+            (
+                format!("./{name}/src/**/*.rs"),
+                format!("./{name}/src/gen/x.rs"),
+            ),
+            (
+                format!("{cwd}/{name}/src/**/*.rs"),
+                format!("{cwd}/{name}/src/gen/x.rs"),
+            ),
+        ] {
+            let rules = vec![Rule::new(
+                vec![
+                    Box::new(GlobExplorer::from_cli_arg(&glob).unwrap()),
+                    Box::new(ExactExplorer::from_cli_arg(&exact).unwrap()),
+                ],
+                vec!["cargo".to_string(), "test".to_string()],
+            )];
+
+            let paths = watched_paths(&rules, Rc::new(exclusions.clone()));
+
+            assert_eq!(paths.len(), 1, "{glob}: {paths:?}");
+            assert!(paths[0].ends_with("src/main.rs"), "{glob}: {paths:?}");
+        }
+    }
+
+    #[test]
+    fn given_rules_when_resolve_then_attributes_paths_to_expanded_patterns() {
+        let tmp = tempdir_in(".").unwrap();
+        let basedir = tmp.path().to_string_lossy().to_string();
+        make_files(
+            tmp.path(),
+            vec![
+                "src/main.rs",
+                "src/main.rs.swp",
+                "src/notes.txt",
+                "src/nested/lib.rs",
+                "Cargo.toml",
+            ],
+        );
+        let pattern = format!("{basedir}/src/*.{{rs,swp}}");
+        let rules = vec![Rule::new(
+            vec![Box::new(GlobExplorer::from_cli_arg(&pattern).unwrap())],
+            vec!["cargo".to_string(), "test".to_string()],
+        )];
+        let exclusions = Exclusions::from_cli_args(&["*.swp".to_string()]).unwrap();
+
+        let paths = resolve(&rules, &exclusions, false);
+
+        let summary: Vec<(String, PathStatus, Option<String>)> = paths
+            .iter()
+            .map(|listed| {
+                return (
+                    listed.path.clone(),
+                    listed.status,
+                    listed.subpattern.clone(),
+                );
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (
+                    format!("{basedir}/src/main.rs"),
+                    PathStatus::Matched,
+                    Some(format!("{basedir}/src/*.rs"))
+                ),
+                (
+                    format!("{basedir}/src/main.rs.swp"),
+                    PathStatus::Excluded,
+                    Some(format!("{basedir}/src/*.swp"))
+                ),
+                (format!("{basedir}/src/nested"), PathStatus::Unmatched, None),
+                (
+                    format!("{basedir}/src/notes.txt"),
+                    PathStatus::Unmatched,
+                    None
+                ),
+            ]
+        );
+        assert_eq!(paths[0].pattern, Some(pattern));
+        assert_eq!(paths[0].rule, Some(rules[0].describe()));
+        assert_eq!(paths[0].size, Some(0));
+        assert!(paths[0].mtime.is_some());
+    }
+
+    #[test]
+    fn given_ignore_files_when_resolve_then_ignored_paths_are_not_listed() {
+        let tmp = tempdir_in(".").unwrap();
+        let basedir = tmp.path().to_string_lossy().to_string();
+        make_files(
+            tmp.path(),
+            vec![
+                "src/main.rs",
+                "target/debug/build.rs",
+                "notes.txt",
+                ".git/HEAD",
+            ],
+        );
+        std::fs::write(tmp.path().join(".gitignore"), "target/\n").unwrap();
+        let rules = vec![Rule::new(
+            vec![Box::new(
+                GlobExplorer::from_cli_arg(&format!("{basedir}/**/*.rs"))
+                    .unwrap()
+                    .with_ignore_files(true),
+            )],
+            vec!["cargo".to_string(), "test".to_string()],
+        )];
+
+        let paths = resolve(&rules, &Exclusions::default(), true);
+        let table = table(&paths);
+        let json = serde_json::to_string(&paths).unwrap();
+
+        assert!(table.contains(&format!("{basedir}/src/main.rs")));
+        assert!(table.contains(&format!("{basedir}/notes.txt")));
+        for output in [&table, &json] {
+            assert!(!output.contains("target"));
+            assert!(!output.contains(".git/"));
+        }
+        assert!(json.contains("main.rs"));
+    }
+
+    #[test]
+    fn given_listed_paths_when_table_then_aligns_columns() {
+        let paths = vec![
+            ListedPath {
+                path: "config.yaml".to_string(),
+                status: PathStatus::Matched,
+                size: Some(1024),
+                mtime: None,
+                rule: Some("config.{yml,yaml}".to_string()),
+                pattern: Some("config.{yml,yaml}".to_string()),
+                subpattern: Some("config.yaml".to_string()),
+            },
+            ListedPath {
+                path: "notes.txt".to_string(),
+                status: PathStatus::Unmatched,
+                size: None,
+                mtime: None,
+                rule: None,
+                pattern: None,
+                subpattern: None,
+            },
+        ];
+
+        assert_eq!(
+            table(&paths),
+            "PATH         STATUS     SIZE  MODIFIED  PATTERN\n\
+             config.yaml  matched    1024  -         config.{yml,yaml} (config.yaml)\n\
+             notes.txt    unmatched  -     -         -\n"
+        );
+    }
+}
@@ -1,17 +1,201 @@
+#![allow(clippy::needless_return)]
+
 #[macro_use]
 extern crate log;
-use clap::{CommandFactory, Parser};
+use clap::{CommandFactory, FromArgMatches};
 use clap_complete::generate;
 use flexi_logger::{AdaptiveFormat, Logger};
 
 mod cli;
+mod config;
+mod exclusions;
 mod explorers;
+#[cfg(target_os = "linux")]
+mod inotify_watcher;
 mod jfswatch;
+mod list;
+mod rule;
+mod runner;
+mod shutdown;
 mod test_utils;
 mod watched_fs;
 
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use clap::ArgMatches;
+
+use crate::cli::{Command, ListArgs, WatchArgs};
+use crate::config::Config;
+use crate::exclusions::Exclusions;
 use crate::explorers::*;
-use crate::jfswatch::JFSWatch;
+use crate::jfswatch::{JFSWatch, WatchOptions, WatchSettings};
+use crate::list::ListFormat;
+use crate::rule::Rule;
+use crate::runner::StopOptions;
+
+/// Reports an `error` the same way as invalid arguments to the `subcommand`, or to jfswatch itself without one, and
+/// exits
+fn exit_with_error(subcommand: Option<&str>, error: String) -> ! {
+    let mut cmd = cli::Cli::command();
+    // builds the usage of the subcommands, so it includes `jfswatch`
+    cmd.build();
+    if let Some(sub) = subcommand.and_then(|name| cmd.find_subcommand_mut(name)) {
+        sub.error(clap::error::ErrorKind::ValueValidation, error)
+            .exit();
+    }
+    cmd.error(clap::error::ErrorKind::ValueValidation, error)
+        .exit();
+}
+
+/// Loads the rules and options to watch from the command line `matches`, with the options which weren't given on the
+/// command line read from the config file at `config_path`, if one is used
+fn load_settings(
+    matches: &ArgMatches,
+    config_path: Option<&Path>,
+) -> Result<WatchSettings, String> {
+    let mut parsed = WatchArgs::from_arg_matches(matches).map_err(|error| error.to_string())?;
+
+    let mut named_rules = Vec::new();
+    if let Some(path) = config_path {
+        let config = Config::load(path)?;
+        let invalid =
+            |error: String| format!("Invalid config file '{}': {}", path.display(), error);
+        config.merge_into(&mut parsed, matches).map_err(invalid)?;
+        named_rules = config
+            .rules(parsed.gitignore, !parsed.no_shell)
+            .map_err(invalid)?;
+    }
+    trace!("Parsed CLI args: {:?}", parsed);
+
+    let mut explorers: Vec<Box<dyn Explorer>> =
+        Vec::with_capacity(parsed.exact.len() + parsed.glob.len());
+    for arg in parsed.exact.iter() {
+        let explorer = ExactExplorer::from_cli_arg(arg).map_err(|error| error.to_string())?;
+        explorers.push(Box::new(explorer));
+    }
+    for arg in parsed.glob.iter() {
+        let explorer = GlobExplorer::from_cli_arg(arg).map_err(|error| error.to_string())?;
+        explorers.push(Box::new(explorer.with_ignore_files(parsed.gitignore)));
+    }
+    let exclusions =
+        Exclusions::from_cli_args(&parsed.exclude).map_err(|error| error.to_string())?;
+
+    let mut rules: Vec<Rule> = Vec::with_capacity(parsed.rule.len() + named_rules.len() + 1);
+    if !parsed.cmd.is_empty() || !explorers.is_empty() {
+        rules.push(Rule::new(explorers, parsed.cmd));
+    }
+    for arg in parsed.rule.iter() {
+        rules.push(Rule::from_cli_arg(arg, parsed.gitignore)?);
+    }
+    rules.extend(named_rules);
+
+    return Ok(WatchSettings {
+        rules,
+        interval: parsed.interval,
+        sleep: parsed.sleep.unwrap_or(parsed.interval),
+        options: WatchOptions {
+            batch: parsed.batch,
+            separator: parsed.separator,
+            hash_limit: parsed.hash.then_some(parsed.hash_limit),
+            backend: parsed.backend,
+            reconcile: parsed.reconcile,
+            exclusions: Rc::new(exclusions),
+            restart: parsed.restart,
+            stop: StopOptions {
+                signal: parsed.stop_signal,
+                timeout: parsed.stop_timeout,
+            },
+            stdin: parsed.stdin,
+            shell: !parsed.no_shell,
+        },
+        respect_ignore_files: parsed.gitignore,
+    });
+}
+
+/// Validates the settings from the command line `matches` and config file, and then watches them until jfswatch is
+/// asked to shut down
+fn watch(matches: ArgMatches, subcommand: Option<&str>, config_path: Option<PathBuf>) -> ! {
+    if let Some(path) = &config_path {
+        info!("Using config file '{}'", path.display());
+    }
+
+    let jfs_result = load_settings(&matches, config_path.as_deref()).and_then(|settings| {
+        return JFSWatch::new(
+            settings.rules,
+            settings.interval,
+            settings.sleep,
+            settings.options,
+        );
+    });
+
+    let mut jfs = match (jfs_result, config_path) {
+        // invalid edits to the config file are logged, rather than stopping the watch
+        (Ok(jfs), Some(path)) => jfs.with_config(
+            &path.clone(),
+            Box::new(move || load_settings(&matches, Some(&path))),
+        ),
+        (Ok(jfs), None) => jfs,
+        (Err(error), _) => exit_with_error(subcommand, error),
+    };
+    let status = jfs.watch();
+    std::process::exit(status);
+}
+
+/// Prints every path that the settings from the command line `matches` and config file would watch, in the `format`
+/// given on the command line
+fn list(matches: &ArgMatches, config_path: Option<&Path>) {
+    let format = ListArgs::from_arg_matches(matches)
+        .unwrap_or_else(|error| error.exit())
+        .format;
+    let settings = load_settings(matches, config_path)
+        .unwrap_or_else(|error| exit_with_error(Some("list"), error));
+
+    match format {
+        ListFormat::Paths => {
+            for path in list::watched_paths(&settings.rules, settings.options.exclusions) {
+                println!("{}", path);
+            }
+        }
+        ListFormat::Table => {
+            let paths = list::resolve(
+                &settings.rules,
+                &settings.options.exclusions,
+                settings.respect_ignore_files,
+            );
+            print!("{}", list::table(&paths));
+        }
+        ListFormat::Json => {
+            let paths = list::resolve(
+                &settings.rules,
+                &settings.options.exclusions,
+                settings.respect_ignore_files,
+            );
+            println!("{}", serde_json::to_string_pretty(&paths).unwrap());
+        }
+    }
+}
+
+/// Validates the settings from the command line `matches` and config file the same way as watching them would, and
+/// prints the rules that would be watched
+fn check(matches: &ArgMatches, config_path: Option<&Path>) {
+    let settings = load_settings(matches, config_path)
+        .unwrap_or_else(|error| exit_with_error(Some("check"), error));
+    let rules: Vec<String> = settings.rules.iter().map(Rule::describe).collect();
+
+    if let Err(error) = JFSWatch::new(
+        settings.rules,
+        settings.interval,
+        settings.sleep,
+        settings.options,
+    ) {
+        exit_with_error(Some("check"), error);
+    }
+
+    for rule in rules {
+        println!("ok: {}", rule);
+    }
+}
 
 fn main() {
     Logger::try_with_env_or_str("info")
@@ -21,53 +205,26 @@ fn main() {
         .start()
         .unwrap();
 
-    let parsed = cli::Cli::parse();
-    trace!("Parsed CLI args: {:?}", parsed);
-
-    if let Some(shell) = parsed.autocomplete {
-        let mut cmd = cli::Cli::command();
-        let name = cmd.get_name().to_string();
-        generate(shell, &mut cmd, name, &mut std::io::stdout());
-        return;
-    }
-
-    if parsed.cmd.len() == 0 {
-        let mut cmd = cli::Cli::command();
-        cmd.error(
-            clap::error::ErrorKind::ValueValidation,
-            "A command must be specified. Use -h for more help",
-        )
-        .exit();
-    }
+    let matches = cli::Cli::command().get_matches();
+    let parsed = cli::Cli::from_arg_matches(&matches).unwrap_or_else(|error| error.exit());
 
-    let mut explorers: Vec<Box<dyn Explorer>> =
-        Vec::with_capacity(parsed.exact.len() + parsed.glob.len());
-    explorers.extend(
-        parsed
-            .exact
-            .iter()
-            .map(|arg| -> Box<dyn Explorer> { Box::new(ExactExplorer::from_cli_arg(arg)) }),
-    );
-    explorers.extend(
-        parsed
-            .glob
-            .iter()
-            .map(|arg| -> Box<dyn Explorer> { Box::new(GlobExplorer::from_cli_arg(arg)) }),
-    );
-
-    let jfs_result = JFSWatch::new(
-        explorers,
-        parsed.interval,
-        parsed.sleep.unwrap_or(parsed.interval),
-        parsed.cmd,
-    );
-
-    match jfs_result {
-        Ok(mut jfs) => jfs.watch(),
-        Err(error) => {
-            let mut cmd = <cli::Cli as clap::CommandFactory>::command();
-            cmd.error(clap::error::ErrorKind::ValueValidation, error)
-                .exit();
+    // without a subcommand, the options of `watch` are given directly
+    let (args, arg_matches) = match (parsed.command, matches.subcommand()) {
+        (Some(Command::Completions { shell }), _) => {
+            let mut cmd = cli::Cli::command();
+            let name = cmd.get_name().to_string();
+            generate(shell, &mut cmd, name, &mut std::io::stdout());
+            return;
         }
+        (Some(Command::Watch(args) | Command::Check(args)), Some((_, sub))) => (args, sub.clone()),
+        (Some(Command::List(args)), Some((_, sub))) => (args.watch, sub.clone()),
+        (_, _) => (parsed.watch, matches.clone()),
+    };
+    let config_path = config::find(args.config.as_deref());
+
+    match matches.subcommand_name() {
+        Some("list") => list(&arg_matches, config_path.as_deref()),
+        Some("check") => check(&arg_matches, config_path.as_deref()),
+        subcommand => watch(arg_matches, subcommand, config_path),
     }
 }
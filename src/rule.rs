@@ -0,0 +1,153 @@
+use crate::explorers::{Explorer, GlobExplorer};
+
+/// Separates a rule's pattern from its command
+pub const RULE_SEPARATOR: &str = "->";
+
+/// Runs a command when any of the paths that the rule's explorers find change. Several rules can be watched at once,
+/// each with their own timing. What the wildcards of an explorer's pattern matched can be used in the command, e.g.
+/// `src/{name}.md -> pandoc $path -o out/{name}.html`
+pub struct Rule {
+    /// What the rule is called in logs. `None` describes it by its patterns instead
+    pub name: Option<String>,
+
+    /// How to discover the rule's paths on the file system
+    pub explorers: Vec<Box<dyn Explorer>>,
+
+    /// The command to run when one of the rule's paths changes
+    pub cmd: Vec<String>,
+
+    /// Seconds to wait between non-changing checks of the rule's paths. `None` uses the watch's interval
+    pub interval: Option<f32>,
+
+    /// Seconds to wait after running the command before checking the rule's paths again. `None` uses the watch's
+    /// sleep
+    pub sleep: Option<f32>,
+
+    /// Run the command once per check with every changed path, rather than once per changed path. `None` uses the
+    /// watch's option
+    pub batch: Option<bool>,
+
+    /// Whether what the wildcards of the explorers' patterns matched is substituted into the command, like `{name}`.
+    /// Otherwise braces are always left for the shell
+    pub captures: bool,
+}
+
+impl Rule {
+    /// Construct a rule which runs `cmd` when any path found by the `explorers` changes
+    pub fn new(explorers: Vec<Box<dyn Explorer>>, cmd: Vec<String>) -> Self {
+        return Self {
+            name: None,
+            explorers,
+            cmd,
+            interval: None,
+            sleep: None,
+            batch: None,
+            captures: false,
+        };
+    }
+
+    /// Construct a rule from a cli argument of the form `PATTERN -> COMMAND`. Braces around a bare name like `{name}`
+    /// in the extended glob pattern are a named capture which matches like `*`. Each whitespace separated word of the
+    /// command is an argument. The command always runs once per changed path, so it can use what the captures matched
+    pub fn from_cli_arg(arg: &str, respect_ignore_files: bool) -> Result<Self, String> {
+        let Some((pattern, command)) = arg.split_once(RULE_SEPARATOR) else {
+            return Err(format!(
+                "Rule '{arg}' must look like 'PATTERN {RULE_SEPARATOR} COMMAND'"
+            ));
+        };
+
+        let pattern = pattern.trim();
+        if pattern.is_empty() {
+            return Err(format!("Rule '{arg}' has no pattern"));
+        }
+        let command = command.trim();
+        if command.is_empty() {
+            return Err(format!("Rule '{arg}' has no command"));
+        }
+
+        let explorer = GlobExplorer::from_rule_pattern(pattern)
+            .map_err(|error| error.to_string())?
+            .with_ignore_files(respect_ignore_files);
+        return Ok(Self {
+            batch: Some(false),
+            captures: true,
+            ..Self::new(
+                vec![Box::new(explorer)],
+                command.split_whitespace().map(str::to_string).collect(),
+            )
+        });
+    }
+
+    /// Describes the rule in logs by its name, or otherwise by the patterns that its explorers look for
+    pub fn describe(&self) -> String {
+        if let Some(name) = &self.name {
+            return name.clone();
+        }
+
+        return self
+            .explorers
+            .iter()
+            .map(|explorer| explorer.pattern())
+            .collect::<Vec<String>>()
+            .join(", ");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use rstest::rstest;
+
+    use super::*;
+    use crate::explorers::ExactExplorer;
+
+    #[test]
+    fn given_rule_when_from_cli_arg_then_splits_pattern_and_command() {
+        let rule =
+            Rule::from_cli_arg("src/{name}.md -> pandoc $path -o out/{name}.html", false).unwrap();
+
+        assert_eq!(rule.describe(), "src/{name}.md");
+        assert_eq!(rule.cmd, vec!["pandoc", "$path", "-o", "out/{name}.html"]);
+        assert_eq!(rule.batch, Some(false));
+        assert!(rule.captures);
+
+        let captures = rule.explorers[0]
+            .captures(Path::new("src/intro.md"))
+            .unwrap();
+        assert_eq!(captures.get("name"), Some("intro"));
+    }
+
+    #[rstest]
+    #[case("src/*.md")]
+    #[case(" -> echo $path")]
+    #[case("src/*.md -> ")]
+    #[case("src/*.md} -> echo $path")]
+    fn given_incomplete_rule_when_from_cli_arg_then_err(#[case] arg: &str) {
+        assert!(Rule::from_cli_arg(arg, false).is_err());
+    }
+
+    #[test]
+    fn given_several_explorers_when_describe_then_lists_their_patterns() {
+        let rule = Rule::new(
+            vec![
+                Box::new(ExactExplorer::from_cli_arg("Cargo.toml").unwrap()),
+                Box::new(GlobExplorer::from_cli_arg("src/**/*.rs").unwrap()),
+            ],
+            vec!["cargo".to_string(), "test".to_string()],
+        );
+
+        assert_eq!(rule.describe(), "Cargo.toml, src/**/*.rs");
+        assert_eq!(rule.interval, None);
+    }
+
+    #[test]
+    fn given_named_rule_when_describe_then_uses_name() {
+        let rule = Rule {
+            name: Some("tests".to_string()),
+            ..Rule::from_cli_arg("src/*.rs -> cargo test", false).unwrap()
+        };
+
+        assert_eq!(rule.describe(), "tests");
+    }
+}
@@ -0,0 +1,743 @@
+use std::fmt;
+#[cfg(unix)]
+use std::io::IsTerminal;
+use std::io::Write;
+#[cfg(unix)]
+use std::os::unix::process::CommandExt;
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::str::FromStr;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Deserializer};
+
+use crate::shutdown::Shutdown;
+
+/// How often to check whether a stopping command has exited yet
+const STOP_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// How long to wait for killed processes to disappear before giving up on them
+const KILL_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Signals which can be named on the command line, without their `SIG` prefix
+#[cfg(unix)]
+const SIGNALS: [(&str, i32); 7] = [
+    ("HUP", libc::SIGHUP),
+    ("INT", libc::SIGINT),
+    ("QUIT", libc::SIGQUIT),
+    ("KILL", libc::SIGKILL),
+    ("USR1", libc::SIGUSR1),
+    ("USR2", libc::SIGUSR2),
+    ("TERM", libc::SIGTERM),
+];
+
+#[cfg(not(unix))]
+const SIGNALS: [(&str, i32); 3] = [("INT", 2), ("KILL", 9), ("TERM", 15)];
+
+/// A signal that can be sent to a running command
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Signal(pub i32);
+
+impl Signal {
+    /// Stops processes without giving them a chance to clean up
+    #[cfg(unix)]
+    const KILL: Signal = Signal(libc::SIGKILL);
+
+    #[cfg(not(unix))]
+    const KILL: Signal = Signal(9);
+
+    /// Politely asks processes to stop
+    #[cfg(unix)]
+    const TERM: Signal = Signal(libc::SIGTERM);
+
+    #[cfg(not(unix))]
+    const TERM: Signal = Signal(15);
+}
+
+impl FromStr for Signal {
+    type Err = String;
+
+    /// Parses a signal name like `SIGTERM` or `term`, or a signal number like `15`
+    fn from_str(arg: &str) -> Result<Self, Self::Err> {
+        if let Ok(number) = arg.parse::<i32>() {
+            if number <= 0 {
+                return Err(format!("Signal number must be positive: '{arg}'"));
+            }
+            return Ok(Signal(number));
+        }
+
+        let upper = arg.to_ascii_uppercase();
+        let name = upper.strip_prefix("SIG").unwrap_or(&upper);
+        return SIGNALS
+            .iter()
+            .find(|(known, _)| *known == name)
+            .map(|(_, number)| Signal(*number))
+            .ok_or(format!("Unknown signal: '{arg}'"));
+    }
+}
+
+impl<'de> Deserialize<'de> for Signal {
+    /// Reads a signal name like `SIGTERM` or a signal number like `15`, the same as `::from_str`
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum SignalValue {
+            Name(String),
+            Number(i64),
+        }
+
+        let arg = match SignalValue::deserialize(deserializer)? {
+            SignalValue::Name(name) => name,
+            SignalValue::Number(number) => number.to_string(),
+        };
+        return Signal::from_str(&arg).map_err(serde::de::Error::custom);
+    }
+}
+
+impl fmt::Display for Signal {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        return match SIGNALS.iter().find(|(_, number)| *number == self.0) {
+            Some((name, _)) => write!(f, "SIG{}", name),
+            None => write!(f, "signal {}", self.0),
+        };
+    }
+}
+
+/// How a running command is stopped, e.g. before it's restarted
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StopOptions {
+    /// Asks the command to stop before it's restarted
+    pub signal: Signal,
+
+    /// Seconds to wait for the command to exit after being signalled before killing it
+    pub timeout: f32,
+}
+
+impl Default for StopOptions {
+    fn default() -> Self {
+        return StopOptions {
+            signal: Signal::TERM,
+            timeout: 5.0,
+        };
+    }
+}
+
+/// Quotes `arg` for POSIX shells, so that it's always a single word without any expansions
+pub fn shell_quote(arg: &str) -> String {
+    let is_safe = |c: char| c.is_ascii_alphanumeric() || "@%+=:,./-_".contains(c);
+    if !arg.is_empty() && arg.chars().all(is_safe) {
+        return arg.to_string();
+    }
+
+    return format!("'{}'", arg.replace('\'', "'\\''"));
+}
+
+/// A command to run, after variables were substituted
+#[derive(Debug, Clone, PartialEq)]
+pub enum CommandLine {
+    /// Run by the user's shell, e.g. `$SHELL -c 'echo hello'`
+    Shell(String),
+
+    /// The program and arguments to execute directly, without a shell
+    Exec(Vec<String>),
+}
+
+impl CommandLine {
+    /// Creates the process which runs the command
+    fn process(&self) -> Command {
+        match self {
+            CommandLine::Shell(command) => {
+                let shell = std::env::var("SHELL").unwrap_or("sh".to_string());
+                let mut process = Command::new(shell);
+                process.args(["-c", command]);
+                return process;
+            }
+            CommandLine::Exec(args) => {
+                let mut process = Command::new(args.first().map_or("", String::as_str));
+                process.args(args.iter().skip(1));
+                return process;
+            }
+        }
+    }
+}
+
+impl fmt::Display for CommandLine {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        return match self {
+            CommandLine::Shell(command) => write!(f, "{}", command),
+            CommandLine::Exec(args) => {
+                let quoted: Vec<String> = args.iter().map(|arg| shell_quote(arg)).collect();
+                write!(f, "{}", quoted.join(" "))
+            }
+        };
+    }
+}
+
+/// Sends `signal` to every process in the `child`'s process group
+#[cfg(unix)]
+fn signal_group(child: &mut Child, signal: Signal) -> std::io::Result<()> {
+    // SAFETY: `kill` has no memory safety requirements. The group is named after the child, and its id isn't reused
+    // while any process remains in the group
+    let result = unsafe { libc::kill(-(child.id() as libc::pid_t), signal.0) };
+    if result < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    return Ok(());
+}
+
+/// Only killing the child itself is supported where there are no signals or process groups
+#[cfg(not(unix))]
+fn signal_group(child: &mut Child, _signal: Signal) -> std::io::Result<()> {
+    return child.kill();
+}
+
+/// Whether any process in the `child`'s process group is still running. An exited child counts until it's waited on
+#[cfg(unix)]
+fn group_running(child: &Child) -> bool {
+    // SAFETY: signal 0 only checks whether the group exists
+    return unsafe { libc::kill(-(child.id() as libc::pid_t), 0) } == 0;
+}
+
+#[cfg(not(unix))]
+fn group_running(_child: &Child) -> bool {
+    return false;
+}
+
+/// The stdin for a command that isn't fed the changed paths. Commands run in their own background process group, so
+/// reading from the terminal would stop them with `SIGTTIN`. They get an empty stdin instead when jfswatch's stdin is
+/// a terminal, and otherwise inherit it, e.g. when jfswatch is piped into
+#[cfg(unix)]
+fn inherited_stdin() -> Stdio {
+    if std::io::stdin().is_terminal() {
+        return Stdio::null();
+    }
+    return Stdio::inherit();
+}
+
+#[cfg(not(unix))]
+fn inherited_stdin() -> Stdio {
+    return Stdio::inherit();
+}
+
+/// Waits until the `child` has exited and every other process in its group is gone. The child's exit status is
+/// stored in `status` once it's known. Returns false if processes are still running at the `deadline`
+fn wait_for_group(child: &mut Child, status: &mut Option<ExitStatus>, deadline: Instant) -> bool {
+    loop {
+        if status.is_none() {
+            match child.try_wait() {
+                Ok(exited) => *status = exited,
+                Err(error) => {
+                    error!("... Error waiting for process {}: {}", child.id(), error);
+                    return false;
+                }
+            }
+        }
+
+        if status.is_some() && !group_running(child) {
+            return true;
+        }
+        if Instant::now() >= deadline {
+            return false;
+        }
+        sleep(STOP_POLL_INTERVAL);
+    }
+}
+
+/// Runs commands through the user's shell. Each command is started in its own process group, so that stopping it
+/// also stops any processes it started
+pub struct Runner {
+    /// Whether commands are restarted in the background rather than waited on
+    restart: bool,
+
+    /// How running commands are stopped
+    stop: StopOptions,
+
+    /// Stops a running command when jfswatch is shutting down, and prevents new commands from starting
+    shutdown: Shutdown,
+
+    /// The command that is running
+    child: Option<Child>,
+
+    /// How many commands were started
+    commands: usize,
+
+    /// How many commands couldn't start, or exited unsuccessfully without being stopped
+    failures: usize,
+}
+
+impl Runner {
+    /// Creates a runner which waits for each command to exit, or restarts them in the background
+    pub fn new(restart: bool, stop: StopOptions, shutdown: Shutdown) -> Self {
+        return Runner {
+            restart,
+            stop,
+            shutdown,
+            child: None,
+            commands: 0,
+            failures: 0,
+        };
+    }
+
+    /// Whether commands keep running in the background until the next one is run
+    pub fn restarts(&self) -> bool {
+        return self.restart;
+    }
+
+    /// How many commands were started
+    pub fn commands(&self) -> usize {
+        return self.commands;
+    }
+
+    /// How many commands couldn't start, or exited unsuccessfully without being stopped
+    pub fn failures(&self) -> usize {
+        return self.failures;
+    }
+
+    /// Executes the specified command with the environment variables in `env` set, or removed when they have no
+    /// value. The command's stdin is fed the `stdin` bytes when given, or otherwise inherited unless it's a terminal
+    /// (see `inherited_stdin`). In restart mode, the
+    /// previous command is stopped first and the new command is left running in the background. Otherwise, waits
+    /// for the command to exit unless a shutdown is requested
+    pub fn run(
+        &mut self,
+        command: CommandLine,
+        env: &[(&str, Option<String>)],
+        stdin: Option<Vec<u8>>,
+    ) {
+        self.stop(self.stop.signal);
+        if self.shutdown.requested().is_some() {
+            return;
+        }
+
+        info!("$ {}", command);
+        info!("\n{}", "-".repeat(80));
+
+        let mut process = command.process();
+        for (name, value) in env {
+            match value {
+                Some(value) => process.env(name, value),
+                None => process.env_remove(name),
+            };
+        }
+        process
+            .stderr(Stdio::inherit())
+            .stdout(Stdio::inherit())
+            .stdin(match stdin {
+                Some(_) => Stdio::piped(),
+                None => inherited_stdin(),
+            });
+        #[cfg(unix)]
+        process.process_group(0);
+
+        self.commands += 1;
+        let mut child = match process.spawn() {
+            Ok(child) => child,
+            Err(error) => {
+                info!("\n{}", "-".repeat(80));
+                error!("... Error running command: {}", error);
+                self.failures += 1;
+                return;
+            }
+        };
+
+        if let (Some(input), Some(mut pipe)) = (stdin, child.stdin.take()) {
+            // written by another thread, so a command that only reads some of its input after writing lots of output
+            // can't block jfswatch. Errors mean the command exited or closed its stdin without reading everything
+            std::thread::spawn(move || {
+                let _ = pipe.write_all(&input);
+            });
+        }
+
+        if self.restart {
+            info!("... Running in the background as process {}", child.id());
+            self.child = Some(child);
+            return;
+        }
+
+        loop {
+            match child.try_wait() {
+                Ok(Some(status)) => {
+                    info!("\n{}", "-".repeat(80));
+                    self.log_exit(Ok(status), true);
+                    return;
+                }
+                Ok(None) => {}
+                Err(error) => {
+                    info!("\n{}", "-".repeat(80));
+                    self.log_exit(Err(error), true);
+                    return;
+                }
+            }
+
+            if let Some(signal) = self.shutdown.requested() {
+                // the command's process group doesn't receive signals meant for jfswatch, so they're forwarded
+                self.child = Some(child);
+                self.stop(signal);
+                return;
+            }
+            sleep(STOP_POLL_INTERVAL);
+        }
+    }
+
+    /// Stops the running command, if there is one, along with every process it started. The process group is sent
+    /// the `signal`, and then killed if anything is still running after the stop timeout
+    pub fn stop(&mut self, signal: Signal) {
+        let Some(mut child) = self.child.take() else {
+            return;
+        };
+
+        let mut status = None;
+        if wait_for_group(&mut child, &mut status, Instant::now()) {
+            // the command exited on its own
+            self.log_exit(Ok(status.unwrap()), true);
+            return;
+        }
+        let exited_on_its_own = status.is_some();
+
+        info!("... Stopping process group {} with {}", child.id(), signal);
+        if let Err(error) = signal_group(&mut child, signal) {
+            error!("... Error sending {}: {}", signal, error);
+        }
+
+        let deadline = Instant::now() + Duration::from_secs_f32(self.stop.timeout);
+        if !wait_for_group(&mut child, &mut status, deadline) {
+            warn!(
+                "... Process group {} is still running after {}s, killing it",
+                child.id(),
+                self.stop.timeout
+            );
+            if let Err(error) = signal_group(&mut child, Signal::KILL) {
+                error!("... Error killing process group {}: {}", child.id(), error);
+            }
+
+            let deadline = Instant::now() + KILL_TIMEOUT;
+            if !wait_for_group(&mut child, &mut status, deadline) {
+                error!(
+                    "... Processes in group {} are still running after being killed",
+                    child.id()
+                );
+            }
+        }
+
+        match status {
+            Some(status) => self.log_exit(Ok(status), exited_on_its_own),
+            None => self.log_exit(child.wait(), exited_on_its_own),
+        }
+    }
+
+    /// Logs how a command exited. Unsuccessful exits are counted as failures unless the command was stopped
+    fn log_exit(&mut self, status: std::io::Result<ExitStatus>, exited_on_its_own: bool) {
+        match status {
+            Ok(status) => {
+                info!("... Exited with status: {}", status);
+                if exited_on_its_own && !status.success() {
+                    self.failures += 1;
+                }
+            }
+            Err(error) => {
+                error!("... Error running command: {}", error);
+                self.failures += 1;
+            }
+        }
+    }
+}
+
+impl Drop for Runner {
+    fn drop(&mut self) {
+        self.stop(self.stop.signal);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    fn restarting_runner(timeout: f32) -> Runner {
+        let stop = StopOptions {
+            timeout,
+            ..StopOptions::default()
+        };
+        return Runner::new(true, stop, Shutdown::default());
+    }
+
+    fn waiting_runner() -> Runner {
+        return Runner::new(false, StopOptions::default(), Shutdown::default());
+    }
+
+    fn shell(command: &str) -> CommandLine {
+        return CommandLine::Shell(command.to_string());
+    }
+
+    #[rstest]
+    #[case("TERM", "SIGTERM")]
+    #[case("SIGTERM", "SIGTERM")]
+    #[case("int", "SIGINT")]
+    #[case("SigHup", "SIGHUP")]
+    #[case("9", "SIGKILL")]
+    #[case("64", "signal 64")]
+    fn given_signal_name_or_number_when_parsed_then_ok(#[case] arg: &str, #[case] expected: &str) {
+        let signal: Signal = arg.parse().unwrap();
+        assert_eq!(signal.to_string(), expected);
+    }
+
+    #[rstest]
+    #[case("src/main.rs", "src/main.rs")]
+    #[case("", "''")]
+    #[case("with space.txt", "'with space.txt'")]
+    #[case("a; rm -rf ~", "'a; rm -rf ~'")]
+    #[case("$HOME", "'$HOME'")]
+    #[case("it's", "'it'\\''s'")]
+    fn given_arg_when_shell_quote_then_is_single_literal_word(
+        #[case] arg: &str,
+        #[case] expected: &str,
+    ) {
+        assert_eq!(shell_quote(arg), expected);
+    }
+
+    #[test]
+    fn given_exec_command_line_when_display_then_args_are_quoted() {
+        let command =
+            CommandLine::Exec(vec!["echo".to_string(), "a b".to_string(), "c".to_string()]);
+        assert_eq!(command.to_string(), "echo 'a b' c");
+    }
+
+    #[test]
+    fn given_exec_command_line_when_run_then_args_are_not_interpreted_by_a_shell() {
+        let tmp = tempfile::tempdir_in(".").unwrap();
+        let path = tmp.path().join("a; echo $HOME 'quoted'");
+        let mut runner = waiting_runner();
+
+        runner.run(
+            CommandLine::Exec(vec![
+                "touch".to_string(),
+                path.to_string_lossy().to_string(),
+            ]),
+            &[],
+            None,
+        );
+
+        assert!(path.exists());
+        assert!(!tmp.path().join("a").exists());
+        assert_eq!(runner.failures(), 0);
+    }
+
+    #[test]
+    fn given_environment_when_run_then_variables_are_set_and_removed() {
+        // HOME is inherited by every command, so removing it shows inherited variables are removed
+        let mut runner = waiting_runner();
+
+        runner.run(
+            shell(r#"test "$JFSWATCH_TEST_SET" = 'a b' && test -z "${HOME+x}""#),
+            &[
+                ("JFSWATCH_TEST_SET", Some("a b".to_string())),
+                ("HOME", None),
+            ],
+            None,
+        );
+
+        assert_eq!(runner.commands(), 1);
+        assert_eq!(runner.failures(), 0);
+    }
+
+    #[test]
+    fn given_stdin_when_run_then_command_reads_it() {
+        let tmp = tempfile::tempdir_in(".").unwrap();
+        let output = tmp.path().join("output");
+        let mut runner = waiting_runner();
+
+        runner.run(
+            CommandLine::Exec(vec![
+                "cp".to_string(),
+                "/dev/stdin".to_string(),
+                output.to_string_lossy().to_string(),
+            ]),
+            &[],
+            Some(b"a b\0c\nd\0".to_vec()),
+        );
+
+        assert_eq!(std::fs::read(&output).unwrap(), b"a b\0c\nd\0");
+    }
+
+    #[test]
+    fn given_large_stdin_when_command_does_not_read_it_then_does_not_block() {
+        let mut runner = waiting_runner();
+
+        runner.run(shell("true"), &[], Some(vec![b'a'; 4 * 1024 * 1024]));
+
+        assert_eq!(runner.failures(), 0);
+    }
+
+    #[test]
+    fn given_missing_program_when_run_exec_command_line_then_counts_failure() {
+        let mut runner = waiting_runner();
+
+        runner.run(
+            CommandLine::Exec(vec!["/does/not/exist".to_string()]),
+            &[],
+            None,
+        );
+
+        assert_eq!(runner.commands(), 1);
+        assert_eq!(runner.failures(), 1);
+    }
+
+    #[rstest]
+    #[case("")]
+    #[case("SIG")]
+    #[case("TERMINATE")]
+    #[case("0")]
+    #[case("-15")]
+    fn given_invalid_signal_when_parsed_then_err(#[case] arg: &str) {
+        assert!(arg.parse::<Signal>().is_err());
+    }
+
+    #[test]
+    fn given_no_restart_when_run_then_waits_for_command() {
+        let mut runner = waiting_runner();
+
+        let started = Instant::now();
+        runner.run(shell("sleep 0.2"), &[], None);
+
+        assert!(started.elapsed() >= Duration::from_millis(200));
+        assert!(runner.child.is_none());
+        assert_eq!(runner.commands(), 1);
+        assert_eq!(runner.failures(), 0);
+    }
+
+    #[rstest]
+    #[case("true", 0)]
+    #[case("false", 1)]
+    #[case("exit 3", 1)]
+    fn given_no_restart_when_command_exits_unsuccessfully_then_counts_failure(
+        #[case] command: &str,
+        #[case] expected_failures: usize,
+    ) {
+        let mut runner = waiting_runner();
+
+        runner.run(shell(command), &[], None);
+
+        assert_eq!(runner.commands(), 1);
+        assert_eq!(runner.failures(), expected_failures);
+    }
+
+    #[test]
+    fn given_no_restart_when_shutdown_requested_then_signal_is_forwarded_to_command() {
+        let shutdown = Shutdown::default();
+        let mut runner = Runner::new(false, StopOptions::default(), shutdown.clone());
+        std::thread::spawn(move || {
+            sleep(Duration::from_millis(100));
+            shutdown.request(Signal::TERM);
+        });
+
+        let started = Instant::now();
+        runner.run(shell("sleep 10"), &[], None);
+
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert!(runner.child.is_none());
+        assert_eq!(runner.failures(), 0);
+
+        runner.run(shell("true"), &[], None);
+        assert_eq!(runner.commands(), 1);
+    }
+
+    #[test]
+    fn given_restart_when_command_exits_unsuccessfully_before_restart_then_counts_failure() {
+        let mut runner = restarting_runner(5.0);
+        runner.run(shell("false"), &[], None);
+        sleep(Duration::from_millis(100));
+
+        runner.run(shell("sleep 10"), &[], None);
+        runner.stop(Signal::TERM);
+
+        assert_eq!(runner.commands(), 2);
+        assert_eq!(runner.failures(), 1);
+    }
+
+    #[test]
+    fn given_restart_when_run_then_command_runs_in_background() {
+        let mut runner = restarting_runner(5.0);
+
+        let started = Instant::now();
+        runner.run(shell("sleep 10"), &[], None);
+
+        assert!(started.elapsed() < Duration::from_secs(5));
+        let child = runner.child.as_mut().unwrap();
+        assert!(child.try_wait().unwrap().is_none());
+    }
+
+    #[test]
+    fn given_running_command_when_run_again_then_previous_is_stopped() {
+        let mut runner = restarting_runner(5.0);
+        runner.run(shell("sleep 10"), &[], None);
+        let first_pid = runner.child.as_ref().unwrap().id();
+
+        let started = Instant::now();
+        runner.run(shell("sleep 10"), &[], None);
+
+        assert!(started.elapsed() < Duration::from_secs(5));
+        let second_pid = runner.child.as_ref().unwrap().id();
+        assert_ne!(first_pid, second_pid);
+    }
+
+    #[test]
+    fn given_command_ignoring_signal_when_stop_then_killed_after_timeout() {
+        let mut runner = restarting_runner(0.2);
+        runner.run(
+            shell("trap '' TERM; while true; do sleep 0.05; done"),
+            &[],
+            None,
+        );
+        // give the shell a chance to install its trap
+        sleep(Duration::from_millis(100));
+
+        let started = Instant::now();
+        runner.stop(Signal::TERM);
+
+        assert!(started.elapsed() >= Duration::from_millis(200));
+        assert!(runner.child.is_none());
+    }
+
+    #[test]
+    fn given_command_with_child_processes_when_stop_then_whole_group_is_stopped() {
+        let mut runner = restarting_runner(5.0);
+        runner.run(shell("sleep 30 & sleep 30 & wait"), &[], None);
+        sleep(Duration::from_millis(100));
+        let pid = runner.child.as_ref().unwrap().id();
+
+        runner.stop(Signal::TERM);
+
+        // SAFETY: signal 0 only checks whether the group exists
+        assert_ne!(unsafe { libc::kill(-(pid as libc::pid_t), 0) }, 0);
+    }
+
+    #[test]
+    fn given_command_exited_leaving_child_process_when_stop_then_child_is_stopped() {
+        let mut runner = restarting_runner(5.0);
+        runner.run(shell("sleep 30 &"), &[], None);
+        sleep(Duration::from_millis(100));
+        let child = runner.child.as_mut().unwrap();
+        let pid = child.id();
+        assert!(child.try_wait().unwrap().is_some());
+        assert!(group_running(child));
+
+        runner.stop(Signal::TERM);
+
+        // SAFETY: signal 0 only checks whether the group exists
+        assert_ne!(unsafe { libc::kill(-(pid as libc::pid_t), 0) }, 0);
+    }
+
+    #[test]
+    fn given_command_already_exited_when_stop_then_does_not_wait() {
+        let mut runner = restarting_runner(5.0);
+        runner.run(shell("true"), &[], None);
+        sleep(Duration::from_millis(100));
+
+        let started = Instant::now();
+        runner.stop(Signal::TERM);
+
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert!(runner.child.is_none());
+    }
+}
@@ -0,0 +1,101 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use signal_hook::consts::{SIGINT, SIGTERM};
+
+use crate::runner::Signal;
+
+/// The longest a sleep goes without checking whether a shutdown was requested
+const CHECK_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Records the signal which asked jfswatch to shut down. Clones share the same request
+#[derive(Debug, Clone, Default)]
+pub struct Shutdown {
+    /// The number of the requested signal, or 0 if no shutdown was requested
+    signal: Arc<AtomicUsize>,
+}
+
+impl Shutdown {
+    /// Requests a shutdown whenever SIGINT or SIGTERM is received, instead of exiting immediately
+    pub fn register(&self) -> std::io::Result<()> {
+        for signal in [SIGINT, SIGTERM] {
+            signal_hook::flag::register_usize(signal, Arc::clone(&self.signal), signal as usize)?;
+        }
+        return Ok(());
+    }
+
+    /// Requests a shutdown as if `signal` was received
+    #[cfg(test)]
+    pub fn request(&self, signal: Signal) {
+        self.signal.store(signal.0 as usize, Ordering::SeqCst);
+    }
+
+    /// The signal which requested a shutdown, if one was requested
+    pub fn requested(&self) -> Option<Signal> {
+        return match self.signal.load(Ordering::SeqCst) {
+            0 => None,
+            signal => Some(Signal(signal as i32)),
+        };
+    }
+
+    /// Sleeps for the `duration`, unless a shutdown is requested in the meantime. Returns false if it was cut short
+    pub fn sleep(&self, duration: Duration) -> bool {
+        let deadline = Instant::now() + duration;
+
+        loop {
+            if self.requested().is_some() {
+                return false;
+            }
+
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return true;
+            }
+            std::thread::sleep(remaining.min(CHECK_INTERVAL));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn given_no_request_when_sleep_then_sleeps_full_duration() {
+        let shutdown = Shutdown::default();
+
+        let started = Instant::now();
+        assert!(shutdown.sleep(Duration::from_millis(120)));
+
+        assert!(started.elapsed() >= Duration::from_millis(120));
+        assert_eq!(shutdown.requested(), None);
+    }
+
+    #[test]
+    fn given_request_from_another_thread_when_sleep_then_returns_early() {
+        let shutdown = Shutdown::default();
+        let requester = shutdown.clone();
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(100));
+            requester.request(Signal(SIGINT));
+        });
+
+        let started = Instant::now();
+        assert!(!shutdown.sleep(Duration::from_secs(10)));
+
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert_eq!(shutdown.requested(), Some(Signal(SIGINT)));
+    }
+
+    #[test]
+    fn given_registered_when_signal_received_then_shutdown_requested() {
+        let shutdown = Shutdown::default();
+        shutdown.register().unwrap();
+
+        // note: other tests only ever request shutdowns with SIGINT, so they aren't disturbed by the raised signal
+        signal_hook::low_level::raise(SIGINT).unwrap();
+
+        assert_eq!(shutdown.requested(), Some(Signal(SIGINT)));
+    }
+}
@@ -1,10 +1,10 @@
 #[cfg(test)]
 pub mod utils {
     use std::fs;
-    use std::path::PathBuf;
+    use std::path::{Path, PathBuf};
 
     /// Creates `files` inside a `basedir` directory. Returns their full paths in the same order
-    pub fn make_files(basedir: &PathBuf, files: Vec<&str>) -> Vec<PathBuf> {
+    pub fn make_files(basedir: &Path, files: Vec<&str>) -> Vec<PathBuf> {
         let mut fullpaths = Vec::with_capacity(files.len());
 
         for file in files {
@@ -1,16 +1,22 @@
-use std::collections::hash_map::Keys;
+use std::collections::hash_map::{DefaultHasher, Keys};
 use std::collections::HashMap;
 use std::fmt::Display;
+use std::fs::File;
+use std::hash::Hasher;
+use std::io::{BufRead, BufReader};
+use std::rc::Rc;
 
 use chrono::{DateTime, Local};
 
+use crate::exclusions::Exclusions;
+
 /// A type to track the differences between two WatchedFS structs.
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum FSDifference {
-    Unchanged,
     Modified {
         path: String,
         mtime: DateTime<Local>,
+        old_mtime: DateTime<Local>,
     },
     New {
         path: String,
@@ -18,13 +24,80 @@ pub enum FSDifference {
     },
     Deleted {
         path: String,
+        old_mtime: DateTime<Local>,
     },
 }
 
+impl FSDifference {
+    /// The path that changed
+    pub fn path(&self) -> &str {
+        return match self {
+            FSDifference::Modified { path, .. } => path,
+            FSDifference::New { path, .. } => path,
+            FSDifference::Deleted { path, .. } => path,
+        };
+    }
+
+    /// Describes the kind of difference, e.g. `modified`
+    pub fn kind(&self) -> &'static str {
+        return match self {
+            FSDifference::Modified { .. } => "modified",
+            FSDifference::New { .. } => "new",
+            FSDifference::Deleted { .. } => "deleted",
+        };
+    }
+
+    /// The modified time of the path after it changed, or `None` if it was deleted
+    pub fn mtime(&self) -> Option<&DateTime<Local>> {
+        return match self {
+            FSDifference::Modified { mtime, .. } => Some(mtime),
+            FSDifference::New { mtime, .. } => Some(mtime),
+            FSDifference::Deleted { .. } => None,
+        };
+    }
+
+    /// The modified time of the path before it changed, or `None` if it is new
+    pub fn old_mtime(&self) -> Option<&DateTime<Local>> {
+        return match self {
+            FSDifference::Modified { old_mtime, .. } => Some(old_mtime),
+            FSDifference::Deleted { old_mtime, .. } => Some(old_mtime),
+            FSDifference::New { .. } => None,
+        };
+    }
+}
+
+/// Hashes the contents of the file at `path`. Returns `None` if the file cannot be read
+fn hash_contents(path: &std::path::Path) -> Option<u64> {
+    let mut reader = BufReader::new(File::open(path).ok()?);
+    let mut hasher = DefaultHasher::new();
+
+    loop {
+        let buffer = reader.fill_buf().ok()?;
+        if buffer.is_empty() {
+            break;
+        }
+
+        let length = buffer.len();
+        hasher.write(buffer);
+        reader.consume(length);
+    }
+
+    return Some(hasher.finish());
+}
+
 /// A data structure to manage the watched paths on the filesystem and their last modified time
 #[derive(Debug, PartialEq, Clone)]
 pub struct WatchedFS {
     paths: HashMap<String, DateTime<Local>>,
+
+    /// The content hashes of found files, only recorded when hashing is enabled
+    hashes: HashMap<String, u64>,
+
+    /// Files up to this many bytes have their contents hashed. `None` disables hashing
+    hash_limit: Option<u64>,
+
+    /// Paths which are never recorded as found
+    exclusions: Rc<Exclusions>,
 }
 
 impl WatchedFS {
@@ -32,23 +105,54 @@ impl WatchedFS {
     /// Note: the capacity will expand automatically as needed
     pub fn new(size: usize) -> Self {
         let map = HashMap::with_capacity(size);
-        return WatchedFS { paths: map };
+        return WatchedFS {
+            paths: map,
+            hashes: HashMap::new(),
+            hash_limit: None,
+            exclusions: Rc::new(Exclusions::default()),
+        };
+    }
+
+    /// Enables content hashing for files up to `limit` bytes. Hashed files are only considered modified when their
+    /// contents change, regardless of their mtime. Larger files, directories, and unreadable files still use mtime
+    pub fn with_hash_limit(mut self, limit: Option<u64>) -> Self {
+        self.hash_limit = limit;
+        return self;
+    }
+
+    /// Ignores any path matching the `exclusions` when finding paths
+    pub fn with_exclusions(mut self, exclusions: Rc<Exclusions>) -> Self {
+        self.exclusions = exclusions;
+        return self;
     }
 
     /// Returns an iterator over the paths and their last modified time
-    #[allow(dead_code)]
     pub fn paths(&self) -> Keys<'_, String, DateTime<Local>> {
         return self.paths.keys();
     }
 
-    /// Given a path that might exist, find its last mtime and record it as found under 'paths'
+    /// Given a path that might exist, find its last mtime and record it as found under 'paths'. Excluded paths are
+    /// never recorded, and paths which were already found aren't checked again
     pub fn find(&mut self, path: &std::path::PathBuf) {
+        let path_string = path.to_string_lossy().to_string();
+        if self.paths.contains_key(&path_string) || self.exclusions.excludes(path) {
+            return;
+        }
+
         if let Ok(metadata) = std::fs::metadata(path) {
             let mtime = metadata
                 .modified()
                 .expect("mtime is not supported on your platform");
 
-            self.found(path.to_string_lossy().to_string(), mtime.into());
+            if let Some(limit) = self.hash_limit {
+                if metadata.is_file() && metadata.len() <= limit {
+                    if let Some(hash) = hash_contents(path) {
+                        self.hashes.insert(path_string.clone(), hash);
+                    }
+                }
+            }
+
+            self.found(path_string, mtime.into());
         }
     }
 
@@ -62,34 +166,86 @@ impl WatchedFS {
         return self.paths.len();
     }
 
-    /// Compares the current state of the file system against a previous state. Returns an enum indicating the
-    /// first detected difference, if any
-    pub fn compare(&self, mut prev_fs: WatchedFS) -> FSDifference {
+    /// Removes the given `paths` that have been found. Returns them as their own WatchedFS, which can be compared
+    /// against an updated view of the same paths
+    pub fn take<'a>(&mut self, paths: impl Iterator<Item = &'a String>) -> WatchedFS {
+        let mut taken = WatchedFS::new(0)
+            .with_hash_limit(self.hash_limit)
+            .with_exclusions(Rc::clone(&self.exclusions));
+
+        for path in paths {
+            if let Some((path, mtime)) = self.paths.remove_entry(path) {
+                if let Some(hash) = self.hashes.remove(&path) {
+                    taken.hashes.insert(path.clone(), hash);
+                }
+                taken.found(path, mtime);
+            }
+        }
+
+        return taken;
+    }
+
+    /// Copies the given `paths` that have been found into their own WatchedFS
+    pub fn subset<'a>(&self, paths: impl Iterator<Item = &'a String>) -> WatchedFS {
+        let mut subset = WatchedFS::new(0)
+            .with_hash_limit(self.hash_limit)
+            .with_exclusions(Rc::clone(&self.exclusions));
+
+        for path in paths {
+            if let Some(mtime) = self.paths.get(path) {
+                if let Some(hash) = self.hashes.get(path) {
+                    subset.hashes.insert(path.clone(), *hash);
+                }
+                subset.found(path.clone(), *mtime);
+            }
+        }
+
+        return subset;
+    }
+
+    /// Records every path found by `other`, replacing what was previously known about those paths
+    pub fn extend(&mut self, other: WatchedFS) {
+        self.paths.extend(other.paths);
+        self.hashes.extend(other.hashes);
+    }
+
+    /// Compares the current state of the file system against a previous state. Returns every detected difference
+    /// ordered by path. An empty list means nothing has changed
+    pub fn compare_all(&self, mut prev_fs: WatchedFS) -> Vec<FSDifference> {
+        let mut differences = Vec::new();
+
         // ensure that all paths in the current filesystem existed in the previous filesystem
         for (path, mtime) in &self.paths {
             if let Some((owned_path, prev_mtime)) = prev_fs.paths.remove_entry(path) {
-                // path existed, but now we must check the mtime
-                if mtime != &prev_mtime {
-                    return FSDifference::Modified {
+                // path existed, but now we must check the contents when both were hashed, or otherwise the mtime
+                let modified = match (self.hashes.get(path), prev_fs.hashes.get(path)) {
+                    (Some(hash), Some(prev_hash)) => hash != prev_hash,
+                    _ => mtime != &prev_mtime,
+                };
+
+                if modified {
+                    differences.push(FSDifference::Modified {
                         path: owned_path,
                         mtime: *mtime,
-                    };
+                        old_mtime: prev_mtime,
+                    });
                 }
             } else {
                 // path did not exist in the previous filesystem
-                return FSDifference::New {
+                differences.push(FSDifference::New {
                     path: path.clone(),
                     mtime: *mtime,
-                };
+                });
             }
         }
 
         // if the path still exists in the previous filesystem paths, then it does not exist in self's
-        for (path, _mtime) in prev_fs.paths {
-            return FSDifference::Deleted { path };
+        for (path, old_mtime) in prev_fs.paths {
+            differences.push(FSDifference::Deleted { path, old_mtime });
         }
 
-        return FSDifference::Unchanged;
+        differences.sort_by(|a, b| a.path().cmp(b.path()));
+        return differences;
     }
 }
 
@@ -114,18 +270,39 @@ impl Display for WatchedFS {
 #[cfg(test)]
 mod tests {
     use std::collections::HashSet;
-    use std::time::Duration;
+    use std::path::{Path, PathBuf};
+    use std::time::{Duration, SystemTime};
+
+    use tempfile::tempdir_in;
 
     use super::*;
 
+    /// Overwrites the file at `path` with `contents`, and then sets its mtime
+    fn write_with_mtime(path: &Path, contents: &str, mtime: SystemTime) {
+        std::fs::write(path, contents).unwrap();
+        File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(mtime)
+            .unwrap();
+    }
+
+    fn find_with_hash_limit(path: &PathBuf, limit: Option<u64>) -> WatchedFS {
+        let mut watched = WatchedFS::new(1).with_hash_limit(limit);
+        watched.find(path);
+        return watched;
+    }
+
     #[test]
     fn given_watched_fs_when_path_found_then_is_added_to_paths() {
         let mut watched = WatchedFS {
             paths: HashMap::new(),
+            ..WatchedFS::new(0)
         };
         let mock_path = "mock/path".to_string();
         let mock_time = Local::now();
-        watched.found(mock_path.clone(), mock_time.clone());
+        watched.found(mock_path.clone(), mock_time);
         assert_eq!(
             watched.paths,
             HashMap::from([(mock_path.clone(), mock_time)])
@@ -137,14 +314,12 @@ mod tests {
     fn given_watched_fs_when_len_then_returns_current_size() {
         let mut watched = WatchedFS {
             paths: HashMap::new(),
+            ..WatchedFS::new(0)
         };
         assert_eq!(watched.len(), 0);
         assert_eq!(watched.paths().len(), 0);
         assert_eq!(
-            watched
-                .paths()
-                .map(|p| p.clone())
-                .collect::<HashSet<String>>(),
+            watched.paths().cloned().collect::<HashSet<String>>(),
             HashSet::new()
         );
 
@@ -152,10 +327,7 @@ mod tests {
         assert_eq!(watched.len(), 1);
         assert_eq!(watched.paths().len(), 1);
         assert_eq!(
-            watched
-                .paths()
-                .map(|p| p.clone())
-                .collect::<HashSet<String>>(),
+            watched.paths().cloned().collect::<HashSet<String>>(),
             HashSet::from(["path/a".to_string()])
         );
 
@@ -163,10 +335,7 @@ mod tests {
         assert_eq!(watched.len(), 2);
         assert_eq!(watched.paths().len(), 2);
         assert_eq!(
-            watched
-                .paths()
-                .map(|p| p.clone())
-                .collect::<HashSet<String>>(),
+            watched.paths().cloned().collect::<HashSet<String>>(),
             HashSet::from(["path/a".to_string(), "path/b".to_string()])
         );
 
@@ -174,10 +343,7 @@ mod tests {
         assert_eq!(watched.len(), 2);
         assert_eq!(watched.paths().len(), 2);
         assert_eq!(
-            watched
-                .paths()
-                .map(|p| p.clone())
-                .collect::<HashSet<String>>(),
+            watched.paths().cloned().collect::<HashSet<String>>(),
             HashSet::from(["path/a".to_string(), "path/b".to_string()])
         );
     }
@@ -186,11 +352,13 @@ mod tests {
     fn given_empty_fs_when_compared_against_another_empty_then_is_unchanged() {
         let a = WatchedFS {
             paths: HashMap::new(),
+            ..WatchedFS::new(0)
         };
         let b = WatchedFS {
             paths: HashMap::new(),
+            ..WatchedFS::new(0)
         };
-        assert_eq!(a.compare(b), FSDifference::Unchanged);
+        assert_eq!(a.compare_all(b), vec![]);
         assert_eq!(a.len(), 0);
     }
 
@@ -198,11 +366,12 @@ mod tests {
     fn given_non_empty_fs_when_compared_against_itself_then_is_unchanged() {
         let mut watched = WatchedFS {
             paths: HashMap::new(),
+            ..WatchedFS::new(0)
         };
         watched.found("/some/path".to_string(), Local::now());
 
         let watched_cloned = watched.clone();
-        assert_eq!(watched.compare(watched_cloned), FSDifference::Unchanged);
+        assert_eq!(watched.compare_all(watched_cloned), vec![]);
         assert_eq!(watched.len(), 1);
     }
 
@@ -214,17 +383,20 @@ mod tests {
 
         let prev_watched = WatchedFS {
             paths: HashMap::from([(path.clone(), mtime_initial)]),
+            ..WatchedFS::new(0)
         };
         let curr_watched = WatchedFS {
-            paths: HashMap::from([(path.clone(), mtime_now.clone())]),
+            paths: HashMap::from([(path.clone(), mtime_now)]),
+            ..WatchedFS::new(0)
         };
 
         assert_eq!(
-            curr_watched.compare(prev_watched),
-            FSDifference::Modified {
-                path: path,
-                mtime: mtime_now
-            }
+            curr_watched.compare_all(prev_watched),
+            vec![FSDifference::Modified {
+                path,
+                mtime: mtime_now,
+                old_mtime: mtime_initial
+            }]
         );
         assert_eq!(curr_watched.len(), 1);
     }
@@ -234,18 +406,20 @@ mod tests {
         let new_path = "new/path".to_string();
         let prev_watched = WatchedFS {
             paths: HashMap::new(),
+            ..WatchedFS::new(0)
         };
         let mtime = Local::now();
         let curr_watched = WatchedFS {
-            paths: HashMap::from([(new_path.clone(), mtime.clone())]),
+            paths: HashMap::from([(new_path.clone(), mtime)]),
+            ..WatchedFS::new(0)
         };
 
         assert_eq!(
-            curr_watched.compare(prev_watched),
-            FSDifference::New {
+            curr_watched.compare_all(prev_watched),
+            vec![FSDifference::New {
                 path: new_path,
-                mtime: mtime
-            }
+                mtime
+            }]
         );
         assert_eq!(curr_watched.len(), 1);
     }
@@ -253,20 +427,68 @@ mod tests {
     #[test]
     fn given_deleted_file_when_compared_then_returns_deleted_path() {
         let deleted_path = "deleted/path".to_string();
+        let old_mtime = Local::now();
         let prev_watched = WatchedFS {
-            paths: HashMap::from([(deleted_path.clone(), Local::now())]),
+            paths: HashMap::from([(deleted_path.clone(), old_mtime)]),
+            ..WatchedFS::new(0)
         };
         let curr_watched = WatchedFS {
             paths: HashMap::new(),
+            ..WatchedFS::new(0)
         };
 
         assert_eq!(
-            curr_watched.compare(prev_watched),
-            FSDifference::Deleted { path: deleted_path }
+            curr_watched.compare_all(prev_watched),
+            vec![FSDifference::Deleted {
+                path: deleted_path,
+                old_mtime
+            }]
         );
         assert_eq!(curr_watched.len(), 0);
     }
 
+    #[test]
+    fn given_many_changes_when_compared_all_then_returns_every_difference_ordered_by_path() {
+        let mtime_initial: DateTime<Local> = Local::now() - Duration::new(10, 0); // 10s ago
+        let mtime_now = Local::now();
+
+        let prev_watched = WatchedFS {
+            paths: HashMap::from([
+                ("a/unchanged".to_string(), mtime_initial),
+                ("b/modified".to_string(), mtime_initial),
+                ("c/deleted".to_string(), mtime_initial),
+            ]),
+            ..WatchedFS::new(0)
+        };
+        let curr_watched = WatchedFS {
+            paths: HashMap::from([
+                ("a/unchanged".to_string(), mtime_initial),
+                ("b/modified".to_string(), mtime_now),
+                ("d/new".to_string(), mtime_now),
+            ]),
+            ..WatchedFS::new(0)
+        };
+
+        assert_eq!(
+            curr_watched.compare_all(prev_watched),
+            vec![
+                FSDifference::Modified {
+                    path: "b/modified".to_string(),
+                    mtime: mtime_now,
+                    old_mtime: mtime_initial
+                },
+                FSDifference::Deleted {
+                    path: "c/deleted".to_string(),
+                    old_mtime: mtime_initial
+                },
+                FSDifference::New {
+                    path: "d/new".to_string(),
+                    mtime: mtime_now
+                },
+            ]
+        );
+    }
+
     #[test]
     fn given_watched_fs_when_displayed_then_shows_all_paths() {
         let mut watched = WatchedFS::new(3);
@@ -289,4 +511,163 @@ mod tests {
 
         assert_eq!(displayed, "".to_string());
     }
+
+    #[test]
+    fn given_hashing_when_touched_with_same_contents_then_is_unchanged() {
+        let tmp = tempdir_in(".").unwrap();
+        let path = tmp.path().join("config.yml");
+        let mtime = SystemTime::now() - Duration::new(10, 0);
+
+        write_with_mtime(&path, "same contents", mtime);
+        let prev_watched = find_with_hash_limit(&path, Some(1024));
+        write_with_mtime(&path, "same contents", SystemTime::now());
+        let curr_watched = find_with_hash_limit(&path, Some(1024));
+
+        assert_eq!(curr_watched.compare_all(prev_watched), vec![]);
+    }
+
+    #[test]
+    fn given_hashing_when_contents_change_with_same_mtime_then_is_modified() {
+        let tmp = tempdir_in(".").unwrap();
+        let path = tmp.path().join("config.yml");
+        let mtime = SystemTime::now() - Duration::new(10, 0);
+
+        write_with_mtime(&path, "old contents", mtime);
+        let prev_watched = find_with_hash_limit(&path, Some(1024));
+        write_with_mtime(&path, "new contents", mtime);
+        let curr_watched = find_with_hash_limit(&path, Some(1024));
+
+        assert_eq!(
+            curr_watched.compare_all(prev_watched),
+            vec![FSDifference::Modified {
+                path: path.to_string_lossy().to_string(),
+                mtime: mtime.into(),
+                old_mtime: mtime.into()
+            }]
+        );
+    }
+
+    #[test]
+    fn given_no_hashing_when_contents_change_with_same_mtime_then_is_unchanged() {
+        let tmp = tempdir_in(".").unwrap();
+        let path = tmp.path().join("config.yml");
+        let mtime = SystemTime::now() - Duration::new(10, 0);
+
+        write_with_mtime(&path, "old contents", mtime);
+        let prev_watched = find_with_hash_limit(&path, None);
+        write_with_mtime(&path, "new contents", mtime);
+        let curr_watched = find_with_hash_limit(&path, None);
+
+        assert_eq!(curr_watched.compare_all(prev_watched), vec![]);
+    }
+
+    #[test]
+    fn given_file_larger_than_hash_limit_when_touched_then_is_modified_by_mtime() {
+        let tmp = tempdir_in(".").unwrap();
+        let path = tmp.path().join("large.bin");
+        let mtime_initial = SystemTime::now() - Duration::new(10, 0);
+        let mtime_now = SystemTime::now();
+
+        write_with_mtime(&path, "more than four bytes", mtime_initial);
+        let prev_watched = find_with_hash_limit(&path, Some(4));
+        write_with_mtime(&path, "more than four bytes", mtime_now);
+        let curr_watched = find_with_hash_limit(&path, Some(4));
+
+        assert_eq!(
+            curr_watched.compare_all(prev_watched),
+            vec![FSDifference::Modified {
+                path: path.to_string_lossy().to_string(),
+                mtime: mtime_now.into(),
+                old_mtime: mtime_initial.into()
+            }]
+        );
+    }
+
+    #[test]
+    fn given_watched_fs_when_take_then_moves_found_paths_into_new_watched_fs() {
+        let mtime = Local::now();
+        let mut watched = WatchedFS::new(3);
+        watched.found("path/a".to_string(), mtime);
+        watched.found("path/b".to_string(), mtime);
+
+        let taken = watched.take(["path/a".to_string(), "path/c".to_string()].iter());
+
+        assert_eq!(taken.paths, HashMap::from([("path/a".to_string(), mtime)]));
+        assert_eq!(
+            watched.paths,
+            HashMap::from([("path/b".to_string(), mtime)])
+        );
+    }
+
+    #[test]
+    fn given_watched_fs_when_subset_then_copies_found_paths_into_new_watched_fs() {
+        let mtime = Local::now();
+        let mut watched = WatchedFS::new(2);
+        watched.found("path/a".to_string(), mtime);
+        watched.found("path/b".to_string(), mtime);
+
+        let subset = watched.subset(["path/a".to_string(), "path/c".to_string()].iter());
+
+        assert_eq!(subset.paths, HashMap::from([("path/a".to_string(), mtime)]));
+        assert_eq!(watched.len(), 2);
+    }
+
+    #[test]
+    fn given_path_already_found_when_find_then_keeps_recorded_mtime() {
+        let tmp = tempdir_in(".").unwrap();
+        let path = tmp.path().join("file.txt");
+        std::fs::write(&path, "").unwrap();
+        let mtime = Local::now() - Duration::new(10, 0); // 10s ago
+
+        let mut watched = WatchedFS::new(1);
+        watched.found(path.to_string_lossy().to_string(), mtime);
+        watched.find(&path);
+
+        assert_eq!(
+            watched.paths,
+            HashMap::from([(path.to_string_lossy().to_string(), mtime)])
+        );
+    }
+
+    #[test]
+    fn given_watched_fs_when_extend_then_replaces_and_adds_paths() {
+        let mtime_initial: DateTime<Local> = Local::now() - Duration::new(10, 0); // 10s ago
+        let mtime_now = Local::now();
+        let mut watched = WatchedFS::new(2);
+        watched.found("path/a".to_string(), mtime_initial);
+        watched.found("path/b".to_string(), mtime_initial);
+
+        let mut updates = WatchedFS::new(2);
+        updates.found("path/b".to_string(), mtime_now);
+        updates.found("path/c".to_string(), mtime_now);
+        watched.extend(updates);
+
+        assert_eq!(
+            watched.paths,
+            HashMap::from([
+                ("path/a".to_string(), mtime_initial),
+                ("path/b".to_string(), mtime_now),
+                ("path/c".to_string(), mtime_now),
+            ])
+        );
+    }
+
+    #[test]
+    fn given_exclusions_when_find_then_excluded_paths_are_not_recorded() {
+        let tmp = tempdir_in(".").unwrap();
+        let kept = tmp.path().join("kept.txt");
+        let excluded = tmp.path().join("excluded.swp");
+        std::fs::write(&kept, "").unwrap();
+        std::fs::write(&excluded, "").unwrap();
+
+        let exclusions = Exclusions::from_cli_args(&["*.swp".to_string()]).unwrap();
+        let mut watched = WatchedFS::new(2).with_exclusions(Rc::new(exclusions));
+        watched.find(&kept);
+        watched.find(&excluded);
+
+        assert_eq!(
+            watched.paths().cloned().collect::<Vec<String>>(),
+            vec![kept.to_string_lossy().to_string()]
+        );
+    }
 }